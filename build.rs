@@ -0,0 +1,5 @@
+fn main() {
+    // Target triple for the About section in Settings
+    println!("cargo:rustc-env=BUILD_TARGET={}", std::env::var("TARGET").unwrap_or_default());
+    println!("cargo:rerun-if-changed=build.rs");
+}
@@ -1,10 +1,20 @@
-use std::path::PathBuf;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use eframe::egui;
 use rfd::FileDialog;
 use anyhow::Result;
-use crate::types::{AvailableFile, FileType, FileAction};
-use crate::config::AppConfig;
-use crate::ucl_bindings::UclLibrary;
-use crate::file_ops::{scan_psdz_files, generate_output_filename, get_program_directory, process_files};
+use bmw_virtual_reader::VirtualReader;
+use crate::types::{AvailableFile, ChecksumAlgorithm, ExtractionProgress, FileType, FileValidation, FlashSegment, ImageChecksum, OutputFormat, OutputLayout, OutputOptions, PsdzScan, RunPreview, ScanLimits, UclPathCheck, ValidationSort, ValidationStatus, XmlInspection};
+use crate::config::{AppConfig, UclPathSource};
+use crate::cli;
+use crate::project::{ProjectFile, ProjectInput};
+use crate::ucl_bindings::{resolve_library_path, UclLibrary, BUFFER_SIZE_TIERS};
+use crate::file_ops::{check_output_collision, check_ucl_variant, compute_checksum, describe_output_changes, find_swe_directory, format_hex_dump, scan_psdz_files, generate_output_filename, get_program_directory, get_xml_path, is_same_file, parse_file_list, process_files_preview, process_single_file, recalculate_segment_checksums, replace_last_in_file_name, unused_output_path, validate_file};
+use crate::xml_parser::{flash_segment_rows, parse_xml, parse_xml_str, read_xml_stripped};
 use crate::ui::UIState;
 
 pub struct BMWVirtualReaderApp {
@@ -12,15 +22,142 @@ pub struct BMWVirtualReaderApp {
     pub swfl1_file: Option<PathBuf>,
     pub swfl2_file: Option<PathBuf>,
     pub output_file: Option<PathBuf>,
+    /// Whether `output_file` is derived from the inputs rather than picked by the user
+    pub output_is_auto: bool,
     pub status_message: String,
+    pub warnings: Vec<String>,
+    /// Per-input progress of the last run, as (label, fraction) in combine order
+    pub file_progress: Vec<(String, f32)>,
     pub is_processing: bool,
-    pub ucl_library: Option<UclLibrary>,
+    /// Shared with the worker thread of a running extraction
+    pub reader: Option<Arc<VirtualReader>>,
+    /// Checksum of the image written by the last successful run
+    pub last_checksum: Option<ImageChecksum>,
+    /// Latest progress report of the current or last run
+    pub progress: Option<ExtractionProgress>,
+    pub ucl_path_override: Option<(String, UclPathSource)>,
+    /// Whether the selected files contain compressed segments, `None` if nothing is
+    /// selected or an XML couldn't be read
+    pub ucl_required: Option<bool>,
     pub config: AppConfig,
-    pub psdz_folder: Option<PathBuf>,
+    /// Selected PSDZ folders; their files accumulate in `available_files`
+    pub psdz_folders: Vec<PathBuf>,
     pub available_files: Vec<AvailableFile>,
+    /// Background scan of a PSDZ folder, if one is running
+    pub psdz_scan: Option<PendingScan>,
+    /// Extraction running on a worker thread, if any
+    extraction: Option<PendingExtraction>,
+    pub segment_preview: Vec<(String, Vec<FlashSegment>)>,
+    /// Result of the last "Validate All" preflight over `available_files`
+    pub validation_report: Vec<FileValidation>,
+    /// Dry run of the current inputs and options, from the last "Preview"
+    pub run_preview: Option<RunPreview>,
+    pub xml_inspection: Option<XmlInspection>,
     pub ui_state: UIState,
+    pub config_dirty_since: Option<Instant>,
+    /// The last combined image written, to tell whether a re-run changed anything
+    last_run: Option<LastRun>,
+    /// Outcome of probing the UCL path shown in the settings
+    pub ucl_path_check: Option<UclPathCheck>,
+    /// Path and pinned symbol last typed into the settings and when, for debouncing
+    /// `ucl_path_check`
+    ucl_path_edit: Option<((String, String), Instant)>,
+    /// Report which decompression buffer sizes a run used (`--buffer-stats`)
+    buffer_stats: bool,
 }
 
+/// A PSDZ folder scan running on a worker thread
+pub struct PendingScan {
+    pub psdz_path: PathBuf,
+    pub limits: ScanLimits,
+    /// The swe directory and its files, `None` if no swe directory was found
+    receiver: mpsc::Receiver<Option<(PathBuf, PsdzScan)>>,
+}
+
+/// An extraction running on a worker thread, with what it was started with
+struct PendingExtraction {
+    inputs: Vec<(String, PathBuf)>,
+    output_path: PathBuf,
+    options: OutputOptions,
+    receiver: mpsc::Receiver<ExtractionEvent>,
+    worker: JoinHandle<()>,
+}
+
+/// What the extraction worker reports back to the UI thread
+enum ExtractionEvent {
+    Status(String),
+    Progress(ExtractionProgress),
+    /// The outcome of a successful run, or why it failed
+    Finished(Result<RunOutcome, String>),
+}
+
+/// A successful run as reported by the worker
+struct RunOutcome {
+    /// The checksum of a combined image
+    checksum: Option<ImageChecksum>,
+    /// For an output that is a single raw file, the image that was written and how it
+    /// compares to the previous run's
+    output: Option<(OutputSnapshot, Option<String>)>,
+}
+
+/// Fingerprint of a combined image written by a run, along with the image itself when
+/// `keep_last_output` is set
+#[derive(Clone)]
+struct OutputSnapshot {
+    /// CRC-32 and length of the whole file
+    fingerprint: ImageChecksum,
+    data: Option<Arc<Vec<u8>>>,
+}
+
+/// Inputs and output of a combined image written by an earlier run
+struct LastRun {
+    inputs: Vec<(String, PathBuf)>,
+    output: OutputSnapshot,
+}
+
+/// Read back the output a run wrote, fingerprint it and compare it with `previous`.
+/// Runs on the extraction worker so large images aren't read on the UI thread; the
+/// data is only kept when `keep_data` is set.
+fn snapshot_output(path: &Path, previous: Option<&OutputSnapshot>, keep_data: bool) -> Option<(OutputSnapshot, Option<String>)> {
+    let data = std::fs::read(path).ok()?;
+    let fingerprint = compute_checksum(ChecksumAlgorithm::Crc32, &data);
+    let comparison = previous.map(|previous| match &previous.data {
+        Some(previous_data) => describe_output_changes(previous_data, &data),
+        None => describe_fingerprint_change(&previous.fingerprint, &fingerprint),
+    });
+    Some((OutputSnapshot { fingerprint, data: keep_data.then(|| Arc::new(data)) }, comparison))
+}
+
+/// Like `describe_output_changes` when only the fingerprints of the two runs are known
+fn describe_fingerprint_change(previous: &ImageChecksum, current: &ImageChecksum) -> String {
+    if previous == current {
+        return format!("Identical to the previous run ({} bytes)", current.len);
+    }
+    let mut summary = format!("Differs from the previous run ({}, was 0x{:08X})", current, previous.value);
+    if previous.len != current.len {
+        summary.push_str(&format!("; size changed from {} to {} bytes", previous.len, current.len));
+    }
+    summary.push_str("; enable \"Keep last output\" in Settings to list the changed ranges");
+    summary
+}
+
+/// How long past its deadline a scan may take to report before it is given up on.
+/// Covers a single directory read that blocks on an unresponsive share.
+const SCAN_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// Most bytes a segment hex dump puts on the clipboard
+const MAX_HEX_DUMP_BYTES: usize = 4096;
+
+/// Delay after the last settings change before the config is autosaved
+const CONFIG_AUTOSAVE_DELAY: Duration = Duration::from_secs(1);
+
+/// Delay after the last keystroke in the UCL path field before the path is probed
+const UCL_PATH_CHECK_DELAY: Duration = Duration::from_millis(500);
+
+/// How long closing the window waits for a stopped extraction to wind down. A
+/// decompression call can't see the cancel flag, so a large segment may take longer.
+const STOP_EXTRACTION_TIMEOUT: Duration = Duration::from_secs(2);
+
 impl Default for BMWVirtualReaderApp {
     fn default() -> Self {
         Self {
@@ -28,32 +165,153 @@ impl Default for BMWVirtualReaderApp {
             swfl1_file: None,
             swfl2_file: None,
             output_file: None,
+            output_is_auto: true,
             status_message: "Ready".to_string(),
+            warnings: Vec::new(),
+            file_progress: Vec::new(),
             is_processing: false,
-            ucl_library: None,
+            reader: None,
+            last_checksum: None,
+            progress: None,
+            ucl_path_override: None,
+            ucl_required: None,
             config: AppConfig::load(),
-            psdz_folder: None,
+            psdz_folders: Vec::new(),
             available_files: Vec::new(),
+            psdz_scan: None,
+            extraction: None,
+            segment_preview: Vec::new(),
+            validation_report: Vec::new(),
+            run_preview: None,
+            xml_inspection: None,
             ui_state: UIState::default(),
+            config_dirty_since: None,
+            last_run: None,
+            ucl_path_check: None,
+            ucl_path_edit: None,
+            buffer_stats: false,
         }
     }
 }
 
 impl BMWVirtualReaderApp {
-    pub fn new(_cc: &eframe::CreationContext<'_>) -> Self {
-        let mut app = Self::default();
+    pub fn new(_cc: &eframe::CreationContext<'_>, cli_ucl_path: Option<String>, buffer_stats: bool) -> Self {
+        let mut app = Self {
+            ucl_path_override: AppConfig::ucl_library_override(cli_ucl_path),
+            buffer_stats,
+            ..Self::default()
+        };
         
         // Try to load the UCL library
-        if let Ok(lib) = UclLibrary::new(&app.config.ucl_library_path) {
-            app.ucl_library = Some(lib);
-            app.status_message = "UCL library loaded successfully".to_string();
-        } else {
-            app.status_message = format!("Warning: Could not load UCL library from {}", app.config.ucl_library_path);
+        let (ucl_path, source) = app.ucl_library_path();
+        match app.load_reader(&ucl_path) {
+            Ok(reader) => {
+                let lib = reader.library();
+                app.status_message = format!("UCL library loaded successfully ({}) from {} [{}]", lib.decompress_symbol(), lib.path().display(), source);
+                app.reader = Some(Arc::new(reader));
+            }
+            Err(e) => {
+                app.status_message = format!("Warning: Could not load UCL library from {} [{}]: {}", ucl_path, source, e);
+                if !app.config.ucl_setup_complete {
+                    app.ui_state.show_ucl_setup = true;
+                    app.ui_state.ucl_candidates = AppConfig::find_ucl_library_candidates();
+                    app.ui_state.ucl_search_done = true;
+                }
+            }
         }
         
+        if let Err(e) = app.config.check_xml_schema() {
+            app.warnings.push(format!("Warning: {}", e));
+        }
+        
+        app.restore_last_selections();
+        
         app
     }
 
+    /// Select the inputs and hand-picked output of the previous session again. Entries
+    /// whose file has been deleted since are dropped from the config with a warning.
+    fn restore_last_selections(&mut self) {
+        let stored = [
+            ("btld", self.config.last_btld.clone()),
+            ("swfl1", self.config.last_swfl1.clone()),
+            ("swfl2", self.config.last_swfl2.clone()),
+        ];
+        for (slot, path) in stored {
+            let Some(path) = path.map(PathBuf::from) else {
+                continue;
+            };
+            if !path.is_file() {
+                self.warnings.push(format!("Warning: The last used {} file no longer exists and was not restored: {}",
+                    slot.to_uppercase(), path.display()));
+                continue;
+            }
+            match slot {
+                "btld" => self.btld_file = Some(path),
+                "swfl1" => self.swfl1_file = Some(path),
+                _ => self.swfl2_file = Some(path),
+            }
+        }
+        
+        // The output is only written by a run, so its folder is what has to still exist
+        if let Some(path) = self.config.last_output.clone().map(PathBuf::from) {
+            if path.parent().is_some_and(|dir| dir.as_os_str().is_empty() || dir.is_dir()) {
+                self.output_file = Some(path);
+                self.output_is_auto = false;
+            } else {
+                self.warnings.push(format!("Warning: The folder of the last used output no longer exists, using an automatic name instead: {}",
+                    path.display()));
+            }
+        }
+        
+        // Also writes the dropped entries back as cleared
+        self.selection_changed();
+    }
+
+    /// Store the current selections in the config for the next session
+    pub fn remember_selections(&mut self) {
+        let to_string = |path: &Option<PathBuf>| path.as_ref().map(|path| path.to_string_lossy().to_string());
+        let last_output = self.output_file.as_ref()
+            .filter(|_| !self.output_is_auto)
+            .map(|path| path.to_string_lossy().to_string());
+        let selections = (to_string(&self.btld_file), to_string(&self.swfl1_file), to_string(&self.swfl2_file), last_output);
+        
+        let config = &mut self.config;
+        if selections != (config.last_btld.clone(), config.last_swfl1.clone(), config.last_swfl2.clone(), config.last_output.clone()) {
+            (config.last_btld, config.last_swfl1, config.last_swfl2, config.last_output) = selections;
+            self.mark_config_dirty();
+        }
+    }
+
+    /// The UCL library path for this session and where it came from
+    pub fn ucl_library_path(&self) -> (String, UclPathSource) {
+        match &self.ucl_path_override {
+            Some((path, source)) => (path.clone(), *source),
+            None => self.config.configured_ucl_library_path(),
+        }
+    }
+
+    /// Build and runtime details for bug reports, as shown in the About section
+    pub fn diagnostics(&self) -> String {
+        let (ucl_path, source) = self.ucl_library_path();
+        let ucl_status = match self.reader.as_deref().map(VirtualReader::library) {
+            Some(lib) => format!("loaded from {}, {} (version {}), compressor: {}", lib.path().display(), lib.decompress_symbol(),
+                lib.version().unwrap_or("unknown"), lib.compress_symbol().unwrap_or("none")),
+            None => "not loaded".to_string(),
+        };
+        let config_state = if self.config.loaded_from_file { "loaded" } else { "not saved yet, using defaults" };
+        
+        [
+            format!("BMW Virtual Reader {}", env!("CARGO_PKG_VERSION")),
+            format!("Build target: {}", env!("BUILD_TARGET")),
+            format!("OS: {} ({})", std::env::consts::OS, std::env::consts::ARCH),
+            format!("UCL library: {} [{}]", ucl_path, source),
+            format!("UCL status: {}", ucl_status),
+            format!("Config file: {} ({})", AppConfig::config_path().display(), config_state),
+            format!("Safe mode: {}", if self.config.safe_mode { "on" } else { "off" }),
+        ].join("\n")
+    }
+
     pub fn select_psdz_folder(&mut self) {
         let mut dialog = FileDialog::new()
             .add_filter("Directories", &["*"]);
@@ -63,81 +321,296 @@ impl BMWVirtualReaderApp {
         }
         
         if let Some(path) = dialog.pick_folder() {
-            self.psdz_folder = Some(path.clone());
-            self.scan_psdz_files(&path);
+            self.start_psdz_scan(&path);
             
             // Update config
             self.config.last_input_dir = Some(path.to_string_lossy().to_string());
+            self.mark_config_dirty();
         }
     }
 
-    pub fn scan_psdz_files(&mut self, psdz_path: &PathBuf) {
-        self.available_files.clear();
-        self.status_message = "Scanning PSDZ files...".to_string();
-        
-        self.available_files = scan_psdz_files(psdz_path);
-        
-        self.status_message = format!("Found {} files ({} BTLD, {} SWFL)", 
-            self.available_files.len(),
-            self.available_files.iter().filter(|f| f.file_type == FileType::BTLD).count(),
-            self.available_files.iter().filter(|f| f.file_type == FileType::SWFL).count());
-    }
-
-    pub fn select_file_by_index(&mut self, index: usize, file_type: &str) {
-        if index < self.available_files.len() {
-            let file = &self.available_files[index];
-            match file_type {
-                "btld" => {
-                    self.btld_file = Some(file.path.clone());
-                    self.ui_state.selected_btld_index = Some(index);
-                    
-                    // Auto-generate output file path if not set
-                    if self.output_file.is_none() {
-                        if let Some(file_name) = file.path.file_name() {
-                            let file_name_str = file_name.to_string_lossy();
-                            let output_file_name = file_name_str.replace(".bin", ".extracted");
-                            let mut output_path = file.path.clone();
-                            output_path.set_file_name(output_file_name);
-                            self.output_file = Some(output_path);
-                        }
-                    }
-                }
-                "swfl1" => {
-                    self.swfl1_file = Some(file.path.clone());
-                    self.ui_state.selected_swfl1_index = Some(index);
-                    
-                    // Auto-generate output file path based on SWFL1
-                    if let Some(output_filename) = generate_output_filename(&file.path) {
-                        let mut output_path = get_program_directory();
-                        output_path.push(output_filename);
-                        self.output_file = Some(output_path);
-                    }
-                }
-                "swfl2" => {
-                    self.swfl2_file = Some(file.path.clone());
-                    self.ui_state.selected_swfl2_index = Some(index);
+    /// Scan a PSDZ folder on a worker thread, bounded by the configured timeout and
+    /// file cap. `poll_psdz_scan` picks up the result. A scan that is still running
+    /// is cancelled first; its thread stops at the next directory entry.
+    pub fn start_psdz_scan(&mut self, psdz_path: &Path) {
+        let previous = self.cancel_psdz_scan();
+        
+        let limits = ScanLimits {
+            deadline: Instant::now() + Duration::from_secs(self.config.scan_timeout_secs.max(1)),
+            max_files: self.config.scan_max_files.max(1),
+            max_depth: self.config.scan_max_depth.max(1),
+            cancel: Arc::new(AtomicBool::new(false)),
+        };
+        let (sender, receiver) = mpsc::channel();
+        let worker_path = psdz_path.to_path_buf();
+        let worker_limits = limits.clone();
+        std::thread::spawn(move || {
+            let result = find_swe_directory(&worker_path, &worker_limits).map(|swe_path| {
+                let scan = scan_psdz_files(&swe_path, &worker_path, &worker_limits);
+                (swe_path, scan)
+            });
+            // The receiver is gone if the scan was cancelled
+            let _ = sender.send(result);
+        });
+        
+        self.status_message = match previous {
+            Some(previous) => format!("Scan of {} cancelled, scanning PSDZ files in {}...", previous.display(), psdz_path.display()),
+            None => format!("Scanning PSDZ files in {}...", psdz_path.display()),
+        };
+        self.psdz_scan = Some(PendingScan {
+            psdz_path: psdz_path.to_path_buf(),
+            limits,
+            receiver,
+        });
+    }
+
+    /// Write the synthetic sample PSDZ folder and scan it like a real one
+    #[cfg(feature = "sample-data")]
+    pub fn load_sample_data(&mut self) {
+        match crate::sample_data::write_sample_psdz() {
+            Ok(psdz_path) => self.start_psdz_scan(&psdz_path),
+            Err(e) => self.status_message = format!("Error: Failed to write sample data: {}", e),
+        }
+    }
+
+    /// Abort the running PSDZ scan, if any, and return the folder it was scanning.
+    /// Files already shown from earlier scans are kept.
+    pub fn cancel_psdz_scan(&mut self) -> Option<PathBuf> {
+        let pending = self.psdz_scan.take()?;
+        pending.limits.cancel.store(true, Ordering::Relaxed);
+        self.status_message = format!("Scan of {} cancelled", pending.psdz_path.display());
+        Some(pending.psdz_path)
+    }
+
+    /// Check on the background PSDZ scan and apply its result once it is in.
+    /// Called every frame; keeps repainting while a scan is running.
+    pub fn poll_psdz_scan(&mut self, ctx: &egui::Context) {
+        let Some(pending) = &self.psdz_scan else {
+            return;
+        };
+        
+        let result = match pending.receiver.try_recv() {
+            Ok(result) => result,
+            Err(mpsc::TryRecvError::Empty) => {
+                if Instant::now() < pending.limits.deadline + SCAN_GRACE_PERIOD {
+                    ctx.request_repaint_after(Duration::from_millis(100));
+                    return;
                 }
-                _ => {}
+                self.status_message = format!("Error: Scan of {} timed out after {}s without a response",
+                    pending.psdz_path.display(), self.config.scan_timeout_secs);
+                self.psdz_scan = None;
+                return;
+            }
+            Err(mpsc::TryRecvError::Disconnected) => {
+                self.status_message = format!("Error: Scan of {} stopped unexpectedly", pending.psdz_path.display());
+                self.psdz_scan = None;
+                return;
+            }
+        };
+        
+        let Some(pending) = self.psdz_scan.take() else {
+            return;
+        };
+        let psdz_path = pending.psdz_path.as_path();
+        let Some((swe_path, scan)) = result else {
+            self.status_message = if pending.limits.expired() {
+                format!("Error: Scan timed out after {}s before a swe directory was found in {}", self.config.scan_timeout_secs, psdz_path.display())
+            } else {
+                format!("Error: No swe directory with btld/swfl folders found in {}", psdz_path.display())
+            };
+            return;
+        };
+        
+        let describe = |name: &str, exists: bool, file_type: FileType| {
+            if exists {
+                let count = scan.files.iter().filter(|f| f.file_type == file_type).count();
+                format!("{}: {} files", name, count)
+            } else {
+                format!("{}: 0 files (directory missing)", name)
+            }
+        };
+        let summary = format!("{}, {}",
+            describe("btld", scan.btld_dir_exists, FileType::BTLD),
+            describe("swfl", scan.swfl_dir_exists, FileType::SWFL));
+        
+        self.status_message = if scan.timed_out {
+            format!("Warning: Scan timed out after {}s, showing partial results ({}) from {}", self.config.scan_timeout_secs, summary, swe_path.display())
+        } else if scan.truncated {
+            format!("Warning: Scan truncated at {} files ({}) in {}", scan.files.len(), summary, swe_path.display())
+        } else if scan.btld_dir_exists && scan.swfl_dir_exists {
+            format!("Found {} files ({}) in {}", scan.files.len(), summary, swe_path.display())
+        } else {
+            format!("Warning: Partial PSDZ folder, scanned {} in {}", summary, swe_path.display())
+        };
+        
+        if !self.psdz_folders.iter().any(|folder| folder == psdz_path) {
+            self.psdz_folders.push(psdz_path.to_path_buf());
+        }
+        self.available_files.retain(|file| file.source_folder != psdz_path);
+        self.available_files.extend(scan.files);
+        
+        // Selections are kept by path, only files that disappeared are dropped
+        for label in self.drop_missing_selections() {
+            self.warnings.push(format!("Warning: Cleared the {} selection, the file no longer exists", label));
+        }
+    }
+
+    /// Scan an already added PSDZ folder again, e.g. after its contents changed
+    pub fn rescan_psdz_folder(&mut self, index: usize) {
+        if let Some(folder) = self.psdz_folders.get(index).cloned() {
+            self.start_psdz_scan(&folder);
+        }
+    }
+
+    /// Drop a PSDZ folder and its files from the browser. Selected files stay selected.
+    pub fn remove_psdz_folder(&mut self, index: usize) {
+        if index < self.psdz_folders.len() {
+            let folder = self.psdz_folders.remove(index);
+            self.available_files.retain(|file| file.source_folder != folder);
+        }
+    }
+
+    /// Clear selected files that no longer exist, e.g. after a rescan. Returns the
+    /// labels of the cleared slots.
+    fn drop_missing_selections(&mut self) -> Vec<&'static str> {
+        let mut cleared = Vec::new();
+        for (label, slot) in [("BTLD", &mut self.btld_file), ("SWFL1", &mut self.swfl1_file), ("SWFL2", &mut self.swfl2_file)] {
+            if slot.as_ref().is_some_and(|path| !path.exists()) {
+                *slot = None;
+                self.ui_state.disabled_slots.remove(&label.to_lowercase());
+                cleared.push(label);
             }
         }
+        if !cleared.is_empty() {
+            self.selection_changed();
+        }
+        cleared
+    }
+
+    /// Set a slot from a browser selection or a typed or pasted path
+    pub fn set_file_path(&mut self, file_type: &str, path: PathBuf) {
+        // Using one file twice doubles its segments, which only shows up later as overlaps
+        let duplicate_of = self.slot_holding(&path, file_type);
+        
+        // A segment map from a project belongs to the file it was loaded with
+        self.ui_state.output_options.xml_paths.remove(&file_type.to_uppercase());
+        match file_type {
+            "btld" => self.btld_file = Some(path),
+            "swfl1" => self.swfl1_file = Some(path),
+            "swfl2" => self.swfl2_file = Some(path),
+            _ => {}
+        }
+        self.selection_changed();
+        
+        if let Some(other_label) = duplicate_of {
+            let warning = format!("Warning: The {} file is also selected as {}; its segments would be combined twice",
+                file_type.to_uppercase(), other_label);
+            self.status_message = warning.clone();
+            self.warnings.push(warning);
+        }
+    }
+
+    /// Label of a slot other than `except_slot` that holds `path`, comparing resolved
+    /// paths where possible so `./x.bin` and `x.bin` count as the same file
+    fn slot_holding(&self, path: &Path, except_slot: &str) -> Option<&'static str> {
+        ["btld", "swfl1", "swfl2"].into_iter()
+            .filter(|&slot| slot != except_slot)
+            .filter_map(|slot| self.slot_input(slot))
+            .find(|(_, other)| is_same_file(other, path))
+            .map(|(label, _)| label)
+    }
+
+    /// Assign the files of a pasted `role:path` list to their slots. Lines that couldn't
+    /// be used are kept in `file_list_problems` and the import window stays open for them.
+    pub fn import_file_list(&mut self) {
+        let (assignments, problems) = parse_file_list(&self.ui_state.file_list_text);
+        for (slot, path) in &assignments {
+            self.set_file_path(slot, path.clone());
+        }
+        
+        self.status_message = if problems.is_empty() {
+            format!("Imported {} files from the list", assignments.len())
+        } else {
+            format!("Warning: Imported {} files from the list, {} lines could not be used", assignments.len(), problems.len())
+        };
+        self.ui_state.show_file_list_import = !problems.is_empty();
+        self.ui_state.file_list_problems = problems;
     }
 
     pub fn clear_file_selection(&mut self, file_type: &str) {
+        // A slot that is selected again starts out enabled
+        self.ui_state.disabled_slots.remove(file_type);
+        self.ui_state.output_options.xml_paths.remove(&file_type.to_uppercase());
         match file_type {
-            "btld" => {
-                self.btld_file = None;
-                self.ui_state.selected_btld_index = None;
-            }
-            "swfl1" => {
-                self.swfl1_file = None;
-                self.ui_state.selected_swfl1_index = None;
-            }
-            "swfl2" => {
-                self.swfl2_file = None;
-                self.ui_state.selected_swfl2_index = None;
-            }
+            "btld" => self.btld_file = None,
+            "swfl1" => self.swfl1_file = None,
+            "swfl2" => self.swfl2_file = None,
             _ => {}
         }
+        self.selection_changed();
+    }
+
+    /// The output path derived from the highest-priority selected input: SWFL1 gives
+    /// the configured name template (`<version>.vr.bin`) in the program directory, otherwise BTLD gives `<name>.extracted`
+    /// next to the BTLD file.
+    fn auto_output_path(&self) -> Option<PathBuf> {
+        let template = self.config.output_name_template();
+        let output_path = if let Some(output_filename) = self.swfl1_file.as_ref().and_then(|path| generate_output_filename(path, template)) {
+            get_program_directory().join(output_filename)
+        } else {
+            let btld_path = self.btld_file.as_ref()?;
+            btld_path.with_file_name(replace_last_in_file_name(btld_path.file_name()?, ".bin", ".extracted")?)
+        };
+        
+        if self.ui_state.output_options.layout == OutputLayout::SegmentDirectory {
+            let mut dir_name = output_path.file_name()?.to_os_string();
+            dir_name.push("_segments");
+            return Some(unused_output_path(&output_path.with_file_name(dir_name)));
+        }
+        let output_path = match self.ui_state.output_options.format {
+            OutputFormat::RawBinary => output_path,
+            format => output_path.with_extension(format.extension()),
+        };
+        // Never point an auto-generated name at the output of an earlier run
+        Some(unused_output_path(&output_path))
+    }
+
+    /// Refresh everything derived from the selected inputs
+    fn selection_changed(&mut self) {
+        self.update_auto_output_path();
+        self.update_ucl_requirement();
+        self.remember_selections();
+    }
+
+    /// Check the selected files' XML for compressed segments, so the header can tell
+    /// whether a missing UCL library actually matters. Unreadable XML counts as unknown.
+    fn update_ucl_requirement(&mut self) {
+        let selected = self.ordered_inputs();
+        if selected.is_empty() {
+            self.ucl_required = None;
+            return;
+        }
+        
+        let schema = self.config.xml_schema();
+        let mut required = Some(false);
+        for (label, bin_path) in selected {
+            match parse_xml(&self.xml_path_for(label, &bin_path), &schema) {
+                Ok(segments) if segments.iter().any(|segment| segment.is_compressed) => {
+                    required = Some(true);
+                    break;
+                }
+                Ok(_) => {}
+                Err(_) => required = None,
+            }
+        }
+        self.ucl_required = required;
+    }
+
+    /// Re-derive the output path from the current inputs unless the user picked one
+    pub fn update_auto_output_path(&mut self) {
+        if self.output_is_auto {
+            self.output_file = self.auto_output_path();
+        }
     }
 
     pub fn select_btld_file(&mut self) {
@@ -149,23 +622,12 @@ impl BMWVirtualReaderApp {
         }
         
         if let Some(path) = dialog.pick_file() {
-            self.btld_file = Some(path.clone());
-            
-            // Auto-generate output file path if not set and no SWFL1 selected
-            if self.output_file.is_none() && self.swfl1_file.is_none() {
-                if let Some(file_name) = path.file_name() {
-                    let file_name_str = file_name.to_string_lossy();
-                    // Replace .bin with .extracted in the filename
-                    let output_file_name = file_name_str.replace(".bin", ".extracted");
-                    let mut output_path = path.clone();
-                    output_path.set_file_name(output_file_name);
-                    self.output_file = Some(output_path);
-                }
-            }
+            self.set_file_path("btld", path.clone());
             
             // Update config
             if let Some(ref output_path) = self.output_file {
                 self.config.update_directories(&path, output_path);
+                self.mark_config_dirty();
             }
         }
     }
@@ -179,17 +641,11 @@ impl BMWVirtualReaderApp {
         }
         
         if let Some(path) = dialog.pick_file() {
-            self.swfl1_file = Some(path.clone());
-            
-            // Auto-generate output file path based on SWFL1
-            if let Some(output_filename) = generate_output_filename(&path) {
-                let mut output_path = get_program_directory();
-                output_path.push(output_filename);
-                self.output_file = Some(output_path);
-            }
+            self.set_file_path("swfl1", path.clone());
             
             // Update config
             self.config.last_input_dir = path.parent().map(|p| p.to_string_lossy().to_string());
+            self.mark_config_dirty();
         }
     }
 
@@ -202,11 +658,103 @@ impl BMWVirtualReaderApp {
         }
         
         if let Some(path) = dialog.pick_file() {
-            self.swfl2_file = Some(path.clone());
+            self.set_file_path("swfl2", path.clone());
             
             // Update config
             self.config.last_input_dir = path.parent().map(|p| p.to_string_lossy().to_string());
+            self.mark_config_dirty();
+        }
+    }
+
+    pub fn open_project(&mut self) {
+        let mut dialog = FileDialog::new()
+            .add_filter("Project files", &["json"])
+            .add_filter("All files", &["*"]);
+        
+        if let Some(ref last_dir) = self.config.last_input_dir {
+            dialog = dialog.set_directory(last_dir);
+        }
+        
+        if let Some(path) = dialog.pick_file() {
+            match ProjectFile::load(&path) {
+                Ok(project) => self.apply_project(project, &path),
+                Err(e) => self.status_message = format!("Error: {:#}", e),
+            }
+        }
+    }
+
+    /// Replace the selection, combine order, output and output options with a project's
+    fn apply_project(&mut self, project: ProjectFile, path: &Path) {
+        self.btld_file = None;
+        self.swfl1_file = None;
+        self.swfl2_file = None;
+        self.ui_state.disabled_slots.clear();
+        self.ui_state.output_options = project.options;
+        
+        let mut combine_order: Vec<String> = project.inputs.iter().map(|input| input.role.clone()).collect();
+        for slot in ["btld", "swfl1", "swfl2"] {
+            if !combine_order.iter().any(|existing| existing == slot) {
+                combine_order.push(slot.to_string());
+            }
+        }
+        self.ui_state.combine_order = combine_order;
+        
+        for input in project.inputs {
+            if !input.bin.is_file() {
+                self.warnings.push(format!("Warning: Project {} file not found: {}", input.role.to_uppercase(), input.bin.display()));
+            }
+            match input.role.as_str() {
+                "btld" => self.btld_file = Some(input.bin),
+                "swfl1" => self.swfl1_file = Some(input.bin),
+                "swfl2" => self.swfl2_file = Some(input.bin),
+                _ => continue,
+            }
+            if let Some(xml) = input.xml {
+                self.ui_state.output_options.xml_paths.insert(input.role.to_uppercase(), xml);
+            }
+            if !input.enabled {
+                self.ui_state.disabled_slots.insert(input.role);
+            }
+        }
+        
+        self.output_is_auto = project.output.is_none();
+        self.output_file = project.output;
+        self.selection_changed();
+        self.status_message = format!("Opened project {}", path.display());
+    }
+
+    pub fn save_project(&mut self) {
+        let mut dialog = FileDialog::new()
+            .add_filter("Project files", &["json"])
+            .set_file_name("project.json");
+        
+        if let Some(ref last_dir) = self.config.last_input_dir {
+            dialog = dialog.set_directory(last_dir);
         }
+        
+        let Some(path) = dialog.save_file() else {
+            return;
+        };
+        let project = ProjectFile {
+            inputs: self.ui_state.combine_order.iter()
+                .filter_map(|slot| {
+                    let (label, bin) = self.slot_input(slot)?;
+                    Some(ProjectInput {
+                        role: slot.clone(),
+                        xml: self.ui_state.output_options.xml_paths.get(label).cloned(),
+                        bin,
+                        enabled: !self.ui_state.disabled_slots.contains(slot),
+                    })
+                })
+                .collect(),
+            output: self.output_file.clone().filter(|_| !self.output_is_auto),
+            options: self.ui_state.output_options.clone(),
+        };
+        
+        self.status_message = match project.save(&path) {
+            Ok(()) => format!("Saved project to {}", path.display()),
+            Err(e) => format!("Error: {:#}", e),
+        };
     }
 
     pub fn select_output_file(&mut self) {
@@ -217,65 +765,572 @@ impl BMWVirtualReaderApp {
             dialog = dialog.set_directory(last_dir);
         }
         
-        if let Some(path) = dialog.save_file() {
+        let picked = if self.ui_state.output_options.layout == OutputLayout::SegmentDirectory {
+            dialog.pick_folder()
+        } else {
+            dialog.save_file()
+        };
+        
+        if let Some(path) = picked {
+            if self.ui_state.output_options.layout == OutputLayout::CombinedImage && self.config.format_from_extension {
+                let format = OutputFormat::from_extension(&path);
+                self.ui_state.output_options.format = format;
+                self.status_message = format!("Output format: {} (from the file extension)", format);
+            }
             self.output_file = Some(path.clone());
+            self.output_is_auto = false;
+            self.remember_selections();
             
             // Update config
             if let Some(ref btld_path) = self.btld_file {
                 self.config.update_directories(btld_path, &path);
+                self.mark_config_dirty();
             }
         }
     }
 
-    pub fn process_files(&mut self) -> Result<()> {
-        self.is_processing = true;
-        self.status_message = "Processing...".to_string();
+    pub fn select_reference_file(&mut self) {
+        let mut dialog = FileDialog::new()
+            .add_filter("All files", &["*"]);
         
+        if let Some(ref last_dir) = self.config.last_output_dir {
+            dialog = dialog.set_directory(last_dir);
+        }
+        
+        if let Some(path) = dialog.pick_file() {
+            self.ui_state.output_options.reference_file = Some(path);
+        }
+    }
+
+    pub fn select_base_image(&mut self) {
+        let mut dialog = FileDialog::new()
+            .add_filter("Binary files", &["bin"])
+            .add_filter("All files", &["*"]);
+        
+        if let Some(ref last_dir) = self.config.last_output_dir {
+            dialog = dialog.set_directory(last_dir);
+        }
+        
+        if let Some(path) = dialog.pick_file() {
+            self.ui_state.output_options.base_image = Some(path);
+        }
+    }
+
+    /// Check every available file's XML and segment layout without extracting anything
+    pub fn validate_all_files(&mut self) {
+        let schema = self.config.xml_schema();
+        self.validation_report = self.available_files.iter()
+            .map(|file| validate_file(file, &schema))
+            .collect();
+        self.ui_state.validation_sort = (ValidationSort::Status, true);
+        self.validation_report.sort_by_key(|validation| validation.status);
+        
+        let problems = self.validation_report.iter()
+            .filter(|validation| validation.status != ValidationStatus::Ok)
+            .count();
+        self.status_message = if problems == 0 {
+            format!("Validated {} files, no problems found", self.validation_report.len())
+        } else {
+            format!("Warning: {} of {} files have problems, see the validation report", problems, self.validation_report.len())
+        };
+        self.ui_state.show_validation_report = true;
+    }
+
+    /// Work out what "Create binary" would write from the segment maps, without reading
+    /// segment data or writing anything
+    pub fn preview_run(&mut self) {
+        let preview = process_files_preview(&self.enabled_inputs(), &self.run_options());
+        self.status_message = format!("Preview: {} segments, nothing written", preview.segments.len());
+        self.run_preview = Some(preview);
+        self.ui_state.show_run_preview = true;
+    }
+
+    /// Recompute the segment checksums of a combined image the user picks, laid out like
+    /// the current selection and options would write it
+    pub fn recalculate_checksums(&mut self) {
+        let mut dialog = FileDialog::new()
+            .add_filter("Binary files", &["bin"])
+            .add_filter("All files", &["*"]);
+        
+        if let Some(output_dir) = self.output_file.as_ref().and_then(|path| path.parent()) {
+            dialog = dialog.set_directory(output_dir);
+        } else if let Some(ref last_dir) = self.config.last_output_dir {
+            dialog = dialog.set_directory(last_dir);
+        }
+        
+        let Some(image_path) = dialog.pick_file() else {
+            return;
+        };
+        let mut warnings = Vec::new();
+        let mut last_status = String::new();
+        let result = recalculate_segment_checksums(&image_path, &self.enabled_inputs(), &self.run_options(), &mut |status| {
+            if status.starts_with("Warning") {
+                warnings.push(status.to_string());
+            }
+            last_status = status.to_string();
+        });
+        self.warnings = warnings;
+        self.status_message = match result {
+            Ok(_) => last_status,
+            Err(e) => format!("Error: Failed to recalculate checksums: {}", e),
+        };
+    }
+
+    /// The command that repeats the current run without the window, or `None` without an
+    /// output file. Options the command line can't express are listed as a warning.
+    pub fn command_line(&mut self) -> Option<String> {
+        let output_file = self.output_file.clone()?;
+        let (ucl_path, _) = self.ucl_library_path();
+        let (command, unsupported) = cli::command_line(&self.enabled_inputs(), &output_file, &self.ui_state.output_options, &ucl_path);
+        if unsupported.is_empty() {
+            self.warnings.clear();
+            self.status_message = "Copied the command line to the clipboard".to_string();
+        } else {
+            self.warnings = vec![format!("Warning: The command line can't express the {}; it uses their defaults instead", unsupported.join(", "))];
+            self.status_message = "Warning: Copied the command line to the clipboard, but some options are left out".to_string();
+        }
+        Some(command)
+    }
+
+    /// Selected input files with their labels, in the user's combine order
+    pub fn ordered_inputs(&self) -> Vec<(&'static str, PathBuf)> {
+        self.ui_state.combine_order.iter()
+            .filter_map(|slot| self.slot_input(slot))
+            .collect()
+    }
+
+    /// The output options of the UI completed with the settings that come from the config,
+    /// with a cancel flag of their own
+    fn run_options(&self) -> OutputOptions {
+        OutputOptions {
+            cancel: Arc::new(AtomicBool::new(false)),
+            safe_mode: self.config.safe_mode,
+            streaming_threshold: self.config.streaming_threshold(),
+            decimal_addresses: self.config.show_decimal_addresses,
+            flash_script_template: self.config.flash_script_template().to_string(),
+            xml_schema: self.config.xml_schema(),
+            ..self.ui_state.output_options.clone()
+        }
+    }
+
+    /// Selected input files that aren't unchecked, in combine order
+    pub fn enabled_inputs(&self) -> Vec<(&'static str, PathBuf)> {
+        self.ui_state.combine_order.iter()
+            .filter(|slot| !self.ui_state.disabled_slots.contains(*slot))
+            .filter_map(|slot| self.slot_input(slot))
+            .collect()
+    }
+
+    /// The segment map for the input labelled `label`: the one a project file named, or
+    /// the `.xml` next to the bin file
+    pub fn xml_path_for(&self, label: &str, bin_path: &Path) -> PathBuf {
+        self.ui_state.output_options.xml_paths.get(label)
+            .cloned()
+            .unwrap_or_else(|| get_xml_path(bin_path))
+    }
+
+    fn slot_input(&self, slot: &str) -> Option<(&'static str, PathBuf)> {
+        match slot {
+            "btld" => self.btld_file.clone().map(|path| ("BTLD", path)),
+            "swfl1" => self.swfl1_file.clone().map(|path| ("SWFL1", path)),
+            "swfl2" => self.swfl2_file.clone().map(|path| ("SWFL2", path)),
+            _ => None,
+        }
+    }
+
+    /// Start combining the selected inputs on a worker thread, so the window stays
+    /// responsive. `poll_extraction` applies its reports and the outcome.
+    pub fn process_files(&mut self) -> Result<()> {
+        if self.extraction.is_some() {
+            return Err(anyhow::anyhow!("An extraction is already running"));
+        }
+        // Checked before the run starts so a refused output doesn't leave it marked as running
         let output_path = self.output_file.as_ref()
             .ok_or_else(|| anyhow::anyhow!("No output file selected"))?
             .clone();
+        let inputs = self.enabled_inputs();
+        check_output_collision(&output_path, &inputs, &self.ui_state.output_options)?;
+        let reader = self.reader.clone()
+            .ok_or_else(|| anyhow::anyhow!("UCL library not loaded"))?;
         
-        if let Some(ref ucl_lib) = self.ucl_library {
-            let desired_size = if self.ui_state.use_desired_size {
-                self.ui_state.desired_size_mb
-            } else {
-                0.0 // Use 0.0 to indicate no desired size (natural size will be used)
-            };
+        let xml_paths: Vec<(PathBuf, PathBuf)> = inputs.iter()
+            .map(|(label, path)| (path.clone(), self.xml_path_for(label, path)))
+            .collect();
+        let options = self.run_options();
+        reader.library().set_transient_retries(self.config.ucl_transient_retries);
+        
+        self.is_processing = true;
+        self.status_message = "Processing...".to_string();
+        self.warnings.clear();
+        self.last_checksum = None;
+        self.progress = None;
+        self.file_progress = inputs.iter()
+            .map(|(label, _)| (label.to_string(), 0.0))
+            .collect();
+        
+        let run_inputs: Vec<(String, PathBuf)> = inputs.iter()
+            .map(|(label, path)| (label.to_string(), path.clone()))
+            .collect();
+        // Only a single raw file can be read back and compared
+        let snapshot = options.layout == OutputLayout::CombinedImage && options.format == OutputFormat::RawBinary && options.split_part_kb.is_none();
+        let previous = self.last_run.as_ref()
+            .filter(|previous| previous.inputs == run_inputs)
+            .map(|previous| previous.output.clone());
+        let keep_output = self.config.keep_last_output;
+        
+        let (sender, receiver) = mpsc::channel();
+        let worker_inputs = inputs.clone();
+        let worker_output = output_path.clone();
+        let worker_options = options.clone();
+        let worker = std::thread::spawn(move || {
+            // Cheap check on a single segment before the full run
+            let selected_files: Vec<(&PathBuf, PathBuf)> = xml_paths.iter()
+                .map(|(bin_path, xml_path)| (bin_path, xml_path.clone()))
+                .collect();
+            if let Some(warning) = check_ucl_variant(&selected_files, reader.library(), &worker_options.xml_schema) {
+                let _ = sender.send(ExtractionEvent::Status(warning));
+            }
             
-            process_files(
-                self.btld_file.as_ref(),
-                self.swfl1_file.as_ref(),
-                self.swfl2_file.as_ref(),
-                &output_path,
-                desired_size,
-                ucl_lib,
-                &mut |status| self.status_message = status.to_string()
-            )?;
-        } else {
-            return Err(anyhow::anyhow!("UCL library not loaded"));
+            // Sends fail only once the app is gone, then there is nobody left to tell
+            let result = reader.extract_with_progress(
+                &worker_inputs,
+                &worker_output,
+                &worker_options,
+                &mut |status| {
+                    let _ = sender.send(ExtractionEvent::Status(status.to_string()));
+                },
+                &mut |progress| {
+                    let _ = sender.send(ExtractionEvent::Progress(progress.clone()));
+                }
+            );
+            let outcome = result.map(|summary| RunOutcome {
+                checksum: summary.checksum,
+                output: snapshot.then(|| snapshot_output(&worker_output, previous.as_ref(), keep_output)).flatten(),
+            });
+            let _ = sender.send(ExtractionEvent::Finished(outcome.map_err(|e| e.to_string())));
+        });
+        
+        self.extraction = Some(PendingExtraction {
+            inputs: run_inputs,
+            output_path,
+            options,
+            receiver,
+            worker,
+        });
+        Ok(())
+    }
+
+    /// Stop a running extraction and wait up to `STOP_EXTRACTION_TIMEOUT` for its worker,
+    /// so the app doesn't exit while the output is being written. The worker stops before
+    /// its next file or segment and discards its `.partial` output instead of renaming it
+    /// into place. A worker still inside a decompression call after the timeout is left
+    /// to finish on its own; the previous output stays untouched either way.
+    pub fn stop_extraction(&mut self) {
+        let Some(pending) = self.extraction.take() else {
+            return;
+        };
+        pending.options.cancel.store(true, Ordering::Relaxed);
+        self.is_processing = false;
+        
+        // The worker drops its sender when it returns, which ends the wait early
+        let deadline = Instant::now() + STOP_EXTRACTION_TIMEOUT;
+        loop {
+            match pending.receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(_) => continue,
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    eprintln!("Extraction worker didn't stop within {} s, not waiting for it", STOP_EXTRACTION_TIMEOUT.as_secs());
+                    return;
+                }
+            }
         }
+        if pending.worker.join().is_err() {
+            eprintln!("Extraction worker panicked");
+        }
+    }
+
+    /// Apply what the extraction worker reported since the last frame, and wrap up the
+    /// run once it is done. Called every frame; keeps repainting while a run is going.
+    pub fn poll_extraction(&mut self, ctx: &egui::Context) {
+        let Some(pending) = &self.extraction else {
+            return;
+        };
+        
+        let result = loop {
+            match pending.receiver.try_recv() {
+                Ok(ExtractionEvent::Status(status)) => {
+                    if status.starts_with("Warning") {
+                        self.warnings.push(status.clone());
+                    }
+                    self.status_message = status;
+                }
+                Ok(ExtractionEvent::Progress(progress)) => {
+                    if let Some(entry) = self.file_progress.iter_mut().find(|(l, _)| *l == progress.label) {
+                        entry.1 = progress.fraction;
+                    }
+                    self.progress = Some(progress);
+                }
+                Ok(ExtractionEvent::Finished(result)) => break result,
+                Err(mpsc::TryRecvError::Empty) => {
+                    ctx.request_repaint_after(Duration::from_millis(100));
+                    return;
+                }
+                Err(mpsc::TryRecvError::Disconnected) => break Err("Extraction stopped unexpectedly".to_string()),
+            }
+        };
         
+        let Some(pending) = self.extraction.take() else {
+            return;
+        };
         self.is_processing = false;
-        Ok(())
+        match result {
+            Ok(outcome) => self.finish_extraction(pending, outcome),
+            Err(e) => self.status_message = format!("Error: {}", e),
+        }
     }
 
-    pub fn reload_ucl_library(&mut self) {
-        self.ucl_library = None;
+    /// Bookkeeping after a successful run: buffer statistics, the comparison with the
+    /// previous output and the recent outputs list
+    fn finish_extraction(&mut self, run: PendingExtraction, outcome: RunOutcome) {
+        self.last_checksum = outcome.checksum;
+        
+        if let Some(hits) = self.reader.as_deref().and_then(|reader| reader.library().take_buffer_stats()) {
+            let histogram: Vec<String> = BUFFER_SIZE_TIERS.iter().zip(hits)
+                .map(|(tier, count)| format!("{}: {}", tier, count))
+                .collect();
+            self.status_message = format!("{} | Buffer sizes used: {}", self.status_message, histogram.join(", "));
+        }
+        
+        if let Some((output, comparison)) = outcome.output {
+            if let Some(comparison) = comparison {
+                self.status_message = format!("{} | {}", self.status_message, comparison);
+            }
+            self.last_run = Some(LastRun {
+                inputs: run.inputs,
+                output,
+            });
+        }
+        
+        self.config.add_recent_output(&run.output_path);
+        self.mark_config_dirty();
+    }
+
+    pub fn refresh_segment_preview(&mut self) {
+        self.segment_preview.clear();
+        let schema = self.config.xml_schema();
+        
+        let selected_files = [
+            ("BTLD", self.btld_file.clone()),
+            ("SWFL1", self.swfl1_file.clone()),
+            ("SWFL2", self.swfl2_file.clone()),
+        ];
         
-        if let Ok(lib) = UclLibrary::new(&self.config.ucl_library_path) {
-            self.ucl_library = Some(lib);
-            self.status_message = "UCL library reloaded successfully".to_string();
+        for (label, file) in selected_files {
+            if let Some(path) = file {
+                match parse_xml(&self.xml_path_for(label, &path), &schema) {
+                    Ok(segments) => self.segment_preview.push((label.to_string(), segments)),
+                    Err(e) => self.status_message = format!("Error: Failed to parse {} XML: {}", label, e),
+                }
+            }
+        }
+    }
+
+    /// Extract segment `index` of the input labelled `label` on its own and return it as
+    /// a hex dump, along with a description for the status line
+    pub fn segment_hex_dump(&self, label: &str, index: usize) -> Result<(String, String)> {
+        let (_, bin_path) = self.ordered_inputs().into_iter()
+            .find(|(input_label, _)| *input_label == label)
+            .ok_or_else(|| anyhow::anyhow!("No {} file selected", label))?;
+        let ucl_lib = self.reader.as_deref()
+            .map(VirtualReader::library)
+            .ok_or_else(|| anyhow::anyhow!("UCL library not loaded"))?;
+        
+        let options = self.run_options();
+        let mut warnings = Vec::new();
+        let segments = process_single_file(
+            &bin_path,
+            &self.xml_path_for(label, &bin_path),
+            ucl_lib,
+            &options,
+            Some(&BTreeSet::from([index])),
+            &mut |status| {
+                if status.starts_with("Warning") {
+                    warnings.push(status.to_string());
+                }
+            },
+            &mut |_, _, _| {}
+        )?;
+        
+        let (target_addr, data, _) = segments.into_iter().next()
+            .ok_or_else(|| anyhow::anyhow!("{} segment {} was not extracted{}", label, index,
+                warnings.first().map(|w| format!(": {}", w)).unwrap_or_default()))?;
+        let data = data.into_bytes()?;
+        let description = format!("Copied {} segment {} as hex ({} bytes at 0x{:08X})", label, index, data.len(), target_addr);
+        Ok((format_hex_dump(&data, target_addr, MAX_HEX_DUMP_BYTES), description))
+    }
+
+    /// Load the XML of the input labelled `label` into the inspector, keeping the text
+    /// even when parsing fails so the failure can be located
+    pub fn inspect_xml(&mut self, label: &str) {
+        let Some((_, bin_path)) = self.ordered_inputs().into_iter().find(|(l, _)| *l == label) else {
+            return;
+        };
+        let xml_path = self.xml_path_for(label, &bin_path);
+        
+        let xml_text = match read_xml_stripped(&xml_path) {
+            Ok(text) => text,
+            Err(e) => {
+                self.status_message = format!("Error: Failed to read {} XML {}: {}", label, xml_path.display(), e);
+                return;
+            }
+        };
+        let schema = self.config.xml_schema();
+        let (segments, parse_error) = match parse_xml_str(&xml_text, &schema) {
+            Ok(segments) => (segments, None),
+            Err(e) => (Vec::new(), Some(e.to_string())),
+        };
+        
+        self.xml_inspection = Some(XmlInspection {
+            label: label.to_string(),
+            xml_path,
+            segment_rows: flash_segment_rows(&xml_text, &schema),
+            xml_text,
+            segments,
+            parse_error,
+        });
+        self.ui_state.inspected_segment = None;
+        self.ui_state.show_xml_inspector = true;
+    }
+
+    /// Record that the config changed so it can be autosaved once changes settle
+    pub fn mark_config_dirty(&mut self) {
+        self.config_dirty_since = Some(Instant::now());
+    }
+
+    /// Save the config if it changed and no further changes arrived within the
+    /// autosave delay. Called every frame; the on-exit save remains as a fallback.
+    pub fn save_config_if_dirty(&mut self, ctx: &egui::Context) {
+        let Some(dirty_since) = self.config_dirty_since else {
+            return;
+        };
+        
+        if !self.config.autosave_config {
+            return;
+        }
+        
+        let elapsed = dirty_since.elapsed();
+        if elapsed < CONFIG_AUTOSAVE_DELAY {
+            ctx.request_repaint_after(CONFIG_AUTOSAVE_DELAY - elapsed);
+            return;
+        }
+        
+        self.config_dirty_since = None;
+        if let Err(e) = self.config.save() {
+            self.status_message = format!("Warning: Failed to save config: {}", e);
+        }
+    }
+
+    /// Probe the UCL path in the settings once it stopped changing, so a bad path shows
+    /// up before Reload is clicked. Called every frame while the settings are open.
+    pub fn check_ucl_path_if_due(&mut self, ctx: &egui::Context) {
+        if !self.ui_state.show_settings {
+            return;
+        }
+        let setting = (self.config.ucl_library_path.clone(), self.config.ucl_decompress_symbol.clone());
+        if self.ucl_path_check.as_ref().is_some_and(|check| (&check.path, &check.pinned_symbol) == (&setting.0, &setting.1)) {
+            return;
+        }
+        
+        match &self.ucl_path_edit {
+            Some((edited, since)) if *edited == setting => {
+                let elapsed = since.elapsed();
+                if elapsed < UCL_PATH_CHECK_DELAY {
+                    ctx.request_repaint_after(UCL_PATH_CHECK_DELAY - elapsed);
+                    return;
+                }
+            }
+            _ => {
+                self.ucl_path_edit = Some((setting, Instant::now()));
+                ctx.request_repaint_after(UCL_PATH_CHECK_DELAY);
+                return;
+            }
+        }
+        
+        self.ucl_path_edit = None;
+        let (path, pinned_symbol) = setting;
+        let result = if resolve_library_path(&path).is_file() {
+            UclLibrary::probe(&path, self.config.pinned_decompress_symbol()).map_err(|e| e.to_string())
         } else {
-            self.status_message = format!("Failed to load UCL library from {}", self.config.ucl_library_path);
+            Err("File not found".to_string())
+        };
+        self.ucl_path_check = Some(UclPathCheck {
+            path,
+            pinned_symbol,
+            result,
+        });
+    }
+
+    /// Replace the config with defaults (re-deriving the default UCL path) and reload
+    /// the library. The last used folders, selections and recent outputs survive unless a
+    /// full wipe was requested.
+    pub fn reset_config(&mut self, keep_recent_folders: bool) {
+        let mut config = AppConfig::default();
+        if keep_recent_folders {
+            config.last_input_dir = self.config.last_input_dir.take();
+            config.last_output_dir = self.config.last_output_dir.take();
+            config.recent_outputs = std::mem::take(&mut self.config.recent_outputs);
+            config.last_btld = self.config.last_btld.take();
+            config.last_swfl1 = self.config.last_swfl1.take();
+            config.last_swfl2 = self.config.last_swfl2.take();
+            config.last_output = self.config.last_output.take();
         }
+        config.loaded_from_file = self.config.loaded_from_file;
+        self.config = config;
+        self.mark_config_dirty();
+        self.reload_ucl_library();
     }
 
-    pub fn handle_file_action(&mut self, action: FileAction) {
-        match action {
-            FileAction::Clear(file_type) => self.clear_file_selection(&file_type),
-            FileAction::SelectBTLD(index) => self.select_file_by_index(index, "btld"),
-            FileAction::SelectSWFL1(index) => self.select_file_by_index(index, "swfl1"),
-            FileAction::SelectSWFL2(index) => self.select_file_by_index(index, "swfl2"),
+    /// Make `path` the configured library and load it
+    pub fn use_ucl_library(&mut self, path: &Path) {
+        self.config.ucl_library_path = path.to_string_lossy().to_string();
+        self.ucl_path_override = None;
+        self.mark_config_dirty();
+        self.reload_ucl_library();
+        
+        if self.reader.is_some() && self.ui_state.show_ucl_setup {
+            self.finish_ucl_setup();
+        }
+    }
+
+    /// Close the first-run setup for good, whether a library was found or it was skipped
+    pub fn finish_ucl_setup(&mut self) {
+        self.ui_state.show_ucl_setup = false;
+        self.config.ucl_setup_complete = true;
+        self.mark_config_dirty();
+    }
+
+    /// Load the library at `ucl_path` with the configured validation, pinned symbol and
+    /// retries, collecting buffer statistics if they were asked for
+    fn load_reader(&self, ucl_path: &str) -> Result<VirtualReader, Box<dyn std::error::Error>> {
+        let reader = VirtualReader::with_library_options(ucl_path, self.config.validate_ucl_library,
+            self.config.pinned_decompress_symbol(), self.config.ucl_transient_retries)?;
+        reader.library().set_buffer_stats_enabled(self.buffer_stats);
+        Ok(reader)
+    }
+
+    pub fn reload_ucl_library(&mut self) {
+        self.reader = None;
+        
+        let (ucl_path, source) = self.ucl_library_path();
+        match self.load_reader(&ucl_path) {
+            Ok(reader) => {
+                let lib = reader.library();
+                self.status_message = format!("UCL library reloaded successfully ({}) from {} [{}]", lib.decompress_symbol(), lib.path().display(), source);
+                self.reader = Some(Arc::new(reader));
+            }
+            Err(e) => {
+                self.status_message = format!("Failed to load UCL library from {} [{}]: {}", ucl_path, source, e);
+            }
         }
     }
 } 
\ No newline at end of file
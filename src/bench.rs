@@ -0,0 +1,62 @@
+use std::time::Instant;
+use crate::config::AppConfig;
+use crate::ucl_bindings::UclLibrary;
+
+/// Uncompressed sizes of the benchmark datasets
+const BENCH_SIZES: [usize; 3] = [16 * 1024, 512 * 1024, 4 * 1024 * 1024];
+
+pub const DEFAULT_BENCH_ITERATIONS: usize = 20;
+
+/// Deterministic filler so runs are comparable between library builds
+fn bench_dataset(size: usize) -> Vec<u8> {
+    let mut state: u32 = 0x2545_F491;
+    (0..size)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+/// Decompress fixed datasets `iterations` times each and print throughput and the
+/// number of output buffers allocated per run of `UclLibrary::decompress`. Validation,
+/// the pinned symbol and retries come from `config`.
+pub fn run_benchmark(ucl_library_path: &str, config: &AppConfig, iterations: usize) -> Result<(), Box<dyn std::error::Error>> {
+    let library = UclLibrary::new(ucl_library_path, config.validate_ucl_library, config.pinned_decompress_symbol(), config.ucl_transient_retries)?;
+    
+    println!("UCL library: {} ({})", library.path().display(), library.decompress_symbol());
+    println!("Iterations per dataset: {}", iterations);
+    println!();
+    println!("{:>12} {:>14} {:>12} {:>12} {:>16}", "Output", "Stream", "Total ms", "MB/s", "Allocs/call");
+    
+    for &size in &BENCH_SIZES {
+        let data = bench_dataset(size);
+        let stream = library.encode_stored(&data);
+        
+        // Verify once outside the timed loop
+        let (output, _) = library.decompress(&stream)?;
+        if output != data {
+            return Err(format!("Benchmark output mismatch for {} byte dataset", size).into());
+        }
+        
+        let allocations_before = library.buffer_allocation_count();
+        let start = Instant::now();
+        for _ in 0..iterations {
+            library.decompress(&stream)?;
+        }
+        let elapsed = start.elapsed().as_secs_f64();
+        let allocations = library.buffer_allocation_count() - allocations_before;
+        
+        let megabytes = (size * iterations) as f64 / (1024.0 * 1024.0);
+        println!("{:>12} {:>14} {:>12.1} {:>12.1} {:>16.2}",
+            size,
+            stream.len(),
+            elapsed * 1000.0,
+            megabytes / elapsed.max(f64::EPSILON),
+            allocations as f64 / iterations.max(1) as f64);
+    }
+    
+    Ok(())
+}
@@ -0,0 +1,343 @@
+use std::path::PathBuf;
+use crate::config::AppConfig;
+use crate::file_ops::{failure_kind, MAX_OUTPUT_SIZE};
+use bmw_virtual_reader::VirtualReader;
+use crate::types::{FailureKind, OutputFormat, OutputOptions, PadMode, RunSummary};
+
+/// Input flags in combine order, with the label each input is reported under
+const INPUT_FLAGS: [(&str, &str); 3] = [("BTLD", "--btld"), ("SWFL1", "--swfl1"), ("SWFL2", "--swfl2")];
+
+/// Options of a command-line run that take a value, besides `INPUT_FLAGS`
+const VALUE_FLAGS: [&str; 7] = ["--output", "--size", "--reference", "--reference-addr", "--format", "--ucl", "--ucl-lib"];
+
+/// Options of a command-line run that take no value
+const SWITCH_FLAGS: [&str; 1] = ["--json"];
+
+/// Whether `args` ask for a headless run instead of the window
+pub fn is_cli_run(args: &[String]) -> bool {
+    args.iter().any(|arg| arg == "--output" || INPUT_FLAGS.iter().any(|(_, flag)| arg == flag))
+}
+
+/// The argument following `flag`, if both are given
+fn flag_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == flag)
+        .and_then(|pos| args.get(pos + 1))
+        .map(String::as_str)
+}
+
+/// Reject options a command-line run doesn't know and options whose value is missing or
+/// is another option, so a typo fails the run instead of silently changing the output
+fn check_args(args: &[String]) -> Result<(), CliFailure> {
+    let mut rest = args.iter().skip(1);
+    while let Some(arg) = rest.next() {
+        let takes_value = VALUE_FLAGS.contains(&arg.as_str()) || INPUT_FLAGS.iter().any(|(_, flag)| flag == arg);
+        if takes_value {
+            match rest.next() {
+                Some(value) if !value.starts_with("--") => {}
+                Some(value) => return Err(CliFailure::usage(format!("{} needs a value, got the option {}", arg, value))),
+                None => return Err(CliFailure::usage(format!("{} needs a value", arg))),
+            }
+        } else if !SWITCH_FLAGS.contains(&arg.as_str()) {
+            return Err(CliFailure::usage(format!("Unknown option: {}", arg)));
+        }
+    }
+    Ok(())
+}
+
+/// Exit status of a command-line run, one per kind of failure so scripts can branch on it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExitStatus {
+    /// The output was written from every input
+    Success = 0,
+    /// Missing or invalid arguments
+    Usage = 2,
+    /// The UCL library couldn't be loaded
+    Library = 3,
+    /// An input or its segment map couldn't be read or parsed
+    Parse = 4,
+    /// A segment didn't decompress to anything near its declared size
+    Decompress = 5,
+    /// The output couldn't be written
+    Write = 6,
+    /// The run was refused before writing, e.g. overlapping segments
+    Validation = 7,
+}
+
+impl ExitStatus {
+    pub fn code(self) -> i32 {
+        self as i32
+    }
+}
+
+impl From<FailureKind> for ExitStatus {
+    fn from(kind: FailureKind) -> Self {
+        match kind {
+            FailureKind::Parse => ExitStatus::Parse,
+            FailureKind::Decompress => ExitStatus::Decompress,
+            FailureKind::Write => ExitStatus::Write,
+            FailureKind::Validation => ExitStatus::Validation,
+        }
+    }
+}
+
+/// Why a command-line run stopped, with the status it exits with
+struct CliFailure {
+    status: ExitStatus,
+    message: String,
+}
+
+impl CliFailure {
+    fn usage(message: impl Into<String>) -> Self {
+        CliFailure { status: ExitStatus::Usage, message: message.into() }
+    }
+}
+
+/// Combine the inputs given with `--btld`, `--swfl1` and `--swfl2` into `--output`
+/// without opening the window, see `extract`. Status messages are printed to stdout,
+/// or with `--json` to stderr, and stdout gets a single JSON object with the result.
+/// An input that fails makes the run fail even though the others are still combined,
+/// since a build script asked for all of them.
+pub fn run_cli(args: &[String], ucl_library_path: &str, config: &AppConfig) -> ExitStatus {
+    let json = args.iter().any(|arg| arg == "--json");
+    let mut warnings = Vec::new();
+    let outcome = extract(args, ucl_library_path, config, &mut |status| {
+        if status.starts_with("Warning") {
+            warnings.push(status.to_string());
+        }
+        if json {
+            eprintln!("{}", status);
+        } else {
+            println!("{}", status);
+        }
+    });
+    
+    let (summary, failure) = match outcome {
+        Ok(summary) if summary.failed_inputs.is_empty() => (Some(summary), None),
+        Ok(summary) => {
+            let failed: Vec<&str> = summary.failed_inputs.iter().map(|input| input.label.as_str()).collect();
+            let failure = CliFailure {
+                status: summary.failed_inputs[0].kind.into(),
+                message: format!("{} of {} inputs could not be processed ({}); {} was written from the rest",
+                    failed.len(), failed.len() + summary.segment_counts.len(), failed.join(", "),
+                    flag_value(args, "--output").unwrap_or_default()),
+            };
+            (Some(summary), Some(failure))
+        }
+        Err(failure) => (None, Some(failure)),
+    };
+    let status = failure.as_ref().map_or(ExitStatus::Success, |failure| failure.status);
+    
+    if json {
+        let summary = summary.unwrap_or_default();
+        let segments: serde_json::Map<String, serde_json::Value> = summary.segment_counts.iter()
+            .map(|(label, count)| (label.clone(), serde_json::json!(count)))
+            .collect();
+        let failed_inputs: Vec<serde_json::Value> = summary.failed_inputs.iter()
+            .map(|input| serde_json::json!({
+                "input": input.label,
+                "kind": input.kind,
+                "error": input.error,
+            }))
+            .collect();
+        println!("{}", serde_json::json!({
+            "success": status == ExitStatus::Success,
+            "exit_code": status.code(),
+            "output": flag_value(args, "--output"),
+            "size": summary.output_size,
+            "base_address": summary.address_range.map(|(start, _)| format!("0x{:08X}", start)),
+            "end_address": summary.address_range.map(|(_, end)| format!("0x{:08X}", end)),
+            "segments": segments,
+            "checksum": summary.checksum.map(|checksum| checksum.to_string()),
+            "warnings": warnings,
+            "failed_inputs": failed_inputs,
+            "error": failure.as_ref().map(|failure| failure.message.as_str()),
+        }));
+    } else if let Some(failure) = &failure {
+        eprintln!("Error: {}", failure.message);
+    }
+    status
+}
+
+/// Run the extraction `args` describe, padded to `--size` MB if given or shaped after
+/// `--reference` (starting at `--reference-addr`, hex) if given. The format is `--format`
+/// if given, otherwise picked from the output's extension unless the config turns that
+/// off. Everything else comes from the config like in the GUI.
+fn extract(args: &[String], ucl_library_path: &str, config: &AppConfig, status_callback: &mut dyn FnMut(&str)) -> Result<RunSummary, CliFailure> {
+    check_args(args)?;
+    let inputs: Vec<(&str, PathBuf)> = INPUT_FLAGS.iter()
+        .filter_map(|&(label, flag)| Some((label, PathBuf::from(flag_value(args, flag)?))))
+        .collect();
+    if inputs.is_empty() {
+        return Err(CliFailure::usage("No input files given, use --btld, --swfl1 and/or --swfl2"));
+    }
+    let output_file = flag_value(args, "--output")
+        .map(PathBuf::from)
+        .ok_or_else(|| CliFailure::usage("No output file given, use --output <path>"))?;
+    
+    let defaults = OutputOptions::default();
+    let (pad_mode, desired_size_mb) = match flag_value(args, "--size") {
+        Some(size) => {
+            let max_mb = MAX_OUTPUT_SIZE / (1024 * 1024);
+            let size_mb: f32 = size.parse()
+                .ok()
+                .filter(|&mb: &f32| mb > 0.0 && mb <= max_mb as f32)
+                .ok_or_else(|| CliFailure::usage(format!("Invalid --size: {} (expected a size in MB up to {})", size, max_mb)))?;
+            (PadMode::DesiredSize, size_mb)
+        }
+        None => (PadMode::Natural, defaults.desired_size_mb),
+    };
+    
+    let reference_file = flag_value(args, "--reference").map(PathBuf::from);
+    let reference_file_addr = match flag_value(args, "--reference-addr") {
+        Some(addr) => Some(u32::from_str_radix(addr.trim_start_matches("0x").trim_start_matches("0X"), 16)
+            .map_err(|_| CliFailure::usage(format!("Invalid --reference-addr: {} (expected a hex address)", addr)))?),
+        None => None,
+    };
+    if reference_file_addr.is_some() && reference_file.is_none() {
+        return Err(CliFailure::usage("--reference-addr needs --reference <path>"));
+    }
+    
+    let format = match flag_value(args, "--format") {
+        Some("raw") => OutputFormat::RawBinary,
+        Some("ihex") => OutputFormat::IntelHex,
+        Some("srec") => OutputFormat::Srec,
+        Some(other) => return Err(CliFailure::usage(format!("Invalid --format: {} (expected raw, ihex or srec)", other))),
+        None if config.format_from_extension => OutputFormat::from_extension(&output_file),
+        None => OutputFormat::RawBinary,
+    };
+    
+    let reader = VirtualReader::with_library_options(ucl_library_path, config.validate_ucl_library, config.pinned_decompress_symbol(), config.ucl_transient_retries)
+        .map_err(|e| CliFailure { status: ExitStatus::Library, message: e.to_string() })?;
+    status_callback(&format!("UCL library: {} ({})", reader.library().path().display(), reader.library().decompress_symbol()));
+    status_callback(&format!("Output format: {}{}", format,
+        if flag_value(args, "--format").is_none() && config.format_from_extension { " (from the file extension)" } else { "" }));
+    
+    let options = OutputOptions {
+        format,
+        pad_mode,
+        desired_size_mb,
+        reference_file,
+        reference_file_addr,
+        safe_mode: config.safe_mode,
+        streaming_threshold: config.streaming_threshold(),
+        decimal_addresses: config.show_decimal_addresses,
+        flash_script_template: config.flash_script_template().to_string(),
+        xml_schema: config.xml_schema(),
+        ..defaults
+    };
+    reader.extract_with_progress(&inputs, &output_file, &options, status_callback, &mut |_| {})
+        .map_err(|e| CliFailure { status: failure_kind(&e).into(), message: e.to_string() })
+}
+
+/// `arg` as the host's shell reads it back: in double quotes for cmd.exe, where paths
+/// can't contain quotes, and in single quotes for POSIX shells. Plain words are left as
+/// they are so the usual command stays readable.
+#[cfg(feature = "gui")]
+fn shell_quote(arg: &str) -> String {
+    if cfg!(windows) {
+        if !arg.is_empty() && !arg.contains(|c: char| c.is_whitespace() || "&|<>^%()\";,=".contains(c)) {
+            return arg.to_string();
+        }
+        return format!("\"{}\"", arg);
+    }
+    if !arg.is_empty() && arg.chars().all(|c| c.is_ascii_alphanumeric() || "/._-+:,@".contains(c)) {
+        return arg.to_string();
+    }
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+/// The command that repeats a run of `inputs` into `output` without the window, and the
+/// options in `options` it can't express; the command line leaves those at their defaults.
+/// Settings that come from the config, like safe mode, are the same either way.
+#[cfg(feature = "gui")]
+pub fn command_line(inputs: &[(&str, PathBuf)], output: &std::path::Path, options: &OutputOptions, ucl_library_path: &str) -> (String, Vec<&'static str>) {
+    let program = std::env::current_exe()
+        .map(|path| path.to_string_lossy().to_string())
+        .unwrap_or_else(|_| "bmw_virtual_reader".to_string());
+    let mut args = vec![program];
+    for (label, path) in inputs {
+        if let Some((_, flag)) = INPUT_FLAGS.iter().find(|(flag_label, _)| flag_label == label) {
+            args.extend([flag.to_string(), path.to_string_lossy().to_string()]);
+        }
+    }
+    args.extend(["--output".to_string(), output.to_string_lossy().to_string()]);
+    if options.format != OutputFormat::RawBinary || OutputFormat::from_extension(output) != OutputFormat::RawBinary {
+        let format = match options.format {
+            OutputFormat::RawBinary => "raw",
+            OutputFormat::IntelHex => "ihex",
+            OutputFormat::Srec => "srec",
+        };
+        args.extend(["--format".to_string(), format.to_string()]);
+    }
+    if options.pad_mode == PadMode::DesiredSize {
+        args.extend(["--size".to_string(), options.desired_size_mb.to_string()]);
+    }
+    if let Some(reference) = &options.reference_file {
+        args.extend(["--reference".to_string(), reference.to_string_lossy().to_string()]);
+        if let Some(addr) = options.reference_file_addr {
+            args.extend(["--reference-addr".to_string(), format!("0x{:08X}", addr)]);
+        }
+    }
+    args.extend(["--ucl-lib".to_string(), ucl_library_path.to_string()]);
+    let command = args.iter().map(|arg| shell_quote(arg)).collect::<Vec<_>>().join(" ");
+    
+    // The command line always combines in the order of INPUT_FLAGS
+    let flag_positions: Vec<usize> = inputs.iter()
+        .filter_map(|(label, _)| INPUT_FLAGS.iter().position(|(flag_label, _)| flag_label == label))
+        .collect();
+    let defaults = OutputOptions::default();
+    let unsupported = [
+        (flag_positions.windows(2).any(|pair| pair[0] > pair[1]), "combine order"),
+        (options.layout != defaults.layout, "segment directory layout"),
+        (options.checksum != defaults.checksum, "checksum algorithm"),
+        (options.pad_mode == PadMode::NextPowerOfTwo, "next power of two padding"),
+        (options.gap_fill != defaults.gap_fill || options.pad_fill != defaults.pad_fill, "gap and pad fill"),
+        (options.base_image.is_some(), "base image"),
+        (options.size_policy != defaults.size_policy, "size mismatch policy"),
+        (options.segment_selection.values().any(|selection| !selection.trim().is_empty()), "segment selection"),
+        (options.header.is_some(), "vendor header"),
+        (options.byte_order != defaults.byte_order, "byte order"),
+        (options.holes_report, "holes report"),
+        (options.segments_csv, "segments CSV"),
+        (options.flash_script, "flashing script"),
+        (options.text_banner, "provenance banner"),
+        (options.min_real_data_percent.is_some(), "real-data check"),
+        (options.allow_overlap != defaults.allow_overlap, "refusing overlaps"),
+        (options.extract_compressed, "copying compressed segments"),
+        (options.split_part_kb.is_some(), "split output"),
+        (!options.xml_paths.is_empty(), "segment maps from a project file"),
+    ].into_iter()
+        .filter_map(|(set, name)| set.then_some(name))
+        .collect();
+    (command, unsupported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage_error(args: &[&str]) -> Option<String> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        match extract(&args, "", &AppConfig::default(), &mut |_| {}) {
+            Err(failure) if failure.status == ExitStatus::Usage => Some(failure.message),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn unknown_options_are_rejected() {
+        let message = usage_error(&["bmw_virtual_reader", "--swfl1", "a.bin", "--output", "out.hex", "--fromat", "ihex"]).unwrap();
+        assert_eq!(message, "Unknown option: --fromat");
+        assert!(usage_error(&["bmw_virtual_reader", "--swfl1", "a.bin", "--output", "out.bin", "--sise", "4"]).is_some());
+        assert!(usage_error(&["bmw_virtual_reader", "--swfl1", "a.bin", "stray", "--output", "out.bin"]).is_some());
+    }
+
+    #[test]
+    fn options_are_not_taken_as_values() {
+        let message = usage_error(&["bmw_virtual_reader", "--swfl1", "a.bin", "--output", "--json"]).unwrap();
+        assert_eq!(message, "--output needs a value, got the option --json");
+        assert_eq!(usage_error(&["bmw_virtual_reader", "--swfl1", "a.bin", "--output"]).unwrap(), "--output needs a value");
+        assert!(usage_error(&["bmw_virtual_reader", "--swfl1", "--btld", "b.bin", "--output", "out.bin"]).is_some());
+    }
+}
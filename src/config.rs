@@ -1,6 +1,9 @@
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::fs;
+use crate::file_ops::{validate_flash_script_template, validate_output_template, DEFAULT_FLASH_SCRIPT_TEMPLATE, DEFAULT_OUTPUT_NAME_TEMPLATE};
+use crate::ucl_bindings::DEFAULT_TRANSIENT_RETRIES;
+use crate::xml_parser::XmlSchema;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AppConfig {
@@ -9,6 +12,128 @@ pub struct AppConfig {
     pub window_width: f32,
     pub window_height: f32,
     pub ucl_library_path: String,
+    #[serde(default = "default_true")]
+    pub validate_ucl_library: bool,
+    /// Exact decompression function to use instead of probing the usual NRV2 names,
+    /// for libraries with nonstandard exports; empty probes
+    #[serde(default)]
+    pub ucl_decompress_symbol: String,
+    #[serde(default = "default_true")]
+    pub autosave_config: bool,
+    /// Whether the first-run UCL setup was finished or skipped
+    #[serde(default)]
+    pub ucl_setup_complete: bool,
+    /// Strict limits and decompression timeouts for untrusted files
+    #[serde(default)]
+    pub safe_mode: bool,
+    /// Give up scanning a PSDZ folder after this many seconds, keeping what was found
+    #[serde(default = "default_scan_timeout_secs")]
+    pub scan_timeout_secs: u64,
+    /// Stop scanning a PSDZ folder once this many files were found
+    #[serde(default = "default_scan_max_files")]
+    pub scan_max_files: usize,
+    /// How many levels below `swe` to look for `btld`/`swfl` directories; 1 is only directly
+    #[serde(default = "default_scan_max_depth")]
+    pub scan_max_depth: usize,
+    /// How often a decompression that failed with a transient UCL error is repeated
+    #[serde(default = "default_ucl_transient_retries")]
+    pub ucl_transient_retries: usize,
+    /// Stream uncompressed segments larger than this many MB instead of buffering them; 0 is off
+    #[serde(default)]
+    pub streaming_threshold_mb: u32,
+    /// Name of auto-generated SWFL1 outputs, see `generate_output_filename` for the placeholders
+    #[serde(default = "default_output_name_template")]
+    pub output_name_template: String,
+    /// Flashing script written next to the output when enabled, see
+    /// `write_flash_script` for the placeholders
+    #[serde(default = "default_flash_script_template")]
+    pub flash_script_template: String,
+    /// Show addresses in the segment preview in decimal too, and add decimal fields to
+    /// segment manifests
+    #[serde(default)]
+    pub show_decimal_addresses: bool,
+    /// Element names the XML parser accepts, for schema variants across PSDZ versions
+    #[serde(default)]
+    pub xml_schema: XmlSchema,
+    /// Keep the last combined image in memory so a re-run can list the changed ranges;
+    /// otherwise only its checksum is kept
+    #[serde(default)]
+    pub keep_last_output: bool,
+    /// Set the format of a combined image from the extension of an output picked by hand
+    #[serde(default = "default_true")]
+    pub format_from_extension: bool,
+    #[serde(default)]
+    pub recent_outputs: Vec<String>,
+    /// Inputs selected when the app was last used, restored on startup if they still exist
+    #[serde(default)]
+    pub last_btld: Option<String>,
+    #[serde(default)]
+    pub last_swfl1: Option<String>,
+    #[serde(default)]
+    pub last_swfl2: Option<String>,
+    /// Output picked by hand when the app was last used; auto-generated names are derived again
+    #[serde(default)]
+    pub last_output: Option<String>,
+    /// Whether this config was read from config.json rather than defaulted
+    #[serde(skip)]
+    pub loaded_from_file: bool,
+}
+
+/// Environment variable that overrides the UCL library path for a session
+pub const UCL_PATH_ENV_VAR: &str = "BMW_UCL_PATH";
+
+/// Where the UCL library path in use came from, in order of precedence
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UclPathSource {
+    CommandLine,
+    Environment,
+    Config,
+    Default,
+}
+
+impl std::fmt::Display for UclPathSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UclPathSource::CommandLine => write!(f, "--ucl/--ucl-lib flag"),
+            UclPathSource::Environment => write!(f, "{} environment variable", UCL_PATH_ENV_VAR),
+            UclPathSource::Config => write!(f, "config.json"),
+            UclPathSource::Default => write!(f, "default search"),
+        }
+    }
+}
+
+/// Settings file, relative to the working directory
+const CONFIG_FILE_NAME: &str = "config.json";
+
+/// How many successfully written output files are remembered
+const MAX_RECENT_OUTPUTS: usize = 10;
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_scan_timeout_secs() -> u64 {
+    30
+}
+
+fn default_scan_max_files() -> usize {
+    10_000
+}
+
+fn default_scan_max_depth() -> usize {
+    3
+}
+
+fn default_ucl_transient_retries() -> usize {
+    DEFAULT_TRANSIENT_RETRIES
+}
+
+fn default_output_name_template() -> String {
+    DEFAULT_OUTPUT_NAME_TEMPLATE.to_string()
+}
+
+fn default_flash_script_template() -> String {
+    DEFAULT_FLASH_SCRIPT_TEMPLATE.to_string()
 }
 
 impl Default for AppConfig {
@@ -19,27 +144,101 @@ impl Default for AppConfig {
             window_width: 600.0,
             window_height: 400.0,
             ucl_library_path: Self::get_default_dll_path(),
+            validate_ucl_library: true,
+            ucl_decompress_symbol: String::new(),
+            autosave_config: true,
+            ucl_setup_complete: false,
+            safe_mode: false,
+            scan_timeout_secs: default_scan_timeout_secs(),
+            scan_max_files: default_scan_max_files(),
+            scan_max_depth: default_scan_max_depth(),
+            ucl_transient_retries: default_ucl_transient_retries(),
+            streaming_threshold_mb: 0,
+            output_name_template: default_output_name_template(),
+            flash_script_template: default_flash_script_template(),
+            show_decimal_addresses: false,
+            xml_schema: XmlSchema::default(),
+            keep_last_output: false,
+            format_from_extension: true,
+            recent_outputs: Vec::new(),
+            last_btld: None,
+            last_swfl1: None,
+            last_swfl2: None,
+            last_output: None,
+            loaded_from_file: false,
         }
     }
 }
 
 impl AppConfig {
+    /// Absolute path of the settings file, whether or not it exists yet
+    pub fn config_path() -> PathBuf {
+        std::env::current_dir()
+            .map(|dir| dir.join(CONFIG_FILE_NAME))
+            .unwrap_or_else(|_| PathBuf::from(CONFIG_FILE_NAME))
+    }
+
     pub fn load() -> Self {
-        if let Ok(config_str) = fs::read_to_string("config.json") {
-            if let Ok(config) = serde_json::from_str(&config_str) {
+        if let Ok(config_str) = fs::read_to_string(Self::config_path()) {
+            if let Ok(mut config) = serde_json::from_str::<AppConfig>(&config_str) {
+                config.loaded_from_file = true;
                 return config;
             }
         }
         Self::default()
     }
 
+    /// The pinned decompression function, `None` to probe
+    pub fn pinned_decompress_symbol(&self) -> Option<&str> {
+        Some(self.ucl_decompress_symbol.trim()).filter(|symbol| !symbol.is_empty())
+    }
+
+    /// `streaming_threshold_mb` in bytes, `None` when streaming is off
+    pub fn streaming_threshold(&self) -> Option<usize> {
+        (self.streaming_threshold_mb > 0).then(|| self.streaming_threshold_mb as usize * 1024 * 1024)
+    }
+
+    /// The output name template, or the default one while the configured template is invalid
+    pub fn output_name_template(&self) -> &str {
+        if validate_output_template(&self.output_name_template).is_ok() {
+            &self.output_name_template
+        } else {
+            DEFAULT_OUTPUT_NAME_TEMPLATE
+        }
+    }
+
+    /// The flashing script template, or the default one while the configured template is invalid
+    pub fn flash_script_template(&self) -> &str {
+        if validate_flash_script_template(&self.flash_script_template).is_ok() {
+            &self.flash_script_template
+        } else {
+            DEFAULT_FLASH_SCRIPT_TEMPLATE
+        }
+    }
+
+    /// The XML schema segment maps are parsed with, or the default names while the
+    /// configured schema is invalid
+    pub fn xml_schema(&self) -> XmlSchema {
+        if self.xml_schema.validate().is_ok() {
+            self.xml_schema.clone()
+        } else {
+            XmlSchema::default()
+        }
+    }
+
+    /// Why the configured XML schema is replaced by the default names, if it is
+    pub fn check_xml_schema(&self) -> Result<(), String> {
+        self.xml_schema.validate()
+            .map_err(|e| format!("Invalid xml_schema in config.json, using the default names: {}", e))
+    }
+
     pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
         let config_str = serde_json::to_string_pretty(self)?;
-        fs::write("config.json", config_str)?;
+        fs::write(Self::config_path(), config_str)?;
         Ok(())
     }
 
-    pub fn update_directories(&mut self, input_path: &PathBuf, output_path: &PathBuf) {
+    pub fn update_directories(&mut self, input_path: &Path, output_path: &Path) {
         if let Some(parent) = input_path.parent() {
             self.last_input_dir = Some(parent.to_string_lossy().to_string());
         }
@@ -48,6 +247,65 @@ impl AppConfig {
         }
     }
 
+    /// Session-only library path from the `--ucl` flag or the environment. An explicit
+    /// flag takes precedence over the environment variable; neither is saved to config.
+    pub fn ucl_library_override(cli_path: Option<String>) -> Option<(String, UclPathSource)> {
+        if let Some(path) = cli_path {
+            return Some((path, UclPathSource::CommandLine));
+        }
+        std::env::var(UCL_PATH_ENV_VAR)
+            .ok()
+            .filter(|path| !path.trim().is_empty())
+            .map(|path| (path, UclPathSource::Environment))
+    }
+
+    /// The configured library path and whether it came from config.json or the default search
+    pub fn configured_ucl_library_path(&self) -> (String, UclPathSource) {
+        let source = if self.loaded_from_file {
+            UclPathSource::Config
+        } else {
+            UclPathSource::Default
+        };
+        (self.ucl_library_path.clone(), source)
+    }
+
+    /// Move `output_path` to the front of the recent outputs list
+    pub fn add_recent_output(&mut self, output_path: &Path) {
+        let output = output_path.to_string_lossy().to_string();
+        self.recent_outputs.retain(|existing| *existing != output);
+        self.recent_outputs.insert(0, output);
+        self.recent_outputs.truncate(MAX_RECENT_OUTPUTS);
+    }
+
+    /// Existing UCL libraries in the places they are usually installed or unpacked to
+    pub fn find_ucl_library_candidates() -> Vec<PathBuf> {
+        const LIBRARY_NAMES: [&str; 6] = ["libucl-1.dll", "ucl.dll", "libucl.so.1", "libucl.so", "libucl.1.dylib", "libucl.dylib"];
+        
+        let mut search_dirs = Vec::new();
+        if let Some(exe_dir) = std::env::current_exe().ok().and_then(|p| p.parent().map(Path::to_path_buf)) {
+            search_dirs.push(exe_dir.join("lib"));
+            search_dirs.push(exe_dir);
+        }
+        if let Ok(current_dir) = std::env::current_dir() {
+            search_dirs.push(current_dir.join("lib"));
+            search_dirs.push(current_dir);
+        }
+        for system_dir in ["/usr/lib", "/usr/local/lib", "/usr/lib/x86_64-linux-gnu", "/opt/homebrew/lib", "C:\\Windows\\System32"] {
+            search_dirs.push(PathBuf::from(system_dir));
+        }
+        
+        let mut candidates: Vec<PathBuf> = Vec::new();
+        for dir in search_dirs {
+            for name in LIBRARY_NAMES {
+                let candidate = dir.join(name);
+                if candidate.is_file() && !candidates.contains(&candidate) {
+                    candidates.push(candidate);
+                }
+            }
+        }
+        candidates
+    }
+
     /// Get the default DLL path based on the current executable location
     fn get_default_dll_path() -> String {
         // Try to get the executable directory
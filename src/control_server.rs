@@ -0,0 +1,431 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use serde::Deserialize;
+use bmw_virtual_reader::VirtualReader;
+use crate::config::AppConfig;
+use crate::file_ops::{parse_hex_bytes, validate_flash_script_template};
+use crate::types::{ChecksumAlgorithm, Endianness, HeaderSpec, OutputFormat, OutputLayout, OutputOptions, PadMode, RawSegmentAddress, SizeMismatchPolicy};
+use crate::xml_parser::XmlSchema;
+
+/// Port the server listens on where there are no Unix sockets
+#[cfg(not(unix))]
+pub const DEFAULT_CONTROL_PORT: u16 = 48400;
+
+/// Name of the socket, in `$XDG_RUNTIME_DIR` or else the temp folder
+#[cfg(unix)]
+const CONTROL_SOCKET_NAME: &str = "bmw_virtual_reader.sock";
+
+/// Name of the file holding the access token of a TCP server, in the per-user app data folder
+#[cfg(not(unix))]
+const CONTROL_TOKEN_FILE: &str = "control_token";
+
+#[derive(Debug, Deserialize)]
+struct ControlInput {
+    label: String,
+    path: PathBuf,
+    /// Segment map, if it isn't the .xml next to `path`
+    #[serde(default)]
+    xml: Option<PathBuf>,
+}
+
+/// Vendor header fields; missing fields take the `HeaderSpec` defaults, except that the
+/// version is only written when given
+#[derive(Debug, Deserialize)]
+struct ControlHeader {
+    /// Magic bytes in hex
+    #[serde(default)]
+    magic: Option<String>,
+    #[serde(default)]
+    version: Option<u32>,
+    #[serde(default)]
+    include_base_addr: Option<bool>,
+    #[serde(default)]
+    include_length: Option<bool>,
+    /// Older spelling of the request's `byte_order`, which takes precedence
+    #[serde(default)]
+    big_endian: Option<bool>,
+}
+
+/// One extraction request, sent as a single line of JSON
+#[derive(Debug, Deserialize)]
+struct ControlRequest {
+    /// Access token from the token file, required where the server listens on TCP
+    #[serde(default)]
+    token: Option<String>,
+    inputs: Vec<ControlInput>,
+    output: PathBuf,
+    /// "natural", "desired_size" or "next_power_of_two"
+    #[serde(default)]
+    pad_mode: Option<String>,
+    #[serde(default)]
+    desired_size_mb: Option<f32>,
+    /// "combined" or "segments"
+    #[serde(default)]
+    layout: Option<String>,
+    /// "raw", "ihex" or "srec", the file format of a combined image
+    #[serde(default)]
+    format: Option<String>,
+    /// "crc32" or "crc16_ccitt", reported over a combined image
+    #[serde(default)]
+    checksum: Option<String>,
+    #[serde(default)]
+    gap_fill: Option<u8>,
+    #[serde(default)]
+    pad_fill: Option<u8>,
+    #[serde(default)]
+    reference_file: Option<PathBuf>,
+    /// Address of the reference's first byte, defaults to the lowest segment address
+    #[serde(default)]
+    reference_file_addr: Option<u32>,
+    /// Existing dump to overlay the segments onto
+    #[serde(default)]
+    base_image: Option<PathBuf>,
+    /// Address of the base image's first byte, defaults to the lowest segment address
+    #[serde(default)]
+    base_image_addr: Option<u32>,
+    #[serde(default)]
+    safe_mode: bool,
+    /// "warn", "truncate" or "reject"
+    #[serde(default)]
+    size_policy: Option<String>,
+    /// Segment indices per input label, e.g. {"SWFL1": "2-5,8"}
+    #[serde(default)]
+    segments: HashMap<String, String>,
+    #[serde(default)]
+    header: Option<ControlHeader>,
+    /// "big" or "little", for every multi-byte field written into the output
+    #[serde(default)]
+    byte_order: Option<String>,
+    #[serde(default)]
+    holes_report: bool,
+    /// Also write a CSV with one row per segment
+    #[serde(default)]
+    segments_csv: bool,
+    /// Also write a flashing script
+    #[serde(default)]
+    flash_script: bool,
+    /// Head text outputs with the tool version, time, inputs and UCL function
+    #[serde(default)]
+    text_banner: bool,
+    /// Template for the flashing script, the default one when missing
+    #[serde(default)]
+    flash_script_template: Option<String>,
+    /// Stream uncompressed segments larger than this many MB
+    #[serde(default)]
+    streaming_threshold_mb: Option<u32>,
+    /// Fail if at most this percentage of the image differs from the gap fill
+    #[serde(default)]
+    min_real_data_percent: Option<f32>,
+    /// Whether a combined image may have overlapping segments, true when missing
+    #[serde(default)]
+    allow_overlap: Option<bool>,
+    /// Copy compressed segments without decompressing them
+    #[serde(default)]
+    extract_compressed: bool,
+    /// "target" or "source", where raw compressed segments are placed
+    #[serde(default)]
+    raw_address: Option<String>,
+    /// Split a combined image into parts of this many KB
+    #[serde(default)]
+    split_part_kb: Option<u32>,
+    /// Add decimal address fields to segment manifests
+    #[serde(default)]
+    decimal_addresses: bool,
+}
+
+impl ControlRequest {
+    /// The run settings this request asks for, parsing segment maps with `xml_schema`
+    fn output_options(&self, xml_schema: &XmlSchema) -> Result<OutputOptions, String> {
+        let defaults = OutputOptions::default();
+        let pad_mode = match self.pad_mode.as_deref() {
+            None | Some("natural") => PadMode::Natural,
+            Some("desired_size") => PadMode::DesiredSize,
+            Some("next_power_of_two") => PadMode::NextPowerOfTwo,
+            Some(other) => return Err(format!("Unknown pad_mode: {}", other)),
+        };
+        let layout = match self.layout.as_deref() {
+            None | Some("combined") => OutputLayout::CombinedImage,
+            Some("segments") => OutputLayout::SegmentDirectory,
+            Some(other) => return Err(format!("Unknown layout: {}", other)),
+        };
+        let format = match self.format.as_deref() {
+            None | Some("raw") => OutputFormat::RawBinary,
+            Some("ihex") => OutputFormat::IntelHex,
+            Some("srec") => OutputFormat::Srec,
+            Some(other) => return Err(format!("Unknown format: {}", other)),
+        };
+        let checksum = match self.checksum.as_deref() {
+            None | Some("crc32") => ChecksumAlgorithm::Crc32,
+            Some("crc16_ccitt") => ChecksumAlgorithm::Crc16Ccitt,
+            Some(other) => return Err(format!("Unknown checksum: {}", other)),
+        };
+        let size_policy = match self.size_policy.as_deref() {
+            None | Some("warn") => SizeMismatchPolicy::WarnAndKeep,
+            Some("truncate") => SizeMismatchPolicy::TruncateToTarget,
+            Some("reject") => SizeMismatchPolicy::Reject,
+            Some(other) => return Err(format!("Unknown size_policy: {}", other)),
+        };
+        let header_big_endian = self.header.as_ref().and_then(|header| header.big_endian);
+        let byte_order = match (self.byte_order.as_deref(), header_big_endian) {
+            (Some("big"), _) | (None, Some(true)) => Endianness::Big,
+            (Some("little"), _) | (None, Some(false)) => Endianness::Little,
+            (None, None) => defaults.byte_order,
+            (Some(other), _) => return Err(format!("Unknown byte_order: {}", other)),
+        };
+        let raw_segment_address = match self.raw_address.as_deref() {
+            None | Some("target") => RawSegmentAddress::Target,
+            Some("source") => RawSegmentAddress::Source,
+            Some(other) => return Err(format!("Unknown raw_address: {}", other)),
+        };
+        
+        let header = match &self.header {
+            Some(header) => {
+                let defaults = HeaderSpec::default();
+                let magic = match &header.magic {
+                    Some(magic) => parse_hex_bytes(magic).map_err(|e| format!("Invalid header magic: {}", e))?,
+                    None => defaults.magic,
+                };
+                Some(HeaderSpec {
+                    magic,
+                    version: header.version,
+                    include_base_addr: header.include_base_addr.unwrap_or(defaults.include_base_addr),
+                    include_length: header.include_length.unwrap_or(defaults.include_length),
+                })
+            }
+            None => None,
+        };
+        
+        Ok(OutputOptions {
+            layout,
+            format,
+            checksum,
+            pad_mode,
+            desired_size_mb: self.desired_size_mb.unwrap_or(defaults.desired_size_mb),
+            gap_fill: self.gap_fill.unwrap_or(defaults.gap_fill),
+            pad_fill: self.pad_fill.unwrap_or(defaults.pad_fill),
+            reference_file: self.reference_file.clone(),
+            reference_file_addr: self.reference_file_addr,
+            base_image: self.base_image.clone(),
+            base_image_addr: self.base_image_addr,
+            safe_mode: self.safe_mode,
+            size_policy,
+            segment_selection: self.segments.clone(),
+            header,
+            byte_order,
+            holes_report: self.holes_report,
+            segments_csv: self.segments_csv,
+            flash_script: self.flash_script,
+            text_banner: self.text_banner,
+            flash_script_template: match &self.flash_script_template {
+                Some(template) => {
+                    validate_flash_script_template(template).map_err(|e| format!("Invalid flash_script_template: {}", e))?;
+                    template.clone()
+                }
+                None => defaults.flash_script_template.clone(),
+            },
+            streaming_threshold: self.streaming_threshold_mb
+                .filter(|&mb| mb > 0)
+                .map(|mb| mb as usize * 1024 * 1024),
+            min_real_data_percent: self.min_real_data_percent,
+            allow_overlap: self.allow_overlap.unwrap_or(defaults.allow_overlap),
+            extract_compressed: self.extract_compressed,
+            raw_segment_address,
+            split_part_kb: self.split_part_kb.filter(|&kb| kb > 0),
+            decimal_addresses: self.decimal_addresses,
+            xml_paths: self.inputs.iter()
+                .filter_map(|input| Some((input.label.clone(), input.xml.clone()?)))
+                .collect(),
+            xml_schema: xml_schema.clone(),
+            cancel: defaults.cancel,
+        })
+    }
+}
+
+/// Serve extraction requests until the process is stopped. On Unix they come in on a
+/// socket only the current user can connect to, at `endpoint` or
+/// `$XDG_RUNTIME_DIR/bmw_virtual_reader.sock`. Elsewhere the server listens on
+/// 127.0.0.1, port `endpoint` or 48400, and every request has to carry the token written
+/// to the user's app data folder, since any local process or web page can reach a TCP port.
+/// Each connection is served on a thread of its own and sends newline-delimited JSON
+/// requests, getting one JSON line back per request. The UCL library is loaded once and
+/// shared by every request; validation, the pinned symbol, retries and the XML schema
+/// come from `config`.
+pub fn run_control_server(ucl_library_path: &str, config: &AppConfig, endpoint: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    let virtual_reader = Arc::new(VirtualReader::with_library_options(ucl_library_path, config.validate_ucl_library,
+        config.pinned_decompress_symbol(), config.ucl_transient_retries)?);
+    let xml_schema = Arc::new(config.xml_schema());
+    let library = virtual_reader.library();
+    println!("UCL library: {} ({})", library.path().display(), library.decompress_symbol());
+    
+    #[cfg(unix)]
+    {
+        let listener = bind_socket(endpoint)?;
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => serve_connection(stream, &virtual_reader, &xml_schema, None),
+                Err(e) => eprintln!("Failed to accept control connection: {}", e),
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let port = match endpoint {
+            Some(port) => port.parse().map_err(|_| format!("Invalid port: {}", port))?,
+            None => DEFAULT_CONTROL_PORT,
+        };
+        // Loopback only, this is not meant to be reachable from other machines
+        let listener = std::net::TcpListener::bind((std::net::Ipv4Addr::LOCALHOST, port))?;
+        let token: Arc<str> = write_token()?.into();
+        println!("Listening for extraction requests on {}", listener.local_addr()?);
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => serve_connection(stream, &virtual_reader, &xml_schema, Some(token.clone())),
+                Err(e) => eprintln!("Failed to accept control connection: {}", e),
+            }
+        }
+    }
+    
+    Ok(())
+}
+
+/// Bind the control socket with owner-only permissions, replacing one left behind by a
+/// server that didn't exit cleanly
+#[cfg(unix)]
+fn bind_socket(endpoint: Option<&str>) -> Result<std::os::unix::net::UnixListener, Box<dyn std::error::Error>> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+    
+    let path = match endpoint {
+        Some(path) => PathBuf::from(path),
+        None => std::env::var_os("XDG_RUNTIME_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(std::env::temp_dir)
+            .join(CONTROL_SOCKET_NAME),
+    };
+    if std::fs::symlink_metadata(&path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        std::fs::remove_file(&path)?;
+    }
+    // No moment in which others could connect: the socket is created 0600 rather than
+    // restricted afterwards. Nothing else runs yet, so changing the umask is safe.
+    let previous_umask = unsafe { libc::umask(0o177) };
+    let listener = std::os::unix::net::UnixListener::bind(&path);
+    unsafe { libc::umask(previous_umask) };
+    let listener = listener.map_err(|e| format!("Failed to create socket {}: {}", path.display(), e))?;
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+    println!("Listening for extraction requests on {}", path.display());
+    Ok(listener)
+}
+
+/// Write a new random access token to the user's app data folder and return it
+#[cfg(not(unix))]
+fn write_token() -> Result<String, Box<dyn std::error::Error>> {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+    
+    // Each RandomState is keyed from the OS random source
+    let token: String = (0..4)
+        .map(|_| format!("{:016x}", RandomState::new().build_hasher().finish()))
+        .collect();
+    let dir = std::env::var_os("LOCALAPPDATA")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+        .join("bmw_virtual_reader");
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join(CONTROL_TOKEN_FILE);
+    std::fs::write(&path, &token)?;
+    println!("Requests need the \"token\" from {}", path.display());
+    Ok(token)
+}
+
+/// Serve one connection on a thread of its own, so a client that keeps its connection
+/// open doesn't hold up the others
+fn serve_connection<S: Read + Write + Send + 'static>(stream: S, virtual_reader: &Arc<VirtualReader>, xml_schema: &Arc<XmlSchema>, token: Option<Arc<str>>) {
+    let virtual_reader = virtual_reader.clone();
+    let xml_schema = xml_schema.clone();
+    std::thread::spawn(move || {
+        if let Err(e) = handle_connection(stream, &virtual_reader, &xml_schema, token.as_deref()) {
+            eprintln!("Control connection failed: {}", e);
+        }
+    });
+}
+
+/// Answer the requests of one connection. Anything that isn't a JSON object, such as the
+/// request line of an HTTP POST a web page sends, closes the connection before a later
+/// line could be run as a request.
+fn handle_connection<S: Read + Write>(stream: S, virtual_reader: &VirtualReader, xml_schema: &XmlSchema, token: Option<&str>) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        if !line.trim_start().starts_with('{') {
+            send_line(reader.get_mut(), &serde_json::json!({ "ok": false, "error": "Requests are single lines of JSON, closing the connection" }))?;
+            return Ok(());
+        }
+        
+        let response = match serde_json::from_str::<ControlRequest>(&line) {
+            Ok(request) if !has_token(&request, token) => serde_json::json!({ "ok": false, "error": "Missing or wrong token" }),
+            Ok(request) => handle_request(&request, virtual_reader, xml_schema),
+            Err(e) => serde_json::json!({ "ok": false, "error": format!("Invalid request: {}", e) }),
+        };
+        send_line(reader.get_mut(), &response)?;
+    }
+}
+
+/// Write `response` and its newline at once; the stream isn't buffered
+fn send_line(stream: &mut impl Write, response: &serde_json::Value) -> std::io::Result<()> {
+    stream.write_all(format!("{}\n", response).as_bytes())
+}
+
+/// Whether `request` carries the server's token; without one any request is accepted
+fn has_token(request: &ControlRequest, token: Option<&str>) -> bool {
+    token.is_none() || request.token.as_deref() == token
+}
+
+fn handle_request(request: &ControlRequest, virtual_reader: &VirtualReader, xml_schema: &XmlSchema) -> serde_json::Value {
+    let options = match request.output_options(xml_schema) {
+        Ok(options) => options,
+        Err(e) => return serde_json::json!({ "ok": false, "error": e }),
+    };
+    let inputs: Vec<(&str, PathBuf)> = request.inputs.iter()
+        .map(|input| (input.label.as_str(), input.path.clone()))
+        .collect();
+    
+    let mut messages = Vec::new();
+    let mut warnings = Vec::new();
+    let result = virtual_reader.extract_with_progress(
+        &inputs,
+        &request.output,
+        &options,
+        &mut |status| {
+            if status.starts_with("Warning") {
+                warnings.push(status.to_string());
+            }
+            messages.push(status.to_string());
+        },
+        &mut |_| {}
+    );
+    
+    match result {
+        Ok(summary) => serde_json::json!({
+            "ok": true,
+            "output": request.output,
+            "checksum": summary.checksum.map(|checksum| checksum.to_string()),
+            "messages": messages,
+            "warnings": warnings,
+        }),
+        Err(e) => serde_json::json!({
+            "ok": false,
+            "error": e.to_string(),
+            "messages": messages,
+            "warnings": warnings,
+        }),
+    }
+}
@@ -1,110 +1,403 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
 use std::fs;
-use std::io::{Read, Seek};
-use std::path::PathBuf;
+use std::io::{BufReader, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use anyhow::{Result, Context};
-use crate::types::{AvailableFile, FileType};
-use crate::xml_parser::parse_xml;
+use crate::types::{AvailableFile, ChecksumAlgorithm, Endianness, ExtractionProgress, FailedInput, FailureKind, FileType, FileValidation, FlashSegment, HeaderSpec, ImageChecksum, OutputFormat, OutputLayout, OutputOptions, PadMode, PreviewSegment, PsdzScan, RawSegmentAddress, RunPreview, RunSummary, ScanLimits, SizeMismatchPolicy, ValidationStatus};
+use crate::xml_parser::{parse_xml, XmlSchema};
 use crate::ucl_bindings::UclLibrary;
 
-pub fn scan_psdz_files(psdz_path: &PathBuf) -> Vec<AvailableFile> {
-    let mut available_files = Vec::new();
+/// Largest source or target size a single segment may declare in safe mode
+const SAFE_MODE_MAX_SEGMENT_SIZE: u32 = 16 * 1024 * 1024;
+/// How long one segment may take to decompress in safe mode
+const SAFE_MODE_DECOMPRESS_TIMEOUT: Duration = Duration::from_secs(10);
+/// Largest combined output, including padding
+pub const MAX_OUTPUT_SIZE: u32 = 200 * 1024 * 1024;
+
+/// Bytes addressable with the 32-bit addresses of the segment maps and output formats
+const ADDRESS_SPACE_SIZE: u64 = 1 << 32;
+
+/// Longest file name most file systems accept, in bytes
+const MAX_FILE_NAME_LEN: usize = 255;
+
+/// How many directory levels below the selected folder are searched for `swe`
+const SWE_SEARCH_MAX_DEPTH: usize = 5;
+
+/// Share of the combined span covered by segments below which the run warns that a
+/// file may be missing or a far-off segment is inflating the image
+const LOW_OCCUPANCY_PERCENT: f64 = 25.0;
+
+fn is_swe_directory(path: &Path, limits: &ScanLimits) -> bool {
+    !find_flash_dirs(path, limits).is_empty()
+}
+
+/// Locate the `swe` directory (containing `btld`/`swfl`, directly or in subfolders up
+/// to the scan depth) for a PSDZ data folder.
+/// The folder itself may be the `swe` directory, contain it directly, or contain it
+/// deeper in a versioned ISTA layout; the search is breadth-first and bounded, and gives
+/// up once `limits` expire or the scan is cancelled.
+pub fn find_swe_directory(data_path: &Path, limits: &ScanLimits) -> Option<PathBuf> {
+    let is_named_swe = data_path.file_name()
+        .map(|name| name.to_string_lossy().eq_ignore_ascii_case("swe"))
+        .unwrap_or(false);
+    if is_named_swe && is_swe_directory(data_path, limits) {
+        return Some(data_path.to_path_buf());
+    }
     
-    // Scan BTLD files
-    let btld_path = psdz_path.join("swe").join("btld");
-    if btld_path.exists() {
-        if let Ok(entries) = fs::read_dir(btld_path) {
-            for entry in entries.filter_map(|e| e.ok()) {
-                let path = entry.path();
-                if let Some(file_name) = path.file_name() {
-                    let file_name_str = file_name.to_string_lossy();
-                    // Check if filename contains ".bin" (for files like .bin.001_015_000)
-                    if file_name_str.contains(".bin") {
-                        if let Ok(metadata) = fs::metadata(&path) {
-                            // Convert display name: replace .bin. with _ for better readability
-                            let display_name = file_name_str.replace(".bin.", "_");
-                            
-                            available_files.push(AvailableFile {
-                                path,
-                                file_type: FileType::BTLD,
-                                display_name,
-                                size: metadata.len(),
-                            });
-                        }
-                    }
+    let mut current_level = vec![data_path.to_path_buf()];
+    for _ in 0..SWE_SEARCH_MAX_DEPTH {
+        let mut next_level = Vec::new();
+        
+        for dir in &current_level {
+            if limits.expired() || limits.cancelled() {
+                return None;
+            }
+            let Ok(entries) = fs::read_dir(dir) else {
+                continue;
+            };
+            let mut subdirs: Vec<PathBuf> = entries
+                .filter_map(|e| e.ok())
+                .map(|e| e.path())
+                .filter(|p| p.is_dir())
+                .collect();
+            subdirs.sort();
+            
+            for subdir in subdirs {
+                let is_named_swe = subdir.file_name()
+                    .map(|name| name.to_string_lossy().eq_ignore_ascii_case("swe"))
+                    .unwrap_or(false);
+                if is_named_swe && is_swe_directory(&subdir, limits) {
+                    return Some(subdir);
+                }
+                next_level.push(subdir);
+            }
+        }
+        
+        if next_level.is_empty() {
+            break;
+        }
+        current_level = next_level;
+    }
+    
+    None
+}
+
+/// The `btld`/`swfl` directories below `swe_path`, searched up to `limits.max_depth`
+/// levels down so layouts with a subfolder per I-step are covered. Matching directories
+/// aren't searched further. Sorted by path.
+fn find_flash_dirs(swe_path: &Path, limits: &ScanLimits) -> Vec<(PathBuf, FileType)> {
+    let mut flash_dirs = Vec::new();
+    let mut current_level = vec![swe_path.to_path_buf()];
+    for _ in 0..limits.max_depth.max(1) {
+        let mut next_level = Vec::new();
+        
+        for dir in &current_level {
+            if limits.expired() || limits.cancelled() {
+                break;
+            }
+            let Ok(entries) = fs::read_dir(dir) else {
+                continue;
+            };
+            for subdir in entries.filter_map(|e| e.ok()).map(|e| e.path()).filter(|p| p.is_dir()) {
+                let name = subdir.file_name()
+                    .map(|name| name.to_string_lossy().to_ascii_lowercase())
+                    .unwrap_or_default();
+                match name.as_str() {
+                    "btld" => flash_dirs.push((subdir, FileType::BTLD)),
+                    "swfl" => flash_dirs.push((subdir, FileType::SWFL)),
+                    _ => next_level.push(subdir),
                 }
             }
         }
+        
+        if next_level.is_empty() {
+            break;
+        }
+        current_level = next_level;
     }
     
-    // Scan SWFL files
-    let swfl_path = psdz_path.join("swe").join("swfl");
-    if swfl_path.exists() {
-        if let Ok(entries) = fs::read_dir(swfl_path) {
-            for entry in entries.filter_map(|e| e.ok()) {
-                let path = entry.path();
-                if let Some(file_name) = path.file_name() {
-                    let file_name_str = file_name.to_string_lossy();
-                    // Check if filename contains ".bin" (for files like .bin.159_010_001)
-                    if file_name_str.contains(".bin") {
-                        if let Ok(metadata) = fs::metadata(&path) {
-                            // Convert display name: replace .bin. with _ for better readability
-                            let display_name = file_name_str.replace(".bin.", "_");
-                            
-                            available_files.push(AvailableFile {
-                                path,
-                                file_type: FileType::SWFL,
-                                display_name,
-                                size: metadata.len(),
-                            });
-                        }
+    flash_dirs.sort_by(|a, b| a.0.cmp(&b.0));
+    flash_dirs
+}
+
+/// Scan every `btld`/`swfl` directory below `swe_path`; files are tagged with `source_folder`
+pub fn scan_psdz_files(swe_path: &Path, source_folder: &Path, limits: &ScanLimits) -> PsdzScan {
+    let mut available_files = Vec::new();
+    let mut truncated = false;
+    // Finding the directories may already have used up the time
+    let mut timed_out = limits.expired();
+    
+    let flash_dirs = find_flash_dirs(swe_path, limits);
+    let btld_dir_exists = flash_dirs.iter().any(|(_, file_type)| *file_type == FileType::BTLD);
+    let swfl_dir_exists = flash_dirs.iter().any(|(_, file_type)| *file_type == FileType::SWFL);
+    
+    'dirs: for (dir, file_type) in &flash_dirs {
+        let relative_dir = dir.strip_prefix(swe_path)
+            .unwrap_or(dir)
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let Ok(entries) = fs::read_dir(dir) else {
+            continue;
+        };
+        for entry in entries.filter_map(|e| e.ok()) {
+            if available_files.len() >= limits.max_files {
+                truncated = true;
+                break 'dirs;
+            }
+            if limits.expired() {
+                timed_out = true;
+                break 'dirs;
+            }
+            // Nobody is waiting for a cancelled scan's result
+            if limits.cancelled() {
+                break 'dirs;
+            }
+            let path = entry.path();
+            if let Some(file_name) = path.file_name() {
+                let file_name_str = file_name.to_string_lossy();
+                // Check if filename contains ".bin" (for files like .bin.001_015_000)
+                if file_name_str.contains(".bin") {
+                    if let Ok(metadata) = fs::metadata(&path) {
+                        // Convert display name: replace .bin. with _ for better readability
+                        let display_name = replace_last_in_file_name(file_name, ".bin.", "_")
+                            .map(|name| name.to_string_lossy().to_string())
+                            .unwrap_or_else(|| file_name_str.to_string());
+                        
+                        available_files.push(AvailableFile {
+                            path,
+                            source_folder: source_folder.to_path_buf(),
+                            file_type: file_type.clone(),
+                            display_name,
+                            relative_dir: relative_dir.clone(),
+                            size: metadata.len(),
+                        });
                     }
                 }
             }
         }
     }
     
-    // Sort files by type and name
+    // Sort files by type and name; the same name from different I-steps by directory
     available_files.sort_by(|a, b| {
         match (&a.file_type, &b.file_type) {
             (FileType::BTLD, FileType::SWFL) => std::cmp::Ordering::Less,
             (FileType::SWFL, FileType::BTLD) => std::cmp::Ordering::Greater,
-            _ => a.display_name.cmp(&b.display_name),
+            _ => a.display_name.cmp(&b.display_name).then_with(|| a.relative_dir.cmp(&b.relative_dir)),
         }
     });
     
-    available_files
+    PsdzScan {
+        files: available_files,
+        btld_dir_exists,
+        swfl_dir_exists,
+        truncated,
+        timed_out,
+    }
+}
+
+/// Replace the last occurrence of an ASCII marker in a file name. Works on the encoded
+/// bytes rather than a lossy UTF-8 copy, so non-ASCII and non-Unicode names survive.
+pub fn replace_last_in_file_name(name: &OsStr, from: &str, to: &str) -> Option<OsString> {
+    let bytes = name.as_encoded_bytes();
+    let pos = bytes.windows(from.len()).rposition(|window| window == from.as_bytes())?;
+    
+    let mut replaced = Vec::with_capacity(bytes.len() - from.len() + to.len());
+    replaced.extend_from_slice(&bytes[..pos]);
+    replaced.extend_from_slice(to.as_bytes());
+    replaced.extend_from_slice(&bytes[pos + from.len()..]);
+    // SAFETY: the bytes come from an OsStr, split and joined only at valid UTF-8 substrings
+    Some(unsafe { OsString::from_encoded_bytes_unchecked(replaced) })
 }
 
-pub fn get_xml_path(bin_path: &PathBuf) -> PathBuf {
-    let mut xml_path = bin_path.clone();
-    if let Some(file_name) = xml_path.file_name() {
-        let file_name_str = file_name.to_string_lossy();
-        // Replace .bin with .xml in the filename (handles extended names like .bin.001_015_000)
-        let xml_file_name = file_name_str.replace(".bin", ".xml");
+pub fn get_xml_path(bin_path: &Path) -> PathBuf {
+    let mut xml_path = bin_path.to_path_buf();
+    // Replace the last .bin with .xml in the filename (handles extended names like .bin.001_015_000)
+    if let Some(xml_file_name) = bin_path.file_name().and_then(|name| replace_last_in_file_name(name, ".bin", ".xml")) {
         xml_path.set_file_name(xml_file_name);
     }
     xml_path
 }
 
-pub fn generate_output_filename(swfl1_path: &PathBuf) -> Option<String> {
-    if let Some(file_name) = swfl1_path.file_name() {
-        let file_name_str = file_name.to_string_lossy();
-        
-        // Extract the base name (remove .bin and any extensions)
-        let base_name = if file_name_str.ends_with(".bin") {
-            &file_name_str[..file_name_str.len() - 4]
+/// Auto-generated output name for SWFL1 inputs when no template is configured
+pub const DEFAULT_OUTPUT_NAME_TEMPLATE: &str = "{version}.vr.bin";
+
+/// Placeholders an output name template may contain
+const OUTPUT_NAME_PLACEHOLDERS: [&str; 4] = ["ecu", "version", "date", "name"];
+
+/// Replace each `{placeholder}` in `template` with the value `lookup` gives for it.
+/// Placeholders outside `placeholders`, unbalanced braces and placeholders without a
+/// value are errors; `kind` names the template in them.
+fn expand_template(
+    template: &str,
+    kind: &str,
+    placeholders: &[&str],
+    lookup: &dyn Fn(&str) -> Option<String>
+) -> Result<String> {
+    let mut expanded = String::new();
+    let mut rest = template;
+    while let Some(open) = rest.find(['{', '}']) {
+        if rest[open..].starts_with('}') {
+            return Err(anyhow::anyhow!("Unmatched '}}' in {}", kind));
+        }
+        expanded.push_str(&rest[..open]);
+        let close = rest[open..].find('}')
+            .ok_or_else(|| anyhow::anyhow!("Unclosed '{{' in {}", kind))? + open;
+        let placeholder = &rest[open + 1..close];
+        if !placeholders.contains(&placeholder) {
+            return Err(anyhow::anyhow!("Unknown placeholder {{{}}} in {}, expected one of {}",
+                placeholder, kind, placeholders.iter().map(|name| format!("{{{}}}", name)).collect::<Vec<_>>().join(", ")));
+        }
+        let value = lookup(placeholder)
+            .ok_or_else(|| anyhow::anyhow!("No value for {{{}}} in this file name", placeholder))?;
+        expanded.push_str(&value);
+        rest = &rest[close + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Check that `template` only uses known placeholders and expands to a plain file name
+pub fn validate_output_template(template: &str) -> Result<()> {
+    let sample = expand_template(template, "output name template", &OUTPUT_NAME_PLACEHOLDERS, &|_| Some("x".to_string()))?;
+    if sample.trim().is_empty() {
+        return Err(anyhow::anyhow!("Output name template is empty"));
+    }
+    if sample.contains(['/', '\\']) {
+        return Err(anyhow::anyhow!("Output name template may not contain path separators"));
+    }
+    Ok(())
+}
+
+/// Flashing script template used when none is configured
+pub const DEFAULT_FLASH_SCRIPT_TEMPLATE: &str = "# Flashing recipe for {output} ({count} files), {date}
+# file, start address, length in bytes, leading header bytes without an address
+{file},0x{addr},{size},{skip}";
+
+/// Placeholders with a value per written file; a template line using any of them is
+/// repeated for every file
+const FLASH_FILE_PLACEHOLDERS: [&str; 7] = ["file", "path", "addr", "end", "addr_dec", "size", "skip"];
+
+/// Every placeholder a flashing script template may contain
+const FLASH_SCRIPT_PLACEHOLDERS: [&str; 10] = ["file", "path", "addr", "end", "addr_dec", "size", "skip", "output", "count", "date"];
+
+/// Whether a flashing script template line is repeated per written file
+fn is_per_file_line(line: &str) -> bool {
+    FLASH_FILE_PLACEHOLDERS.iter().any(|name| line.contains(&format!("{{{}}}", name)))
+}
+
+/// Check that `template` only uses known placeholders and has a line listing the files
+pub fn validate_flash_script_template(template: &str) -> Result<()> {
+    for line in template.lines() {
+        expand_template(line, "flashing script template", &FLASH_SCRIPT_PLACEHOLDERS, &|_| Some("x".to_string()))?;
+    }
+    if !template.lines().any(is_per_file_line) {
+        return Err(anyhow::anyhow!("Flashing script template has no line with a per-file placeholder such as {{file}} or {{addr}}"));
+    }
+    Ok(())
+}
+
+/// Seconds since 1970-01-01 UTC
+fn unix_time() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+/// Today's date in UTC as YYYY-MM-DD
+fn current_date() -> String {
+    civil_date((unix_time() / 86_400) as i64)
+}
+
+/// The current time in UTC as YYYY-MM-DD HH:MM:SS
+fn current_timestamp() -> String {
+    let secs = unix_time();
+    format!("{} {:02}:{:02}:{:02}", civil_date((secs / 86_400) as i64), secs / 3600 % 24, secs / 60 % 60, secs % 60)
+}
+
+/// YYYY-MM-DD of the day `days` after 1970-01-01 (Howard Hinnant's algorithm)
+fn civil_date(days: i64) -> String {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Output file name for `swfl1_path` from `template`. `{version}` is the part after the
+/// last underscore, `{ecu}` the id after the `swfl_` prefix, `{name}` the file name as
+/// shown in the browser and `{date}` today's date. `None` if a placeholder has no value
+/// or the name is too long.
+pub fn generate_output_filename(swfl1_path: &Path, template: &str) -> Option<String> {
+    let file_name = swfl1_path.file_name()?;
+    let file_name_str = file_name.to_string_lossy();
+    
+    // Extract the base name (remove .bin and any extensions)
+    let base_name = file_name_str.strip_suffix(".bin").unwrap_or(&file_name_str);
+    
+    // Find the last underscore to get the version part
+    let version_part = base_name.rfind('_')
+        .map(|last_underscore_pos| &base_name[last_underscore_pos + 1..])
+        .filter(|version| !version.is_empty());
+    let ecu_id = file_name_str.split(['_', '.']).nth(1).filter(|id| !id.is_empty());
+    let display_name = replace_last_in_file_name(file_name, ".bin.", "_")
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| base_name.to_string());
+    
+    let output_name = expand_template(template, "output name template", &OUTPUT_NAME_PLACEHOLDERS, &|placeholder| match placeholder {
+        "version" => version_part.map(str::to_string),
+        "ecu" => ecu_id.map(str::to_string),
+        "date" => Some(current_date()),
+        "name" => Some(display_name.trim_end_matches(".bin").to_string()),
+        _ => None,
+    }).ok()?;
+    if output_name.len() > MAX_FILE_NAME_LEN {
+        return None;
+    }
+    Some(output_name)
+}
+
+/// Highest numeric suffix tried before an existing output name is reused
+const MAX_OUTPUT_NAME_SUFFIX: usize = 999;
+
+/// `path` if nothing exists there yet, otherwise the first free variant with a numeric
+/// suffix: `name.extracted.1`, or `name.vr.1.bin` so `.bin` outputs keep their extension.
+/// Falls back to `path` itself if every suffix is taken.
+pub fn unused_output_path(path: &Path) -> PathBuf {
+    if !path.exists() {
+        return path.to_path_buf();
+    }
+    let Some(file_name) = path.file_name() else {
+        return path.to_path_buf();
+    };
+    
+    let keeps_bin_extension = file_name.as_encoded_bytes().ends_with(b".bin");
+    for suffix in 1..=MAX_OUTPUT_NAME_SUFFIX {
+        let candidate_name = if keeps_bin_extension {
+            replace_last_in_file_name(file_name, ".bin", &format!(".{}.bin", suffix))
         } else {
-            &file_name_str
+            let mut name = file_name.to_os_string();
+            name.push(format!(".{}", suffix));
+            Some(name)
         };
-        
-        // Find the last underscore to get the version part
-        if let Some(last_underscore_pos) = base_name.rfind('_') {
-            let version_part = &base_name[last_underscore_pos + 1..];
-            // Create the new filename: version_part.vr.bin
-            return Some(format!("{}.vr.bin", version_part));
+        let Some(candidate_name) = candidate_name else {
+            break;
+        };
+        let candidate = path.with_file_name(candidate_name);
+        if !candidate.exists() {
+            return candidate;
         }
     }
-    None
+    path.to_path_buf()
 }
 
 pub fn get_program_directory() -> PathBuf {
@@ -119,180 +412,2186 @@ pub fn get_program_directory() -> PathBuf {
     std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."))
 }
 
-pub fn decompress_ucl(ucl_library: &UclLibrary, data: &[u8]) -> Result<Vec<u8>> {
+/// Show `path` in the platform file manager, selecting it where supported
+pub fn reveal_in_file_manager(path: &Path) -> std::io::Result<()> {
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::process::CommandExt;
+        std::process::Command::new("explorer")
+            .raw_arg(format!("/select,\"{}\"", path.display()))
+            .spawn()?;
+    }
+    
+    #[cfg(target_os = "macos")]
+    {
+        std::process::Command::new("open").arg("-R").arg(path).spawn()?;
+    }
+    
+    #[cfg(all(unix, not(target_os = "macos")))]
+    {
+        // xdg-open has no way to select a file, so open the containing folder
+        let folder = if path.is_dir() { path } else { path.parent().unwrap_or(path) };
+        std::process::Command::new("xdg-open").arg(folder).spawn()?;
+    }
+    
+    Ok(())
+}
+
+/// Decompress a UCL stream, returning the data and the number of input bytes consumed.
+/// With the expected output size (the XML target size) the buffer is allocated once at
+/// that size, otherwise it is grown until the data fits.
+pub fn decompress_ucl(ucl_library: &UclLibrary, data: &[u8], expected_len: Option<usize>) -> Result<(Vec<u8>, usize)> {
     if data.is_empty() {
         return Err(anyhow::anyhow!("UCL decompression failed: input data is empty"));
     }
-    ucl_library.decompress(data).map_err(|e| anyhow::anyhow!("UCL decompression failed: {}", e))
+    let result = match expected_len.filter(|&len| len > 0) {
+        Some(len) => ucl_library.decompress_into(data, len),
+        None => ucl_library.decompress(data),
+    };
+    result.map_err(|e| anyhow::anyhow!("UCL decompression failed: {}", e))
+}
+
+/// Decompress only the first compressed segment found in the given (bin, XML) pairs and compare
+/// its size to the XML target size. A large mismatch or a failure is a strong sign the
+/// library exports a different NRV2 variant than the data was packed with.
+pub fn check_ucl_variant(files: &[(&PathBuf, PathBuf)], ucl_library: &UclLibrary, schema: &XmlSchema) -> Option<String> {
+    for (bin_path, xml_path) in files {
+        let Ok(segments) = parse_xml(xml_path, schema) else {
+            continue;
+        };
+        let Some(segment) = segments.iter().find(|s| s.is_compressed) else {
+            continue;
+        };
+        
+        let target_size = segment.target_len() as usize;
+        let mut buffer = Vec::new();
+        // Grown as data arrives, so a bogus range can't allocate its declared size
+        let read_result = fs::File::open(bin_path).and_then(|mut file| {
+            file.seek(std::io::SeekFrom::Start(segment.source_start_addr as u64))?;
+            file.take(segment.source_len()).read_to_end(&mut buffer)
+        });
+        if read_result.is_err() || buffer.len() as u64 != segment.source_len() {
+            continue;
+        }
+        
+        let file_name = bin_path.file_name().unwrap_or_default().to_string_lossy();
+        return match decompress_ucl(ucl_library, &buffer, None) {
+            Ok((decompressed, _)) => {
+                let ratio = decompressed.len() as f64 / target_size as f64;
+                if (0.9..=1.1).contains(&ratio) {
+                    None
+                } else {
+                    Some(format!(
+                        "Warning: First compressed segment of {} decompressed to {} bytes but the XML expects {}; the loaded UCL library ({}) may be the wrong NRV2 variant",
+                        file_name, decompressed.len(), target_size, ucl_library.decompress_symbol()))
+                }
+            }
+            Err(e) => Some(format!(
+                "Warning: First compressed segment of {} failed to decompress ({}); the loaded UCL library ({}) may be the wrong NRV2 variant",
+                file_name, e, ucl_library.decompress_symbol())),
+        };
+    }
+    
+    None
+}
+
+/// Reader wrapper counting calls that reach the underlying file
+struct CountingReader<R> {
+    inner: R,
+    read_calls: usize,
+}
+
+impl<R> CountingReader<R> {
+    fn new(inner: R) -> Self {
+        Self { inner, read_calls: 0 }
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.read_calls += 1;
+        self.inner.read(buf)
+    }
+}
+
+impl<R: Seek> Seek for CountingReader<R> {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+/// Parse a segment index selection such as "2-5,8" into the selected indices.
+/// An empty selection parses to an empty set, which callers treat as "all segments".
+pub fn parse_segment_indices(spec: &str) -> Result<BTreeSet<usize>> {
+    let mut indices = BTreeSet::new();
+    for part in spec.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+        let parse_index = |text: &str| text.trim().parse::<usize>()
+            .map_err(|_| anyhow::anyhow!("'{}' is not a segment index", text.trim()));
+        match part.split_once('-') {
+            Some((start, end)) => {
+                let (start, end) = (parse_index(start)?, parse_index(end)?);
+                if start > end {
+                    return Err(anyhow::anyhow!("Range '{}' runs backwards", part));
+                }
+                indices.extend(start..=end);
+            }
+            None => {
+                indices.insert(parse_index(part)?);
+            }
+        }
+    }
+    Ok(indices)
+}
+
+/// Format indices back into the compact "2-5,8" form accepted by `parse_segment_indices`
+pub fn format_segment_indices(indices: &BTreeSet<usize>) -> String {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for &index in indices {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == index => *end = index,
+            _ => ranges.push((index, index)),
+        }
+    }
+    ranges.iter()
+        .map(|&(start, end)| if start == end { start.to_string() } else { format!("{}-{}", start, end) })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Parse a pasted file list with one `role:path` line per file into slot assignments,
+/// plus a message for every line that couldn't be used. Roles are btld, swfl1 and swfl2;
+/// plain swfl takes the next free SWFL slot. A tab or comma also separates the role,
+/// so columns copied from a spreadsheet work. Blank lines and `#` comments are skipped.
+pub fn parse_file_list(text: &str) -> (Vec<(String, PathBuf)>, Vec<String>) {
+    let mut assignments: Vec<(String, PathBuf)> = Vec::new();
+    let mut problems = Vec::new();
+    
+    for (line_index, line) in text.lines().enumerate() {
+        let line_number = line_index + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        
+        let Some((role, path)) = line.split_once([':', '\t', ',']) else {
+            problems.push(format!("Line {}: expected role:path, e.g. btld:/path/to/file.bin", line_number));
+            continue;
+        };
+        let role = role.trim().to_lowercase();
+        let is_taken = |slot: &str| assignments.iter().any(|(assigned, _)| assigned == slot);
+        let slot = match role.as_str() {
+            "swfl" => ["swfl1", "swfl2"].into_iter().find(|slot| !is_taken(slot)),
+            role @ ("btld" | "swfl1" | "swfl2") => Some(role).filter(|slot| !is_taken(slot)),
+            other => {
+                problems.push(format!("Line {}: unknown role '{}', expected btld, swfl, swfl1 or swfl2", line_number, other));
+                continue;
+            }
+        };
+        let Some(slot) = slot else {
+            problems.push(format!("Line {}: no free slot left for role '{}'", line_number, role));
+            continue;
+        };
+        
+        let path = PathBuf::from(path.trim().trim_matches('"'));
+        if !path.is_file() {
+            problems.push(format!("Line {}: file not found: {}", line_number, path.display()));
+            continue;
+        }
+        assignments.push((slot.to_string(), path));
+    }
+    (assignments, problems)
+}
+
+/// Address ranges (inclusive) covered by more than one of the inclusive `ranges`, merged
+/// and in address order. Inverted ranges are ignored.
+pub fn find_overlaps(ranges: &[(u32, u32)]) -> Vec<(u32, u32)> {
+    let mut sorted: Vec<(u32, u32)> = ranges.iter()
+        .copied()
+        .filter(|(start, end)| end >= start)
+        .collect();
+    sorted.sort();
+    
+    let mut overlaps: Vec<(u32, u32)> = Vec::new();
+    // Highest address covered so far
+    let mut covered_end: Option<u32> = None;
+    for (start, end) in sorted {
+        if let Some(covered) = covered_end.filter(|&covered| start <= covered) {
+            let overlap_end = end.min(covered);
+            match overlaps.last_mut() {
+                Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(overlap_end),
+                _ => overlaps.push((start, overlap_end)),
+            }
+        }
+        covered_end = Some(covered_end.map_or(end, |covered| covered.max(end)));
+    }
+    overlaps
+}
+
+/// Check a file's XML and segment layout without reading or writing any data
+pub fn validate_file(file: &AvailableFile, schema: &XmlSchema) -> FileValidation {
+    let mut validation = FileValidation {
+        path: file.path.clone(),
+        display_name: file.display_name.clone(),
+        file_type: file.file_type.clone(),
+        status: ValidationStatus::Ok,
+        segment_count: 0,
+        detail: String::new(),
+    };
+    
+    let xml_path = get_xml_path(&file.path);
+    if !xml_path.is_file() {
+        validation.status = ValidationStatus::MissingXml;
+        validation.detail = format!("{} not found", xml_path.file_name().unwrap_or_default().to_string_lossy());
+        return validation;
+    }
+    let segments = match parse_xml(&xml_path, schema) {
+        Ok(segments) => segments,
+        Err(e) => {
+            validation.status = ValidationStatus::InvalidXml;
+            validation.detail = e.to_string();
+            return validation;
+        }
+    };
+    validation.segment_count = segments.len();
+    
+    let mut issues = Vec::new();
+    // parse_xml rejects ranges that end before they start
+    for (i, segment) in segments.iter().enumerate() {
+        if segment.source_end_addr as u64 >= file.size {
+            issues.push(format!("segment {} source ends at 0x{:08X}, past the end of the {} byte file", i, segment.source_end_addr, file.size));
+        }
+        let source_size = segment.source_len();
+        let target_size = segment.target_len();
+        if !segment.is_compressed && source_size != target_size {
+            issues.push(format!("uncompressed segment {} is {} bytes in the file but {} bytes in the target", i, source_size, target_size));
+        }
+    }
+    
+    // Overlaps within one file are legal for combining but unusual in a single XML
+    let target_ranges: Vec<(u32, u32)> = segments.iter()
+        .map(|segment| (segment.target_start_addr, segment.target_end_addr))
+        .collect();
+    for (start, end) in find_overlaps(&target_ranges) {
+        issues.push(format!("target ranges overlap at 0x{:08X}-0x{:08X}", start, end));
+    }
+    
+    if !issues.is_empty() {
+        validation.status = ValidationStatus::Suspicious;
+        validation.detail = issues.join("; ");
+    }
+    validation
 }
 
+/// Data of one extracted segment
+#[derive(Debug)]
+pub enum SegmentData {
+    /// Extracted into memory
+    Loaded(Vec<u8>),
+    /// Large uncompressed range left in the input file and copied straight to the
+    /// output when it is written, so it is never buffered on its own
+    Streamed { path: PathBuf, offset: u64, len: usize },
+}
+
+impl SegmentData {
+    pub fn len(&self) -> usize {
+        match self {
+            SegmentData::Loaded(data) => data.len(),
+            SegmentData::Streamed { len, .. } => *len,
+        }
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    
+    fn truncate(&mut self, new_len: usize) {
+        match self {
+            SegmentData::Loaded(data) => data.truncate(new_len),
+            SegmentData::Streamed { len, .. } => *len = (*len).min(new_len),
+        }
+    }
+    
+    fn open_streamed(path: &Path, offset: u64) -> Result<fs::File> {
+        let mut file = fs::File::open(path)
+            .context(format!("Failed to open input file: {}", path.display()))?;
+        file.seek(std::io::SeekFrom::Start(offset))?;
+        Ok(file)
+    }
+    
+    /// Fill `dest`, which must be exactly `len()` bytes long
+    fn read_into(&self, dest: &mut [u8]) -> Result<()> {
+        match self {
+            SegmentData::Loaded(data) => dest.copy_from_slice(data),
+            SegmentData::Streamed { path, offset, .. } => {
+                Self::open_streamed(path, *offset)?.read_exact(dest)
+                    .context(format!("Failed to read segment data from {}", path.display()))?;
+            }
+        }
+        Ok(())
+    }
+    
+    fn write_to(&self, writer: &mut dyn Write) -> Result<()> {
+        match self {
+            SegmentData::Loaded(data) => writer.write_all(data)?,
+            SegmentData::Streamed { path, offset, len } => {
+                let mut source = Self::open_streamed(path, *offset)?.take(*len as u64);
+                std::io::copy(&mut source, writer)
+                    .context(format!("Failed to copy segment data from {}", path.display()))?;
+            }
+        }
+        Ok(())
+    }
+    
+    /// The data in memory, reading a streamed segment in full
+    pub fn into_bytes(self) -> Result<Vec<u8>> {
+        match self {
+            SegmentData::Loaded(data) => Ok(data),
+            streamed => {
+                let mut data = vec![0u8; streamed.len()];
+                streamed.read_into(&mut data)?;
+                Ok(data)
+            }
+        }
+    }
+}
+
+/// Extract the segments described by `xml_path` from `bin_path`. With a
+/// `segment_filter`, only the segments at those indices are extracted. `options`
+/// supplies safe mode, the size mismatch policy and the streaming threshold. With
+/// `extract_compressed`, compressed segments are returned still packed, at the address
+/// `raw_segment_address` selects. Each segment comes with the XML entry it was read from.
+/// `progress_callback` receives the index of the current segment, the number of segments
+/// and the fraction of source bytes processed.
 pub fn process_single_file(
     bin_path: &PathBuf, 
-    xml_path: &PathBuf, 
-    ucl_library: &UclLibrary
-) -> Result<Vec<(u32, Vec<u8>)>> {
+    xml_path: &Path, 
+    ucl_library: &UclLibrary,
+    options: &OutputOptions,
+    segment_filter: Option<&BTreeSet<usize>>,
+    status_callback: &mut dyn FnMut(&str),
+    progress_callback: &mut dyn FnMut(usize, usize, f32)
+) -> Result<Vec<(u32, SegmentData, FlashSegment)>> {
+    // The usual first-run mistake is copying a bin file out of psdzdata without its XML
+    if !xml_path.exists() {
+        return Err(anyhow::anyhow!("Segment map not found: {}. BMW PSDZ bin files need the XML file of the same name next to them; copy it from the same psdzdata folder or name it in a project file.",
+            xml_path.display()));
+    }
+    
     // Parse XML
-    let segments = parse_xml(xml_path)?;
+    let segments = parse_xml(xml_path, &options.xml_schema)?;
+    
+    if let Some(filter) = segment_filter {
+        if let Some(&missing) = filter.iter().find(|&&index| index >= segments.len()) {
+            status_callback(&format!("Warning: Selected segment {} doesn't exist, the file has {} segments", missing, segments.len()));
+        }
+    }
+    let is_selected = |index: usize| segment_filter.map_or(true, |filter| filter.contains(&index));
+    
+    let selected_count = (0..segments.len()).filter(|&i| is_selected(i)).count();
+    // Progress is measured in source bytes, which is what dominates the time per segment
+    let total_source_bytes: u64 = segments.iter()
+        .enumerate()
+        .filter(|(i, _)| is_selected(*i))
+        .map(|(_, s)| s.source_len())
+        .sum();
+    let mut processed_source_bytes: u64 = 0;
     
-    // Read and process binary file
-    let mut input_file = fs::File::open(bin_path)
+    // Read and process binary file. Buffered so files with many small segments don't
+    // cost a syscall per segment; forward skips reuse the buffer where possible.
+    let input_file = fs::File::open(bin_path)
         .context(format!("Failed to open input file: {}", bin_path.display()))?;
+    let input_len = input_file.metadata()?.len();
+    let mut input_file = BufReader::new(CountingReader::new(input_file));
+    let mut position: u64 = 0;
     
+    let safe_mode = options.safe_mode;
     let mut buff_list = Vec::new();
     
-    for (_i, segment) in segments.iter().enumerate() {
-        let source_size = segment.source_end_addr - segment.source_start_addr + 1;
-        let target_size = segment.target_end_addr - segment.target_start_addr + 1;
-        
-        let mut buffer = vec![0u8; source_size as usize];
-        input_file.seek(std::io::SeekFrom::Start(segment.source_start_addr as u64))?;
-        input_file.read_exact(&mut buffer)?;
-        let output_buffer = if segment.is_compressed {
-            match decompress_ucl(ucl_library, &buffer) {
-                Ok(decompressed) => decompressed,
-                Err(_) => {
-                    eprintln!("Warning: UCL decompression failed. Using raw data instead.");
-                    buffer
-                }
-            }
+    for (i, segment) in segments.iter().enumerate() {
+        if !is_selected(i) {
+            continue;
+        }
+        if options.cancelled() {
+            return Err(anyhow::anyhow!("Extraction cancelled"));
+        }
+        let source_size = segment.source_len();
+        let target_size = segment.target_len();
+        // Packed segments are kept as they are, so their declared size is the source size
+        let keep_packed = segment.is_compressed && options.extract_compressed;
+        let expected_size = if keep_packed { source_size } else { target_size };
+        let address = match options.raw_segment_address {
+            RawSegmentAddress::Source if options.extract_compressed => segment.source_start_addr,
+            _ => segment.target_start_addr,
+        };
+        
+        progress_callback(i, segments.len(), processed_source_bytes as f32 / total_source_bytes.max(1) as f32);
+        processed_source_bytes += source_size;
+        
+        if safe_mode && source_size.max(target_size) > SAFE_MODE_MAX_SEGMENT_SIZE as u64 {
+            status_callback(&format!("Warning: Safe mode rejected segment {} - declared size {} bytes exceeds the {} byte limit",
+                i, source_size.max(target_size), SAFE_MODE_MAX_SEGMENT_SIZE));
+            continue;
+        }
+        
+        let source_start = segment.source_start_addr as u64;
+        // Compressed segments need the whole buffer for the FFI; plain ones can be copied later
+        let stream = (!segment.is_compressed || keep_packed)
+            && options.streaming_threshold.is_some_and(|threshold| source_size > threshold as u64);
+        // Checked before anything is allocated for the range
+        if source_start + source_size > input_len {
+            return Err(anyhow::anyhow!("Segment {} source range 0x{:08X}-0x{:08X} extends past the end of the file",
+                i, segment.source_start_addr, segment.source_end_addr));
+        }
+        let mut output_data = if stream {
+            SegmentData::Streamed { path: bin_path.clone(), offset: source_start, len: source_size as usize }
         } else {
-            buffer
+            let mut buffer = vec![0u8; source_size as usize];
+            if source_start >= position {
+                input_file.seek_relative((source_start - position) as i64)?;
+            } else {
+                // Out-of-order source range, seek explicitly
+                input_file.seek(std::io::SeekFrom::Start(source_start))?;
+            }
+            input_file.read_exact(&mut buffer)?;
+            position = source_start + buffer.len() as u64;
+            SegmentData::Loaded(if keep_packed {
+                buffer
+            } else if segment.is_compressed && safe_mode {
+                // No raw fallback here, a segment that doesn't decode cleanly is dropped
+                match ucl_library.decompress_with_timeout(&buffer, target_size as usize, SAFE_MODE_DECOMPRESS_TIMEOUT) {
+                    Ok(decompressed) => decompressed,
+                    Err(e) => {
+                        status_callback(&format!("Warning: Safe mode rejected segment {} - {}", i, e));
+                        continue;
+                    }
+                }
+            } else if segment.is_compressed {
+                match decompress_ucl(ucl_library, &buffer, Some(target_size as usize)) {
+                    Ok((decompressed, consumed)) => {
+                        if consumed < buffer.len() {
+                            status_callback(&format!(
+                                "Warning: Segment {} has {} trailing bytes after the compressed stream (source 0x{:08X}-0x{:08X}); the XML source range may be wrong",
+                                i, buffer.len() - consumed, segment.source_start_addr, segment.source_end_addr));
+                        }
+                        decompressed
+                    }
+                    Err(e) => {
+                        status_callback(&format!("Warning: UCL decompression failed for segment {} ({}). Using raw data instead.", i, e));
+                        buffer
+                    }
+                }
+            } else {
+                buffer
+            })
         };
         
         // More lenient size checking when using fallback raw data
-        let size_ratio = output_buffer.len() as f64 / target_size as f64;
+        let size_ratio = output_data.len() as f64 / expected_size as f64;
         if segment.is_compressed && size_ratio > 0.8 && size_ratio < 1.2 {
             // If we're using raw data for a compressed segment and the size is close to target, 
             // this suggests decompression failed and we're using raw data
-        } else if size_ratio < 0.01 || size_ratio > 50.0 {
+        } else if !(0.01..=50.0).contains(&size_ratio) {
             // Only reject if the size mismatch is extreme
-            return Err(anyhow::anyhow!(
+            return Err(stage(FailureKind::Decompress)(anyhow::anyhow!(
                 "Extreme size mismatch for segment - expected {} bytes, got {} bytes (ratio: {:.2})", 
-                target_size, output_buffer.len(), size_ratio
-            ));
+                expected_size, output_data.len(), size_ratio
+            )));
+        }
+        
+        if output_data.len() != expected_size as usize {
+            match options.size_policy {
+                SizeMismatchPolicy::Reject => {
+                    return Err(stage(FailureKind::Decompress)(anyhow::anyhow!("Segment {} is {} bytes but the XML declares {} bytes",
+                        i, output_data.len(), expected_size)));
+                }
+                SizeMismatchPolicy::TruncateToTarget if output_data.len() > expected_size as usize => {
+                    status_callback(&format!("Warning: Truncated segment {} from {} to the declared {} bytes",
+                        i, output_data.len(), expected_size));
+                    output_data.truncate(expected_size as usize);
+                }
+                SizeMismatchPolicy::TruncateToTarget => {
+                    status_callback(&format!("Warning: Segment {} is {} bytes, shorter than the declared {} bytes; kept as is",
+                        i, output_data.len(), expected_size));
+                }
+                SizeMismatchPolicy::WarnAndKeep => {
+                    status_callback(&format!("Warning: Size mismatch for segment {} - expected {} bytes, got {}", 
+                        i, expected_size, output_data.len()));
+                }
+            }
         }
         
-        if output_buffer.len() != target_size as usize {
-            eprintln!("Warning: Size mismatch for segment - expected {} bytes, got {}", 
-                target_size, output_buffer.len());
+        // Everything downstream computes end addresses in 32 bits
+        if address as u64 + output_data.len() as u64 > ADDRESS_SPACE_SIZE {
+            return Err(stage(FailureKind::Validation)(anyhow::anyhow!("Segment {} at 0x{:08X} is {} bytes and would end past the 32-bit address space",
+                i, address, output_data.len())));
         }
         
-        buff_list.push((segment.target_start_addr, output_buffer));
+        buff_list.push((address, output_data, segment.clone()));
     }
     
+    progress_callback(segments.len().saturating_sub(1), segments.len(), 1.0);
+    status_callback(&format!("Read {} segments from {} using {} read calls",
+        selected_count, bin_path.file_name().unwrap_or_default().to_string_lossy(), input_file.get_ref().read_calls));
+    
     Ok(buff_list)
 }
 
-pub fn process_files(
-    btld_file: Option<&PathBuf>,
-    swfl1_file: Option<&PathBuf>,
-    swfl2_file: Option<&PathBuf>,
-    output_file: &PathBuf,
-    desired_size_mb: f32,
-    ucl_library: &UclLibrary,
-    status_callback: &mut dyn FnMut(&str)
-) -> Result<()> {
-    let mut all_segments = Vec::new();
+/// Whether `a` and `b` name the same file, comparing resolved paths where possible so
+/// `./x.bin` and `x.bin` match
+pub fn is_same_file(a: &Path, b: &Path) -> bool {
+    let resolve = |path: &Path| path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    resolve(a) == resolve(b)
+}
+
+/// Refuse an output path that is one of the inputs or their segment maps, so a run can't
+/// overwrite its own source files
+pub fn check_output_collision(output_file: &Path, inputs: &[(&str, PathBuf)], options: &OutputOptions) -> Result<()> {
+    for (label, bin_path) in inputs {
+        if is_same_file(output_file, bin_path) {
+            return Err(anyhow::anyhow!("The output path is the {} input file {}; choose another output so it isn't overwritten",
+                label, bin_path.display()));
+        }
+        let xml_path = options.xml_paths.get(*label).cloned().unwrap_or_else(|| get_xml_path(bin_path));
+        if is_same_file(output_file, &xml_path) {
+            return Err(anyhow::anyhow!("The output path is the {} segment map {}; choose another output so it isn't overwritten",
+                label, xml_path.display()));
+        }
+    }
+    Ok(())
+}
+
+/// Dry run of `process_files`: the segments it would include, where they would go, the
+/// gaps and overlaps between them and how large the image would be. Only the segment
+/// maps are read; no segment data is read or decompressed and nothing is written, so
+/// sizes are the ones the XML declares.
+pub fn process_files_preview(inputs: &[(&str, PathBuf)], options: &OutputOptions) -> RunPreview {
+    let mut preview = RunPreview::default();
+    // Included segments with their file's position in the combine order
+    let mut included = Vec::new();
     
-    // Process BTLD file
-    if let Some(btld_path) = btld_file {
-        let xml_path = get_xml_path(btld_path);
-        status_callback(&format!("Processing BTLD file: {}", btld_path.file_name().unwrap_or_default().to_string_lossy()));
-        
-        match process_single_file(btld_path, &xml_path, ucl_library) {
-            Ok(segments) => {
-                let segment_count = segments.len();
-                all_segments.extend(segments);
-                status_callback(&format!("BTLD: Found {} segments", segment_count));
+    for (priority, (label, bin_path)) in inputs.iter().enumerate() {
+        let xml_path = options.xml_paths.get(*label).cloned().unwrap_or_else(|| get_xml_path(bin_path));
+        let segments = match parse_xml(&xml_path, &options.xml_schema) {
+            Ok(segments) => segments,
+            Err(e) => {
+                preview.problems.push(format!("Warning: {} file would be skipped: {}", label, e));
+                continue;
             }
+        };
+        let selection = match options.segment_selection.get(*label).map(|spec| parse_segment_indices(spec)).transpose() {
+            Ok(selection) => selection.filter(|indices| !indices.is_empty()),
             Err(e) => {
-                status_callback(&format!("Warning: Failed to process BTLD file: {}", e));
+                preview.problems.push(format!("Error: Invalid segment selection for {}: {}", label, e));
+                continue;
+            }
+        };
+        
+        for (index, segment) in segments.into_iter().enumerate() {
+            if selection.as_ref().is_some_and(|indices| !indices.contains(&index)) {
+                continue;
             }
+            // Same address and size choices as process_single_file
+            let keep_packed = segment.is_compressed && options.extract_compressed;
+            let len = if keep_packed { segment.source_len() } else { segment.target_len() };
+            let addr = match options.raw_segment_address {
+                RawSegmentAddress::Source if options.extract_compressed => segment.source_start_addr,
+                _ => segment.target_start_addr,
+            };
+            included.push((priority, PreviewSegment {
+                label: label.to_string(),
+                index,
+                segment,
+                addr,
+                len,
+            }));
         }
     }
     
-    // Process SWFL1 file
-    if let Some(swfl1_path) = swfl1_file {
-        let xml_path = get_xml_path(swfl1_path);
-        status_callback(&format!("Processing SWFL1 file: {}", swfl1_path.file_name().unwrap_or_default().to_string_lossy()));
-        
-        match process_single_file(swfl1_path, &xml_path, ucl_library) {
-            Ok(segments) => {
-                let segment_count = segments.len();
-                all_segments.extend(segments);
-                status_callback(&format!("SWFL1: Found {} segments", segment_count));
-            }
+    // Same canonical order as process_files
+    included.sort_by_key(|(priority, segment)| (segment.addr, *priority));
+    preview.segments = included.into_iter().map(|(_, segment)| segment).collect();
+    
+    let ranges: Vec<(u32, u32)> = preview.segments.iter()
+        .map(|segment| (segment.addr, (segment.addr as u64 + segment.len - 1).min(u32::MAX as u64) as u32))
+        .collect();
+    preview.gaps = find_gaps(&ranges);
+    preview.overlaps = find_overlaps(&ranges);
+    if !preview.overlaps.is_empty() && !options.allow_overlap {
+        preview.problems.push(format!("Error: Segments overlap in {} places and overlaps are not allowed", preview.overlaps.len()));
+    }
+    
+    if options.layout == OutputLayout::CombinedImage {
+        let span = ranges.first().zip(ranges.iter().map(|&(_, end)| end).max())
+            .map(|(&(start, _), end)| (end - start) as u64 + 1);
+        // A base image or reference fixes the length, like in process_files
+        let fixed_len = |path: &PathBuf, what: &str, problems: &mut Vec<String>| match fs::metadata(path) {
+            Ok(metadata) => Some(metadata.len()),
             Err(e) => {
-                status_callback(&format!("Warning: Failed to process SWFL1 file: {}", e));
+                problems.push(format!("Error: Failed to read {}: {} ({})", what, path.display(), e));
+                None
             }
+        };
+        let natural = match (&options.base_image, &options.reference_file) {
+            (Some(path), _) => fixed_len(path, "base image", &mut preview.problems),
+            (None, Some(path)) => fixed_len(path, "reference file", &mut preview.problems),
+            (None, None) => span,
+        };
+        let address_based = options.format != OutputFormat::RawBinary;
+        preview.image_size = natural.map(|natural| match options.pad_mode {
+            _ if options.reference_file.is_some() || address_based => natural,
+            PadMode::Natural => natural,
+            PadMode::DesiredSize => natural.max((options.desired_size_mb * 1024.0 * 1024.0) as u64),
+            PadMode::NextPowerOfTwo => natural.next_power_of_two(),
+        });
+        if options.base_image.is_none() && options.reference_file.is_none() && span.is_some_and(|span| span > MAX_OUTPUT_SIZE as u64) {
+            preview.problems.push(format!("Error: Output buffer size too large: {} bytes (max: {} bytes)",
+                span.unwrap_or_default(), MAX_OUTPUT_SIZE));
         }
-    }
-    
-    // Process SWFL2 file
-    if let Some(swfl2_path) = swfl2_file {
-        let xml_path = get_xml_path(swfl2_path);
-        status_callback(&format!("Processing SWFL2 file: {}", swfl2_path.file_name().unwrap_or_default().to_string_lossy()));
-        
-        match process_single_file(swfl2_path, &xml_path, ucl_library) {
-            Ok(segments) => {
-                let segment_count = segments.len();
-                all_segments.extend(segments);
-                status_callback(&format!("SWFL2: Found {} segments", segment_count));
+        if let (Some(_), Some(base_image_len)) = (&options.base_image, natural) {
+            if base_image_len > MAX_OUTPUT_SIZE as u64 {
+                preview.problems.push(format!("Error: Base image too large: {} bytes (max: {} bytes)", base_image_len, MAX_OUTPUT_SIZE));
             }
-            Err(e) => {
-                status_callback(&format!("Warning: Failed to process SWFL2 file: {}", e));
+        }
+        if let (None, Some(_), Some(reference_len)) = (&options.base_image, &options.reference_file, natural) {
+            if reference_len > MAX_OUTPUT_SIZE as u64 {
+                preview.problems.push(format!("Error: Reference file too large: {} bytes (max: {} bytes)", reference_len, MAX_OUTPUT_SIZE));
+            }
+            let base_addr = options.reference_file_addr.or(ranges.first().map(|&(start, _)| start)).unwrap_or(0);
+            let outside = ranges.iter()
+                .filter(|&&(start, end)| start < base_addr || (end - base_addr) as u64 >= reference_len)
+                .count();
+            if outside > 0 {
+                preview.problems.push(format!("Error: {} segments fall outside the {} byte reference file", outside, reference_len));
             }
         }
     }
     
-    if all_segments.is_empty() {
-        return Err(anyhow::anyhow!("No valid files to process"));
+    if preview.segments.is_empty() {
+        preview.problems.push("Error: No valid files to process".to_string());
+    }
+    preview
+}
+
+/// A written output file as listed in a flashing script
+struct FlashArtifact {
+    path: PathBuf,
+    /// Address of the first byte after `skip`
+    addr: u32,
+    /// Bytes that have an address
+    size: u64,
+    /// Leading header bytes without an address
+    skip: u64,
+}
+
+/// A processed segment along with the input it came from
+struct CombinedSegment {
+    /// Position of the source file in the combine order; higher wins overlaps
+    priority: usize,
+    source: String,
+    target_addr: u32,
+    data: SegmentData,
+    /// The XML entry the data was extracted from
+    segment: FlashSegment,
+}
+
+impl CombinedSegment {
+    /// Last address the segment covers, its start address if it is empty.
+    /// `process_single_file` rejects segments that would end past the 32-bit space.
+    fn end_addr(&self) -> u32 {
+        self.target_addr.saturating_add((self.data.len() as u64).saturating_sub(1).min(u32::MAX as u64) as u32)
+    }
+}
+
+/// An error marked with the stage it happened in, shown as the error it wraps
+#[derive(Debug)]
+struct StageError {
+    kind: FailureKind,
+    error: anyhow::Error,
+}
+
+impl std::fmt::Display for StageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.error)
+    }
+}
+
+impl std::error::Error for StageError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.error.source()
+    }
+}
+
+/// Mark an error with the stage it happened in, for `map_err`
+fn stage(kind: FailureKind) -> impl FnOnce(anyhow::Error) -> anyhow::Error {
+    move |error| StageError { kind, error }.into()
+}
+
+/// The stage `error` was marked with, if any
+fn marked_stage(error: &anyhow::Error) -> Option<FailureKind> {
+    error.chain()
+        .find_map(|cause| cause.downcast_ref::<StageError>())
+        .map(|stage_error| stage_error.kind)
+}
+
+/// The stage a run failed in. Errors that aren't marked were found while checking the
+/// run, before anything was written.
+pub fn failure_kind(error: &anyhow::Error) -> FailureKind {
+    marked_stage(error).unwrap_or(FailureKind::Validation)
+}
+
+/// Combine the given files into one output. `inputs` pairs a display label with each
+/// file and is processed in order, so later files overwrite earlier ones where they overlap.
+/// `progress_callback` is told the file and segment being processed and how far the
+/// run is, with copying segments into the output counted as one more file.
+/// Inputs that fail are reported with a warning and left out; the returned summary
+/// lists them next to the checksum of a combined image.
+pub fn process_files(
+    inputs: &[(&str, PathBuf)],
+    output_file: &Path,
+    options: &OutputOptions,
+    ucl_library: &UclLibrary,
+    status_callback: &mut dyn FnMut(&str),
+    progress_callback: &mut dyn FnMut(&ExtractionProgress)
+) -> Result<RunSummary> {
+    check_output_collision(output_file, inputs, options)?;
+    
+    let mut all_segments = Vec::new();
+    let mut failed_inputs = Vec::new();
+    let mut segment_counts = Vec::new();
+    
+    for (priority, (label, bin_path)) in inputs.iter().enumerate() {
+        if options.cancelled() {
+            return Err(anyhow::anyhow!("Extraction cancelled"));
+        }
+        let xml_path = options.xml_paths.get(*label).cloned().unwrap_or_else(|| get_xml_path(bin_path));
+        status_callback(&format!("Processing {} file: {}", label, bin_path.file_name().unwrap_or_default().to_string_lossy()));
+        
+        let segment_filter = options.segment_selection.get(*label)
+            .map(|spec| parse_segment_indices(spec))
+            .transpose()
+            .context(format!("Invalid segment selection for {}", label))?
+            .filter(|indices| !indices.is_empty());
+        
+        let file_name = bin_path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let mut file_progress = |segment: usize, segment_count: usize, fraction: f32| progress_callback(&ExtractionProgress {
+            label: label.to_string(),
+            file_name: file_name.clone(),
+            segment,
+            segment_count,
+            fraction,
+            overall: (priority as f32 + fraction) / (inputs.len() + 1) as f32,
+        });
+        match process_single_file(bin_path, &xml_path, ucl_library, options, segment_filter.as_ref(), status_callback, &mut file_progress) {
+            Ok(segments) => {
+                let segment_count = segments.len();
+                segment_counts.push((label.to_string(), segment_count));
+                all_segments.extend(segments.into_iter().map(|(target_addr, data, segment)| CombinedSegment {
+                    priority,
+                    source: file_name.clone(),
+                    target_addr,
+                    data,
+                    segment,
+                }));
+                status_callback(&format!("{}: Found {} segments", label, segment_count));
+            }
+            Err(e) => {
+                status_callback(&format!("Warning: Failed to process {} file: {}", label, e));
+                // Reading and parsing errors aren't marked, they can come from anywhere in the file
+                failed_inputs.push(FailedInput {
+                    label: label.to_string(),
+                    error: e.to_string(),
+                    kind: marked_stage(&e).unwrap_or(FailureKind::Parse),
+                });
+            }
+        }
+    }
+    
+    // A file cut short by cancelling is only reported as failed above
+    if options.cancelled() {
+        return Err(anyhow::anyhow!("Extraction cancelled"));
+    }
+    if all_segments.is_empty() {
+        // Every input failed, so the run failed the way the first one did
+        let kind = failed_inputs.first().map_or(FailureKind::Validation, |failed: &FailedInput| failed.kind);
+        return Err(stage(kind)(anyhow::anyhow!("No valid files to process")));
+    }
+    
+    // Canonical order: by target address, then by combine order. Everything written from
+    // here on follows it, so identical inputs give identical outputs and manifests.
+    all_segments.sort_by_key(|segment| (segment.target_addr, segment.priority));
+    
+    let output_name = output_file.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let mut output_progress = |segment: usize, fraction: f32| progress_callback(&ExtractionProgress {
+        label: "Output".to_string(),
+        file_name: output_name.clone(),
+        segment,
+        segment_count: all_segments.len(),
+        fraction,
+        overall: (inputs.len() as f32 + fraction) / (inputs.len() + 1) as f32,
+    });
+    
+    if options.layout == OutputLayout::SegmentDirectory {
+        if options.header.is_some() {
+            status_callback("Warning: The vendor header only applies to combined images and was not written");
+        }
+        let banner = provenance_banner(inputs, &failed_inputs, ucl_library, options);
+        let artifacts = write_segment_directory(output_file, &all_segments, &banner, options, status_callback)
+            .map_err(stage(FailureKind::Write))?;
+        if options.holes_report {
+            write_holes_report(&output_file.join("holes.json"), &all_segments, status_callback)
+                .map_err(stage(FailureKind::Write))?;
+        }
+        if options.segments_csv {
+            write_segments_csv(&output_file.join("segments.csv"), &all_segments, status_callback)
+                .map_err(stage(FailureKind::Write))?;
+        }
+        if options.flash_script {
+            write_flash_script(&output_file.join("flash.txt"), &options.flash_script_template, output_file, &artifacts, status_callback)
+                .map_err(stage(FailureKind::Write))?;
+        }
+        output_progress(all_segments.len() - 1, 1.0);
+        return Ok(RunSummary {
+            checksum: None,
+            failed_inputs,
+            segment_counts,
+            output_size: None,
+            address_range: None,
+        });
     }
     
     // Write combined aligned output
-    if let Some((base_addr, _)) = all_segments.first() {
-        let base_addr = *base_addr;
+    let mut checksum = None;
+    let mut image_size = None;
+    let mut address_range = None;
+    // Files may be combined in any order, so the base is the lowest target address
+    if let Some(segments_start) = all_segments.first().map(|segment| segment.target_addr) {
         let end_addr = all_segments.iter()
-            .map(|(addr, data)| addr + data.len() as u32 - 1)
+            .map(CombinedSegment::end_addr)
             .max()
-            .unwrap_or(base_addr);
-        let total_size = end_addr - base_addr + 1;
+            .unwrap_or(segments_start);
+        // 64 bits, the span of 0x00000000-0xFFFFFFFF doesn't fit 32
+        let total_size = (end_addr - segments_start) as u64 + 1;
+        
+        // Overlaps are how later files patch earlier ones, but each one loses bytes
+        let overlaps = find_segment_overlaps(&all_segments);
+        report_segment_overlaps(&overlaps, status_callback);
+        if !overlaps.is_empty() && !options.allow_overlap {
+            return Err(anyhow::anyhow!("Output not written: segments overlap in {} places and overlaps are not allowed",
+                overlaps.len()));
+        }
+        
+        // A base image replaces the gap fill and fixes where and how long the output is
+        let base_image = match &options.base_image {
+            Some(path) => {
+                let base_image_len = fs::metadata(path)
+                    .context(format!("Failed to read base image: {}", path.display()))?
+                    .len();
+                if base_image_len > MAX_OUTPUT_SIZE as u64 {
+                    return Err(anyhow::anyhow!("Base image too large: {} bytes (max: {} bytes)", base_image_len, MAX_OUTPUT_SIZE));
+                }
+                Some(fs::read(path)
+                    .context(format!("Failed to read base image: {}", path.display()))?)
+            }
+            None => None,
+        };
+        let base_addr = match (&base_image, &options.reference_file) {
+            (Some(_), _) => options.base_image_addr.unwrap_or(segments_start),
+            (None, Some(_)) => options.reference_file_addr.unwrap_or(segments_start),
+            (None, None) => segments_start,
+        };
         
         // Protection against excessive memory allocation
-        const MAX_OUTPUT_SIZE: u32 = 200 * 1024 * 1024; // 200MB limit for final output
-        if total_size > MAX_OUTPUT_SIZE {
+        if base_image.is_none() && options.reference_file.is_none() && total_size > MAX_OUTPUT_SIZE as u64 {
             return Err(anyhow::anyhow!("Output buffer size too large: {} bytes (max: {} bytes). Address range: 0x{:08X} to 0x{:08X}", 
                 total_size, MAX_OUTPUT_SIZE, base_addr, end_addr));
         }
         
-        let mut full_buffer = vec![0x00u8; total_size as usize];
+        // A reference image fixes the output length; segments are laid out from the same base
+        let reference = match &options.reference_file {
+            Some(path) => {
+                let reference_len = fs::metadata(path)
+                    .context(format!("Failed to read reference file: {}", path.display()))?
+                    .len();
+                if reference_len > MAX_OUTPUT_SIZE as u64 {
+                    return Err(anyhow::anyhow!("Reference file too large: {} bytes (max: {} bytes)", reference_len, MAX_OUTPUT_SIZE));
+                }
+                Some(fs::read(path)
+                    .context(format!("Failed to read reference file: {}", path.display()))?)
+            }
+            None => None,
+        };
         
-        for (target_addr, data) in all_segments {
-            let offset = (target_addr - base_addr) as usize;
-            if offset + data.len() <= full_buffer.len() {
-                full_buffer[offset..offset + data.len()].copy_from_slice(&data);
+        // Nothing may be cut off silently when the output is meant to reproduce the reference
+        if let (None, Some(reference)) = (&base_image, &reference) {
+            let outside: Vec<String> = all_segments.iter()
+                .filter(|segment| segment.target_addr < base_addr
+                    || (segment.end_addr() - base_addr) as u64 >= reference.len() as u64)
+                .map(|segment| format!("0x{:08X}-0x{:08X} from {}", segment.target_addr, segment.end_addr(), segment.source))
+                .collect();
+            if !outside.is_empty() {
+                return Err(anyhow::anyhow!("Output not written: {} segments fall outside the {} byte reference file starting at 0x{:08X}: {}",
+                    outside.len(), reference.len(), base_addr, outside.join(", ")));
             }
         }
         
-        // Calculate desired size in bytes (0.0 means use natural size)
-        if desired_size_mb > 0.0 {
-            let desired_size_bytes = (desired_size_mb * 1024.0 * 1024.0) as usize;
-            
-            // If the current buffer is smaller than desired size, pad it
-            if full_buffer.len() < desired_size_bytes {
-                let padding_needed = desired_size_bytes - full_buffer.len();
-                full_buffer.extend(vec![0x00u8; padding_needed]);
-                status_callback(&format!("Padded output with {} bytes of zero data to reach {} MB", 
-                    padding_needed, desired_size_mb));
+        let mut full_buffer = match (&base_image, &reference) {
+            (Some(base_image), _) => base_image.clone(),
+            (None, Some(reference)) => vec![options.gap_fill; reference.len()],
+            (None, None) => vec![options.gap_fill; total_size as usize],
+        };
+        
+        // Copy in combine order (stable, so canonical within a file) so later files still
+        // win where segments from different files overlap
+        let mut write_order: Vec<&CombinedSegment> = all_segments.iter().collect();
+        write_order.sort_by_key(|segment| segment.priority);
+        let total_bytes = write_order.iter().map(|segment| segment.data.len()).sum::<usize>().max(1);
+        let mut written_bytes = 0;
+        for (index, segment) in write_order.into_iter().enumerate() {
+            output_progress(index, written_bytes as f32 / total_bytes as f32);
+            written_bytes += segment.data.len();
+            let offset = segment.target_addr.checked_sub(base_addr).map(|offset| offset as usize);
+            match offset {
+                Some(offset) if offset + segment.data.len() <= full_buffer.len() => {
+                    segment.data.read_into(&mut full_buffer[offset..offset + segment.data.len()])?;
+                }
+                Some(_) => status_callback(&format!("Warning: Segment at 0x{:08X} from {} extends past the end of the output and was skipped",
+                    segment.target_addr, segment.source)),
+                None => status_callback(&format!("Warning: Segment at 0x{:08X} from {} starts before the base image at 0x{:08X} and was skipped",
+                    segment.target_addr, segment.source, base_addr)),
+            }
+        }
+        
+        if let Some(base_image) = &base_image {
+            report_base_image_changes(&full_buffer, base_image, base_addr, status_callback);
+        }
+        
+        // Bytes between the first and last segment that any segment covers
+        let hole_bytes: u64 = find_holes(&all_segments).iter().map(|&(start, end)| (end - start) as u64 + 1).sum();
+        let occupied_bytes = total_size - hole_bytes;
+        let occupancy_percent = occupied_bytes as f64 * 100.0 / total_size.max(1) as f64;
+        status_callback(&format!("Occupancy: segments cover {} of the {} bytes from 0x{:08X} to 0x{:08X} ({:.1}%)",
+            occupied_bytes, total_size, segments_start, end_addr, occupancy_percent));
+        if occupancy_percent < LOW_OCCUPANCY_PERCENT {
+            status_callback(&format!("Warning: Segments cover only {:.1}% of the combined span; a file may be missing or a far-off segment inflates the image",
+                occupancy_percent));
+        }
+        
+        // Failed or skipped segments leave gap fill behind, which still looks like an image.
+        // Over a base image there is no gap fill, so there is nothing to tell them apart by.
+        if base_image.is_some() {
+            if options.min_real_data_percent.is_some() {
+                status_callback("Warning: The minimum real data check was skipped, a base image supplies the bytes between segments instead of gap fill");
+            }
+        } else {
+            let real_bytes = full_buffer.iter().filter(|&&byte| byte != options.gap_fill).count();
+            let real_percent = real_bytes as f64 * 100.0 / full_buffer.len().max(1) as f64;
+            status_callback(&format!("Real data: {} of {} bytes ({:.1}%) differ from the gap fill 0x{:02X}",
+                real_bytes, full_buffer.len(), real_percent, options.gap_fill));
+            if let Some(min_percent) = options.min_real_data_percent {
+                if real_percent <= min_percent as f64 {
+                    return Err(anyhow::anyhow!("Output not written: only {} bytes ({:.1}%) differ from the gap fill, images with at most {:.1}% are rejected",
+                        real_bytes, real_percent, min_percent));
+                }
+            }
+        }
+        
+        // Address-based formats list only what has data, so padding and headers don't apply
+        let address_based = options.format != OutputFormat::RawBinary;
+        if address_based && options.header.is_some() {
+            status_callback("Warning: The vendor header only applies to raw binary images and was not written");
+        }
+        if address_based && options.split_part_kb.is_some() {
+            status_callback("Warning: Splitting only applies to raw binary images; a single file was written");
+        }
+        
+        // Determine the padded size (the natural size is never truncated, a reference is never padded)
+        let padded_size = match options.pad_mode {
+            _ if reference.is_some() || address_based => full_buffer.len(),
+            PadMode::Natural => full_buffer.len(),
+            PadMode::DesiredSize => (options.desired_size_mb * 1024.0 * 1024.0) as usize,
+            PadMode::NextPowerOfTwo => full_buffer.len().next_power_of_two(),
+        };
+        
+        if options.safe_mode && padded_size > MAX_OUTPUT_SIZE as usize {
+            return Err(anyhow::anyhow!("Safe mode rejected padding to {} bytes (max: {} bytes)", padded_size, MAX_OUTPUT_SIZE));
+        }
+        
+        if full_buffer.len() < padded_size {
+            let padding_needed = padded_size - full_buffer.len();
+            full_buffer.resize(padded_size, options.pad_fill);
+            status_callback(&format!("Padded output with {} bytes of 0x{:02X} to reach {} bytes ({} MB)", 
+                padding_needed, options.pad_fill, padded_size, padded_size as f32 / (1024.0 * 1024.0)));
+        }
+        
+        // Split parts, address-based formats and the flashing script address every byte
+        if base_addr as u64 + full_buffer.len() as u64 > ADDRESS_SPACE_SIZE {
+            return Err(anyhow::anyhow!("Output not written: the {} byte image at 0x{:08X} would end past the 32-bit address space",
+                full_buffer.len(), base_addr));
+        }
+        
+        let image_checksum = compute_checksum(options.checksum, &full_buffer);
+        status_callback(&format!("Checksum: {} over {} bytes of image data{}", image_checksum, image_checksum.len,
+            if options.header.is_some() && !address_based { ", not counting the vendor header" } else { "" }));
+        checksum = Some(image_checksum);
+        
+        let header = options.header.as_ref()
+            .filter(|_| !address_based)
+            .map(|spec| encode_header(spec, options.byte_order, base_addr, full_buffer.len() as u32))
+            .unwrap_or_default();
+        let mut output_size = header.len() + full_buffer.len();
+        let artifacts = if address_based {
+            let blocks = image_blocks(&all_segments, &full_buffer, base_addr, base_image.is_some());
+            let banner = provenance_banner(inputs, &failed_inputs, ucl_library, options);
+            let text = match options.format {
+                OutputFormat::Srec if banner.is_empty() => encode_srec(&blocks, &output_file.file_name().unwrap_or_default().to_string_lossy()),
+                OutputFormat::Srec => encode_srec(&blocks, &banner.join("; ")),
+                _ => encode_intel_hex(&blocks, &banner),
+            };
+            write_output_file(output_file, options, |file| file.write_all(text.as_bytes()))
+                .context("Failed to write output file")
+                .map_err(stage(FailureKind::Write))?;
+            output_size = text.len();
+            status_callback(&format!("Wrote {} in {} format, {} address ranges",
+                output_file.display(), options.format, blocks.len()));
+            vec![FlashArtifact {
+                path: output_file.to_path_buf(),
+                addr: base_addr,
+                size: full_buffer.len() as u64,
+                skip: 0,
+            }]
+        } else if let Some(part_kb) = options.split_part_kb.filter(|&kb| kb > 0) {
+            write_split_parts(output_file, &header, &full_buffer, base_addr, part_kb as usize * 1024, options, status_callback)
+                .map_err(stage(FailureKind::Write))?
+        } else {
+            write_output_file(output_file, options, |file| file.write_all(&header).and_then(|_| file.write_all(&full_buffer)))
+                .context("Failed to write output file")
+                .map_err(stage(FailureKind::Write))?;
+            vec![FlashArtifact {
+                path: output_file.to_path_buf(),
+                addr: base_addr,
+                size: full_buffer.len() as u64,
+                skip: header.len() as u64,
+            }]
+        };
+        
+        status_callback(&format!("Combined extraction complete: {} bytes ({} MB), range: 0x{:08X} to 0x{:08X}, {:.1}% occupied, gap fill 0x{:02X}, pad fill 0x{:02X}", 
+            output_size, output_size as f32 / (1024.0 * 1024.0), base_addr, end_addr, occupancy_percent, options.gap_fill, options.pad_fill));
+        if !header.is_empty() {
+            status_callback(&format!("Wrote a {} byte {} vendor header before {} bytes of image data", header.len(), options.byte_order, full_buffer.len()));
+        }
+        
+        if let Some(reference) = &reference {
+            report_reference_diff(&full_buffer, reference, base_addr, status_callback);
+        }
+        
+        if options.holes_report {
+            let mut holes_name = output_file.file_name().unwrap_or_default().to_os_string();
+            holes_name.push(".holes.json");
+            write_holes_report(&output_file.with_file_name(holes_name), &all_segments, status_callback)
+                .map_err(stage(FailureKind::Write))?;
+        }
+        if options.segments_csv {
+            let mut csv_name = output_file.file_name().unwrap_or_default().to_os_string();
+            csv_name.push(".segments.csv");
+            write_segments_csv(&output_file.with_file_name(csv_name), &all_segments, status_callback)
+                .map_err(stage(FailureKind::Write))?;
+        }
+        if options.flash_script {
+            let mut script_name = output_file.file_name().unwrap_or_default().to_os_string();
+            script_name.push(".flash.txt");
+            write_flash_script(&output_file.with_file_name(script_name), &options.flash_script_template, output_file, &artifacts, status_callback)
+                .map_err(stage(FailureKind::Write))?;
+        }
+        image_size = Some(output_size as u64);
+        address_range = Some((base_addr, end_addr));
+        output_progress(all_segments.len() - 1, 1.0);
+    }
+    
+    Ok(RunSummary {
+        checksum,
+        failed_inputs,
+        segment_counts,
+        output_size: image_size,
+        address_range,
+    })
+}
+
+/// CRC-32 of `data` (IEEE polynomial, reflected, as used by zip)
+fn crc32(data: &[u8]) -> u32 {
+    let table: Vec<u32> = (0..256u32)
+        .map(|byte| (0..8).fold(byte, |crc, _| if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 }))
+        .collect();
+    !data.iter().fold(!0u32, |crc, &byte| table[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8))
+}
+
+/// CRC-16/CCITT-FALSE of `data`: polynomial 0x1021, initial value 0xFFFF
+fn crc16_ccitt(data: &[u8]) -> u16 {
+    let table: Vec<u16> = (0..256u16)
+        .map(|byte| (0..8).fold(byte << 8, |crc, _| if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 }))
+        .collect();
+    data.iter().fold(0xFFFF, |crc, &byte| (crc << 8) ^ table[((crc >> 8) ^ byte as u16) as usize])
+}
+
+/// Checksum of `data` with `algorithm`
+pub fn compute_checksum(algorithm: ChecksumAlgorithm, data: &[u8]) -> ImageChecksum {
+    let value = match algorithm {
+        ChecksumAlgorithm::Crc32 => crc32(data),
+        ChecksumAlgorithm::Crc16Ccitt => crc16_ccitt(data) as u32,
+    };
+    ImageChecksum {
+        algorithm,
+        value,
+        len: data.len(),
+    }
+}
+
+/// `<image>.checksums.json`, where `recalculate_segment_checksums` keeps its results
+pub fn checksum_manifest_path(image_path: &Path) -> PathBuf {
+    let mut name = image_path.file_name().unwrap_or_default().to_os_string();
+    name.push(".checksums.json");
+    image_path.with_file_name(name)
+}
+
+/// Recompute the checksum of each segment over a raw combined image that may have been
+/// edited since it was written, for repacking modified segments. The segment layout comes
+/// from the segment maps of `inputs` placed as a run with `options` would place them, and
+/// the image is expected to start where that run's output starts, after any vendor header.
+/// Writes `<image>.checksums.json` and marks each segment whose checksum differs from the
+/// one in the manifest it replaces. Returns the manifest path.
+pub fn recalculate_segment_checksums(
+    image_path: &Path,
+    inputs: &[(&str, PathBuf)],
+    options: &OutputOptions,
+    status_callback: &mut dyn FnMut(&str)
+) -> Result<PathBuf> {
+    if options.layout != OutputLayout::CombinedImage || options.format != OutputFormat::RawBinary {
+        return Err(anyhow::anyhow!("Segment checksums can only be recalculated over a raw combined image"));
+    }
+    
+    let preview = process_files_preview(inputs, options);
+    for problem in preview.problems.iter().filter(|problem| problem.starts_with("Warning")) {
+        status_callback(problem);
+    }
+    let segments_start = preview.segments.iter()
+        .map(|segment| segment.addr)
+        .min()
+        .ok_or_else(|| anyhow::anyhow!("No segments to check, the selected segment maps couldn't be read"))?;
+    // Same base address choice as process_files
+    let base_addr = match (&options.base_image, &options.reference_file) {
+        (Some(_), _) => options.base_image_addr.unwrap_or(segments_start),
+        (None, Some(_)) => options.reference_file_addr.unwrap_or(segments_start),
+        (None, None) => segments_start,
+    };
+    
+    let image_len = fs::metadata(image_path)
+        .context(format!("Failed to read image: {}", image_path.display()))?
+        .len();
+    let header_len = options.header.as_ref()
+        .map(|spec| encode_header(spec, options.byte_order, 0, 0).len())
+        .unwrap_or(0);
+    if image_len > MAX_OUTPUT_SIZE as u64 + header_len as u64 {
+        return Err(anyhow::anyhow!("Image too large: {} bytes (max: {} bytes)", image_len, MAX_OUTPUT_SIZE));
+    }
+    let file = fs::read(image_path)
+        .context(format!("Failed to read image: {}", image_path.display()))?;
+    let image = file.get(header_len..).unwrap_or_default();
+    
+    // Checksums of the manifest being replaced, by input label and segment index
+    let manifest_path = checksum_manifest_path(image_path);
+    let previous: HashMap<(String, u64), String> = fs::read_to_string(&manifest_path).ok()
+        .and_then(|text| serde_json::from_str::<serde_json::Value>(&text).ok())
+        .and_then(|manifest| manifest["segments"].as_array().cloned())
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|entry| Some((
+            (entry["label"].as_str()?.to_string(), entry["index"].as_u64()?),
+            entry["checksum"].as_str()?.to_string(),
+        )))
+        .collect();
+    
+    let mut entries = Vec::new();
+    let mut changed = 0;
+    for segment in &preview.segments {
+        let offset = segment.addr.checked_sub(base_addr).map(|offset| offset as u64);
+        let Some(offset) = offset.filter(|&offset| offset + segment.len <= image.len() as u64) else {
+            status_callback(&format!("Warning: {} segment {} at 0x{:08X} lies outside the {} byte image starting at 0x{:08X} and was skipped",
+                segment.label, segment.index, segment.addr, image.len(), base_addr));
+            continue;
+        };
+        let checksum = compute_checksum(options.checksum, &image[offset as usize..(offset + segment.len) as usize]).to_string();
+        let previous_checksum = previous.get(&(segment.label.clone(), segment.index as u64));
+        let is_changed = previous_checksum.is_some_and(|previous| *previous != checksum);
+        if is_changed {
+            changed += 1;
+            status_callback(&format!("{} segment {} at 0x{:08X} changed: {} -> {}",
+                segment.label, segment.index, segment.addr, previous_checksum.map(String::as_str).unwrap_or_default(), checksum));
+        }
+        entries.push(serde_json::json!({
+            "label": segment.label,
+            "index": segment.index,
+            "target_start": format!("0x{:08X}", segment.addr),
+            "target_end": format!("0x{:08X}", segment.addr as u64 + segment.len.saturating_sub(1)),
+            "size": segment.len,
+            "checksum": checksum,
+            "changed": is_changed,
+        }));
+    }
+    
+    let manifest = serde_json::json!({
+        "image": image_path.file_name().unwrap_or_default().to_string_lossy(),
+        "base_addr": format!("0x{:08X}", base_addr),
+        "algorithm": options.checksum.to_string(),
+        "segments": entries,
+    });
+    fs::write(&manifest_path, serde_json::to_string_pretty(&manifest)?)
+        .context(format!("Failed to write {}", manifest_path.display()))?;
+    
+    status_callback(&format!("Recalculated {} checksums of {} segments in {}, {} changed since the previous manifest",
+        options.checksum, entries.len(), manifest_path.display(), changed));
+    Ok(manifest_path)
+}
+
+/// Serialize a vendor header for an image of `payload_len` bytes starting at `base_addr`
+fn encode_header(spec: &HeaderSpec, byte_order: Endianness, base_addr: u32, payload_len: u32) -> Vec<u8> {
+    let encode = |value: u32| byte_order.encode_u32(value);
+    
+    let mut header = spec.magic.clone();
+    if let Some(version) = spec.version {
+        header.extend_from_slice(&encode(version));
+    }
+    if spec.include_base_addr {
+        header.extend_from_slice(&encode(base_addr));
+    }
+    if spec.include_length {
+        header.extend_from_slice(&encode(payload_len));
+    }
+    header
+}
+
+/// Parse bytes written as hex, e.g. "42 4D 57 56" or "0x424D5756"
+pub fn parse_hex_bytes(text: &str) -> Result<Vec<u8>> {
+    let digits: String = text.trim().trim_start_matches("0x").trim_start_matches("0X")
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect();
+    if !digits.is_ascii() {
+        return Err(anyhow::anyhow!("Hex bytes may only contain 0-9 and A-F"));
+    }
+    if digits.len() % 2 != 0 {
+        return Err(anyhow::anyhow!("Hex bytes need an even number of digits"));
+    }
+    (0..digits.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16)
+            .map_err(|_| anyhow::anyhow!("'{}' is not a hex byte", &digits[i..i + 2])))
+        .collect()
+}
+
+/// How many differing ranges are listed individually when comparing against a reference
+const MAX_REPORTED_DIFF_RANGES: usize = 5;
+
+/// Compare the output with a reference image of the same length and report each
+/// divergent address range
+fn report_reference_diff(output: &[u8], reference: &[u8], base_addr: u32, status_callback: &mut dyn FnMut(&str)) {
+    let ranges = differing_ranges(output, reference);
+    if ranges.is_empty() {
+        status_callback(&format!("Output matches the reference byte for byte ({} bytes)", output.len()));
+        return;
+    }
+    
+    let differing_bytes: usize = ranges.iter().map(|(start, end)| end - start).sum();
+    for (start, end) in ranges.iter().take(MAX_REPORTED_DIFF_RANGES) {
+        status_callback(&format!("Warning: Output differs from the reference at 0x{:08X}-0x{:08X} ({} bytes)",
+            base_addr as usize + start, base_addr as usize + end - 1, end - start));
+    }
+    status_callback(&format!("Warning: Output differs from the reference in {} bytes across {} ranges",
+        differing_bytes, ranges.len()));
+}
+
+/// Most overlapping segment pairs listed individually
+const MAX_REPORTED_OVERLAPS: usize = 5;
+
+/// Target ranges written by more than one segment, each with the segment already covering
+/// it and the one overlapping it. `segments` must be in canonical order.
+fn find_segment_overlaps(segments: &[CombinedSegment]) -> Vec<(u32, u32, &CombinedSegment, &CombinedSegment)> {
+    let mut overlaps = Vec::new();
+    // The segment reaching furthest so far, and its last address
+    let mut furthest: Option<(u32, &CombinedSegment)> = None;
+    for segment in segments.iter().filter(|segment| !segment.data.is_empty()) {
+        let end = segment.end_addr();
+        if let Some((covered_end, covering)) = furthest {
+            if segment.target_addr <= covered_end {
+                overlaps.push((segment.target_addr, end.min(covered_end), covering, segment));
+            }
+            if covered_end >= end {
+                continue;
+            }
+        }
+        furthest = Some((end, segment));
+    }
+    overlaps
+}
+
+/// Warn about each overlap and which segment's bytes end up in the output
+fn report_segment_overlaps(overlaps: &[(u32, u32, &CombinedSegment, &CombinedSegment)], status_callback: &mut dyn FnMut(&str)) {
+    if overlaps.is_empty() {
+        return;
+    }
+    
+    for &(start, end, covering, overlapping) in overlaps.iter().take(MAX_REPORTED_OVERLAPS) {
+        // Copying is stable by combine order, so on a tie the later segment is written last
+        let winner = if overlapping.priority >= covering.priority { overlapping } else { covering };
+        status_callback(&format!("Warning: Segments overlap at 0x{:08X}-0x{:08X} ({} bytes): 0x{:08X} from {} and 0x{:08X} from {}, the one from {} is kept",
+            start, end, (end - start) as u64 + 1, covering.target_addr, covering.source, overlapping.target_addr, overlapping.source, winner.source));
+    }
+    let overlapping_bytes: u64 = overlaps.iter().map(|&(start, end, _, _)| (end - start) as u64 + 1).sum();
+    status_callback(&format!("Warning: Segments overlap in {} places covering {} bytes; later files in the combine order overwrite earlier ones",
+        overlaps.len(), overlapping_bytes));
+}
+
+/// One-line summary of how `current` differs from `previous`, the same output written
+/// by an earlier run. Ranges are file offsets, so a vendor header counts too.
+pub fn describe_output_changes(previous: &[u8], current: &[u8]) -> String {
+    let ranges = differing_ranges(previous, current);
+    if ranges.is_empty() && previous.len() == current.len() {
+        return format!("Identical to the previous run ({} bytes)", current.len());
+    }
+    
+    let changed_bytes: usize = ranges.iter().map(|(start, end)| end - start).sum();
+    let mut shown: Vec<String> = ranges.iter()
+        .take(MAX_REPORTED_DIFF_RANGES)
+        .map(|(start, end)| format!("0x{:X}-0x{:X}", start, end - 1))
+        .collect();
+    if ranges.len() > MAX_REPORTED_DIFF_RANGES {
+        shown.push("...".to_string());
+    }
+    let mut summary = format!("Differs from the previous run in {} bytes across {} ranges", changed_bytes, ranges.len());
+    if !shown.is_empty() {
+        summary.push_str(&format!(" at offsets {}", shown.join(", ")));
+    }
+    if previous.len() != current.len() {
+        summary.push_str(&format!("; size changed from {} to {} bytes", previous.len(), current.len()));
+    }
+    summary
+}
+
+/// Report which regions of the base image the segments changed
+fn report_base_image_changes(output: &[u8], base_image: &[u8], base_addr: u32, status_callback: &mut dyn FnMut(&str)) {
+    let ranges = differing_ranges(output, base_image);
+    if ranges.is_empty() {
+        status_callback(&format!("Base image unchanged, the segments match its contents ({} bytes)", base_image.len()));
+        return;
+    }
+    
+    let changed_bytes: usize = ranges.iter().map(|(start, end)| end - start).sum();
+    for (start, end) in ranges.iter().take(MAX_REPORTED_DIFF_RANGES) {
+        status_callback(&format!("Patched base image at 0x{:08X}-0x{:08X} ({} bytes)",
+            base_addr as usize + start, base_addr as usize + end - 1, end - start));
+    }
+    status_callback(&format!("Patched {} bytes of the base image across {} ranges",
+        changed_bytes, ranges.len()));
+}
+
+/// Offset ranges (end exclusive) where `a` and `b` differ, over their common length
+fn differing_ranges(a: &[u8], b: &[u8]) -> Vec<(usize, usize)> {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for (offset, (x, y)) in a.iter().zip(b).enumerate() {
+        if x == y {
+            continue;
+        }
+        match ranges.last_mut() {
+            Some((_, end)) if *end == offset => *end = offset + 1,
+            _ => ranges.push((offset, offset + 1)),
+        }
+    }
+    ranges
+}
+
+/// Hex dump with 16 bytes per line, each line prefixed with its address. At most
+/// `max_bytes` are dumped; a trailing note says how much was left out.
+pub fn format_hex_dump(data: &[u8], base_addr: u32, max_bytes: usize) -> String {
+    let shown = &data[..data.len().min(max_bytes)];
+    let mut lines: Vec<String> = shown.chunks(16)
+        .enumerate()
+        .map(|(row, chunk)| {
+            let bytes: Vec<String> = chunk.iter().map(|byte| format!("{:02X}", byte)).collect();
+            format!("0x{:08X}: {}", base_addr as u64 + row as u64 * 16, bytes.join(" "))
+        })
+        .collect();
+    if shown.len() < data.len() {
+        lines.push(format!("... truncated, {} of {} bytes shown", shown.len(), data.len()));
+    }
+    lines.join("\n")
+}
+
+/// Address ranges (inclusive) between the first and last segment that no segment covers.
+/// `segments` must be sorted by target address.
+fn find_holes(segments: &[CombinedSegment]) -> Vec<(u32, u32)> {
+    let ranges: Vec<(u32, u32)> = segments.iter()
+        .filter(|segment| !segment.data.is_empty())
+        .map(|segment| (segment.target_addr, segment.end_addr()))
+        .collect();
+    find_gaps(&ranges)
+}
+
+/// Address ranges (inclusive) between the first and last of the inclusive `ranges` that
+/// none of them covers. `ranges` must be sorted by start address.
+fn find_gaps(ranges: &[(u32, u32)]) -> Vec<(u32, u32)> {
+    let mut gaps = Vec::new();
+    // One past the highest address covered so far
+    let mut covered_end: Option<u64> = None;
+    for &(start, end) in ranges {
+        if let Some(covered) = covered_end {
+            if start as u64 > covered {
+                gaps.push((covered as u32, start - 1));
             }
         }
+        let range_end = end as u64 + 1;
+        covered_end = Some(covered_end.map_or(range_end, |covered| covered.max(range_end)));
+    }
+    gaps
+}
+
+/// Write the unoccupied ranges of the combined span as JSON, so flashing tools can leave
+/// them untouched instead of writing fill bytes over existing data
+fn write_holes_report(path: &Path, segments: &[CombinedSegment], status_callback: &mut dyn FnMut(&str)) -> Result<()> {
+    let holes = find_holes(segments);
+    let span_start = segments.first().map_or(0, |segment| segment.target_addr);
+    let span_end = segments.iter()
+        .map(CombinedSegment::end_addr)
+        .max()
+        .unwrap_or(span_start);
+    
+    let hole_entries: Vec<_> = holes.iter()
+        .map(|&(start, end)| serde_json::json!({
+            "start": format!("0x{:08X}", start),
+            "end": format!("0x{:08X}", end),
+            "size": (end - start) as u64 + 1,
+        }))
+        .collect();
+    let report = serde_json::json!({
+        "span_start": format!("0x{:08X}", span_start),
+        "span_end": format!("0x{:08X}", span_end),
+        "holes": hole_entries,
+    });
+    fs::write(path, serde_json::to_string_pretty(&report)?)
+        .context(format!("Failed to write holes report: {}", path.display()))?;
+    
+    let hole_bytes: u64 = holes.iter().map(|&(start, end)| (end - start) as u64 + 1).sum();
+    status_callback(&format!("Holes report: {} unoccupied ranges ({} bytes) written to {}",
+        holes.len(), hole_bytes, path.display()));
+    Ok(())
+}
+
+/// `<path>.partial`, where an output is written before it is renamed into place
+fn partial_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".partial");
+    path.with_file_name(name)
+}
+
+/// Write `path` through its `.partial` sibling, which only replaces `path` once `write`
+/// succeeded and the run wasn't cancelled meanwhile. Otherwise it is removed, so a
+/// stopped or failed run never leaves a truncated output behind.
+fn write_output_file(
+    path: &Path,
+    options: &OutputOptions,
+    write: impl FnOnce(&mut fs::File) -> std::io::Result<()>
+) -> Result<()> {
+    let partial = partial_path(path);
+    let written = fs::File::create(&partial).and_then(|mut file| write(&mut file));
+    if let Err(e) = written {
+        let _ = fs::remove_file(&partial);
+        return Err(e.into());
+    }
+    if options.cancelled() {
+        let _ = fs::remove_file(&partial);
+        return Err(anyhow::anyhow!("Extraction cancelled"));
+    }
+    fs::rename(&partial, path)?;
+    Ok(())
+}
+
+/// Clean up split parts that were written but not renamed into place
+fn remove_partial_parts(parts: &[(PathBuf, PathBuf)]) {
+    for (partial, _) in parts {
+        let _ = fs::remove_file(partial);
+    }
+}
+
+/// Write the header and image as consecutive `<output>.partNNN` files of `part_size`
+/// bytes (the last one shorter), plus a `<output>.parts.json` index giving each part's
+/// file offset and the image addresses it holds. Header bytes have no address. Parts
+/// are renamed into place together, so a cancelled run leaves no mix of old and new
+/// parts. Returns the parts holding image bytes for the flashing script.
+fn write_split_parts(
+    output_file: &Path,
+    header: &[u8],
+    image: &[u8],
+    base_addr: u32,
+    part_size: usize,
+    options: &OutputOptions,
+    status_callback: &mut dyn FnMut(&str)
+) -> Result<Vec<FlashArtifact>> {
+    let file_name = output_file.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let total_len = header.len() + image.len();
+    let part_count = total_len.div_ceil(part_size).max(1);
+    
+    let mut part_entries = Vec::new();
+    let mut artifacts = Vec::new();
+    let mut parts = Vec::new();
+    for part in 0..part_count {
+        let offset = part * part_size;
+        let end = (offset + part_size).min(total_len);
+        let part_name = format!("{}.part{:03}", file_name, part + 1);
         
-        fs::write(output_file, &full_buffer)
-            .context("Failed to write output file")?;
+        // The part may straddle the header and the image
+        let header_bytes = &header[offset.min(header.len())..end.min(header.len())];
+        let image_bytes = &image[offset.saturating_sub(header.len())..end.saturating_sub(header.len())];
+        let part_path = output_file.with_file_name(&part_name);
+        let partial = partial_path(&part_path);
+        let written = fs::File::create(&partial)
+            .and_then(|mut file| file.write_all(header_bytes).and_then(|_| file.write_all(image_bytes)));
+        parts.push((partial, part_path));
+        if let Err(e) = written {
+            remove_partial_parts(&parts);
+            return Err(anyhow::Error::from(e).context(format!("Failed to write output part: {}", part_name)));
+        }
         
-        status_callback(&format!("Combined extraction complete: {} bytes ({} MB), range: 0x{:08X} to 0x{:08X}", 
-            full_buffer.len(), full_buffer.len() as f32 / (1024.0 * 1024.0), base_addr, end_addr));
+        let (start_addr, end_addr) = if image_bytes.is_empty() {
+            (serde_json::Value::Null, serde_json::Value::Null)
+        } else {
+            let start = base_addr + offset.saturating_sub(header.len()) as u32;
+            let end = start + (image_bytes.len() - 1) as u32;
+            artifacts.push(FlashArtifact {
+                path: output_file.with_file_name(&part_name),
+                addr: start,
+                size: image_bytes.len() as u64,
+                skip: header_bytes.len() as u64,
+            });
+            (format!("0x{:08X}", start).into(), format!("0x{:08X}", end).into())
+        };
+        part_entries.push(serde_json::json!({
+            "file": part_name,
+            "offset": offset,
+            "size": end - offset,
+            "header_bytes": header_bytes.len(),
+            "start": start_addr,
+            "end": end_addr,
+        }));
+    }
+    
+    // Parts only replace earlier ones once all of them were written
+    if options.cancelled() {
+        remove_partial_parts(&parts);
+        return Err(anyhow::anyhow!("Extraction cancelled"));
+    }
+    for (partial, part_path) in &parts {
+        fs::rename(partial, part_path)
+            .context(format!("Failed to write output part: {}", part_path.display()))?;
+    }
+    
+    let index_path = output_file.with_file_name(format!("{}.parts.json", file_name));
+    let index = serde_json::json!({
+        "part_size": part_size,
+        "total_size": total_len,
+        "parts": part_entries,
+    });
+    fs::write(&index_path, serde_json::to_string_pretty(&index)?)
+        .context(format!("Failed to write parts index: {}", index_path.display()))?;
+    
+    status_callback(&format!("Split output into {} parts of up to {} KB, index written to {}",
+        part_count, part_size / 1024, index_path.display()));
+    Ok(artifacts)
+}
+
+/// Provenance lines for text outputs when `OutputOptions::text_banner` is set: tool
+/// version, time of the run, the inputs that made it into the output and the UCL
+/// decompression function. Empty when the banner is off.
+fn provenance_banner(inputs: &[(&str, PathBuf)], failed_inputs: &[FailedInput], ucl_library: &UclLibrary, options: &OutputOptions) -> Vec<String> {
+    if !options.text_banner {
+        return Vec::new();
+    }
+    let sources: Vec<String> = inputs.iter()
+        .filter(|(label, _)| !failed_inputs.iter().any(|failed| failed.label == *label))
+        .map(|(label, path)| format!("{} {}", label, path.file_name().unwrap_or_default().to_string_lossy()))
+        .collect();
+    vec![
+        format!("Created by BMW Virtual Reader {}", env!("CARGO_PKG_VERSION")),
+        format!("Date: {} UTC", current_timestamp()),
+        format!("Sources: {}", sources.join(", ")),
+        format!("UCL: {}", ucl_library.decompress_symbol()),
+    ]
+}
+
+/// Image ranges an address-based format lists, as (address, bytes): the whole image when
+/// it was built on a base image, otherwise what the segments cover. `segments` must be in
+/// canonical order; adjacent and overlapping segments form one range.
+fn image_blocks<'a>(segments: &[CombinedSegment], image: &'a [u8], base_addr: u32, whole_image: bool) -> Vec<(u32, &'a [u8])> {
+    if whole_image {
+        return vec![(base_addr, image)];
+    }
+    
+    // Offsets into the image, end exclusive
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for segment in segments {
+        let Some(start) = segment.target_addr.checked_sub(base_addr).map(|offset| offset as usize) else {
+            continue;
+        };
+        let end = (start + segment.data.len()).min(image.len());
+        if start >= end {
+            continue;
+        }
+        match ranges.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => ranges.push((start, end)),
+        }
+    }
+    ranges.into_iter()
+        .map(|(start, end)| (base_addr + start as u32, &image[start..end]))
+        .collect()
+}
+
+/// Data bytes per line of an address-based format
+const RECORD_DATA_LEN: usize = 16;
+
+/// Most bytes an S0 header record can carry next to its count, address and checksum
+const SREC_HEADER_MAX_LEN: usize = 252;
+
+/// One Intel HEX record with its checksum, the two's complement of the byte sum
+fn intel_hex_record(record_type: u8, address: u16, data: &[u8]) -> String {
+    let mut bytes = vec![data.len() as u8];
+    bytes.extend_from_slice(&address.to_be_bytes());
+    bytes.push(record_type);
+    bytes.extend_from_slice(data);
+    let checksum = bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)).wrapping_neg();
+    bytes.push(checksum);
+    format!(":{}", bytes.iter().map(|byte| format!("{:02X}", byte)).collect::<String>())
+}
+
+/// Intel HEX for `blocks`: data records of up to 16 bytes that never cross a 64 KB
+/// boundary, an extended linear address record whenever the upper 16 address bits
+/// change, and the end-of-file record. Each `banner` line is written first as a `;`
+/// comment, which lenient parsers skip and strict ones reject.
+fn encode_intel_hex(blocks: &[(u32, &[u8])], banner: &[String]) -> String {
+    let mut lines: Vec<String> = banner.iter().map(|line| format!("; {}", line)).collect();
+    let mut upper_bits = None;
+    for &(addr, data) in blocks {
+        let mut offset = 0;
+        while offset < data.len() {
+            let line_addr = addr + offset as u32;
+            let to_boundary = 0x1_0000 - (line_addr & 0xFFFF) as usize;
+            let len = RECORD_DATA_LEN.min(data.len() - offset).min(to_boundary);
+            let line_upper_bits = (line_addr >> 16) as u16;
+            if upper_bits != Some(line_upper_bits) {
+                lines.push(intel_hex_record(0x04, 0, &line_upper_bits.to_be_bytes()));
+                upper_bits = Some(line_upper_bits);
+            }
+            lines.push(intel_hex_record(0x00, line_addr as u16, &data[offset..offset + len]));
+            offset += len;
+        }
+    }
+    lines.push(intel_hex_record(0x01, 0, &[]));
+    lines.join("\r\n") + "\r\n"
+}
+
+/// One S-record with its checksum, the ones' complement of the sum of the count,
+/// address and data bytes. `address_len` is the number of address bytes, 2 to 4.
+fn srec_record(record_type: u8, address: u32, address_len: usize, data: &[u8]) -> String {
+    let mut bytes = vec![(address_len + data.len() + 1) as u8];
+    bytes.extend_from_slice(&address.to_be_bytes()[4 - address_len..]);
+    bytes.extend_from_slice(data);
+    let checksum = !bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+    bytes.push(checksum);
+    format!("S{}{}", record_type, bytes.iter().map(|byte| format!("{:02X}", byte)).collect::<String>())
+}
+
+/// Motorola S-records for `blocks`: an S0 header carrying `header`, S1, S2 or S3 data
+/// records of up to 16 bytes depending on the highest address, an S5 (or S6 past 65535)
+/// count of the data records and the matching S9, S8 or S7 termination record
+fn encode_srec(blocks: &[(u32, &[u8])], header: &str) -> String {
+    let max_addr = blocks.iter()
+        .map(|&(addr, data)| addr + data.len().saturating_sub(1) as u32)
+        .max()
+        .unwrap_or(0);
+    let (data_type, end_type, address_len) = match max_addr {
+        0..=0xFFFF => (1, 9, 2),
+        0x1_0000..=0xFF_FFFF => (2, 8, 3),
+        _ => (3, 7, 4),
+    };
+    
+    // The header's data field is limited by the one-byte record length
+    let mut lines = vec![srec_record(0, 0, 2, &header.as_bytes()[..header.len().min(SREC_HEADER_MAX_LEN)])];
+    let mut data_records: u32 = 0;
+    for &(addr, data) in blocks {
+        for (index, chunk) in data.chunks(RECORD_DATA_LEN).enumerate() {
+            lines.push(srec_record(data_type, addr + (index * RECORD_DATA_LEN) as u32, address_len, chunk));
+            data_records += 1;
+        }
+    }
+    if data_records <= 0xFFFF {
+        lines.push(srec_record(5, data_records, 2, &[]));
+    } else {
+        lines.push(srec_record(6, data_records, 3, &[]));
+    }
+    lines.push(srec_record(end_type, 0, address_len, &[]));
+    lines.join("\r\n") + "\r\n"
+}
+
+/// Quote a CSV field if it contains a separator, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Write one CSV row per extracted segment in canonical order, for spreadsheets. Sizes
+/// are the declared source size and the number of bytes actually written.
+fn write_segments_csv(path: &Path, segments: &[CombinedSegment], status_callback: &mut dyn FnMut(&str)) -> Result<()> {
+    let mut lines = vec!["file,source_start,source_end,target_start,target_end,source_size,target_size,compressed,ratio".to_string()];
+    for CombinedSegment { source, target_addr, data, segment, .. } in segments {
+        let source_size = segment.source_len();
+        let target_size = data.len() as u64;
+        lines.push(format!("{},0x{:08X},0x{:08X},0x{:08X},0x{:08X},{},{},{},{:.3}",
+            csv_field(source),
+            segment.source_start_addr,
+            segment.source_end_addr,
+            target_addr,
+            target_addr + target_size.saturating_sub(1) as u32,
+            source_size,
+            target_size,
+            if segment.is_compressed { "yes" } else { "no" },
+            target_size as f64 / source_size as f64));
+    }
+    fs::write(path, lines.join("\n") + "\n")
+        .context(format!("Failed to write segment CSV: {}", path.display()))?;
+    
+    status_callback(&format!("Segment CSV: {} rows written to {}", segments.len(), path.display()));
+    Ok(())
+}
+
+/// Write a flashing script for `artifacts` from `template`. Lines with a per-file
+/// placeholder are repeated for each file, all others are written once; addresses are
+/// 8 hex digits without a prefix so the template can add the one its tool expects.
+fn write_flash_script(
+    path: &Path,
+    template: &str,
+    output_file: &Path,
+    artifacts: &[FlashArtifact],
+    status_callback: &mut dyn FnMut(&str)
+) -> Result<()> {
+    let output_name = output_file.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let run_value = |placeholder: &str| match placeholder {
+        "output" => Some(output_name.clone()),
+        "count" => Some(artifacts.len().to_string()),
+        "date" => Some(current_date()),
+        _ => None,
+    };
+    
+    let mut lines = Vec::new();
+    for line in template.lines() {
+        if !is_per_file_line(line) {
+            lines.push(expand_template(line, "flashing script template", &FLASH_SCRIPT_PLACEHOLDERS, &run_value)?);
+            continue;
+        }
+        for artifact in artifacts {
+            lines.push(expand_template(line, "flashing script template", &FLASH_SCRIPT_PLACEHOLDERS, &|placeholder| match placeholder {
+                "file" => Some(artifact.path.file_name().unwrap_or_default().to_string_lossy().to_string()),
+                "path" => Some(artifact.path.display().to_string()),
+                "addr" => Some(format!("{:08X}", artifact.addr)),
+                "end" => Some(format!("{:08X}", artifact.addr + artifact.size.saturating_sub(1) as u32)),
+                "addr_dec" => Some(artifact.addr.to_string()),
+                "size" => Some(artifact.size.to_string()),
+                "skip" => Some(artifact.skip.to_string()),
+                other => run_value(other),
+            })?);
+        }
     }
+    fs::write(path, lines.join("\n") + "\n")
+        .context(format!("Failed to write flashing script: {}", path.display()))?;
     
+    status_callback(&format!("Flashing script for {} files written to {}", artifacts.len(), path.display()));
     Ok(())
-} 
\ No newline at end of file
+}
+
+/// Write each segment as `0x<addr>_<file>.bin` into `output_dir`, together with a
+/// `manifest.json` and a plain-text `segments.map` listing them in canonical order.
+/// With `decimal_addresses` the manifest also gives each address as a number, and the
+/// `banner` lines head `segments.map` as `#` comments. A name already taken, e.g. by a
+/// same-named input from another PSDZ folder at the same address, gets a `_2`, `_3`, ...
+/// suffix. A cancelled run removes the segment files it already wrote. Returns the
+/// segment files for the flashing script.
+fn write_segment_directory(
+    output_dir: &Path,
+    segments: &[CombinedSegment],
+    banner: &[String],
+    options: &OutputOptions,
+    status_callback: &mut dyn FnMut(&str)
+) -> Result<Vec<FlashArtifact>> {
+    fs::create_dir_all(output_dir)
+        .context(format!("Failed to create output directory: {}", output_dir.display()))?;
+    
+    let mut manifest_entries = Vec::new();
+    let mut artifacts: Vec<FlashArtifact> = Vec::new();
+    let mut map_lines: Vec<String> = banner.iter().map(|line| format!("# {}", line)).collect();
+    map_lines.push(format!("{:<10} {:<10} {:>10}  {:<40} {}", "Start", "End", "Size", "Segment file", "Source"));
+    // Lowercase, so names that differ only in case don't collide on Windows and macOS
+    let mut used_names = HashSet::new();
+    
+    for CombinedSegment { source, target_addr, data, .. } in segments {
+        // Same readability tweak as the file browser: .bin.001_002_003 becomes _001_002_003
+        let stem = format!("0x{:08X}_{}", target_addr, source.replace(".bin.", "_").trim_end_matches(".bin"));
+        let mut segment_file = format!("{}.bin", stem);
+        let mut copy = 1;
+        while !used_names.insert(segment_file.to_lowercase()) {
+            copy += 1;
+            segment_file = format!("{}_{}.bin", stem, copy);
+        }
+        if copy > 1 {
+            status_callback(&format!("Warning: Segment at 0x{:08X} from {} has the same file name as an earlier segment and was written as {}",
+                target_addr, source, segment_file));
+        }
+        // Segments may be decompressed only now, so this is where a stop request lands
+        if options.cancelled() {
+            for artifact in &artifacts {
+                let _ = fs::remove_file(&artifact.path);
+            }
+            return Err(anyhow::anyhow!("Extraction cancelled"));
+        }
+        let partial = partial_path(&output_dir.join(&segment_file));
+        fs::File::create(&partial).map_err(anyhow::Error::from)
+            .and_then(|mut file| data.write_to(&mut file))
+            .and_then(|_| fs::rename(&partial, output_dir.join(&segment_file)).map_err(anyhow::Error::from))
+            .map_err(|e| {
+                let _ = fs::remove_file(&partial);
+                e
+            })
+            .context(format!("Failed to write segment file: {}", segment_file))?;
+        
+        let end_addr = target_addr + data.len().saturating_sub(1) as u32;
+        map_lines.push(format!("0x{:08X} 0x{:08X} {:>10}  {:<40} {}", target_addr, end_addr, data.len(), segment_file, source));
+        let mut entry = serde_json::json!({
+            "file": segment_file,
+            "source": source,
+            "target_start": format!("0x{:08X}", target_addr),
+            "target_end": format!("0x{:08X}", end_addr),
+            "size": data.len(),
+        });
+        if options.decimal_addresses {
+            entry["target_start_decimal"] = (*target_addr).into();
+            entry["target_end_decimal"] = end_addr.into();
+        }
+        manifest_entries.push(entry);
+        artifacts.push(FlashArtifact {
+            path: output_dir.join(&segment_file),
+            addr: *target_addr,
+            size: data.len() as u64,
+            skip: 0,
+        });
+    }
+    
+    let manifest = serde_json::json!({ "segments": manifest_entries });
+    fs::write(output_dir.join("manifest.json"), serde_json::to_string_pretty(&manifest)?)
+        .context("Failed to write manifest.json")?;
+    fs::write(output_dir.join("segments.map"), map_lines.join("\n") + "\n")
+        .context("Failed to write segments.map")?;
+    
+    status_callback(&format!("Segment export complete: {} files ({} segments, manifest.json, segments.map) in {}",
+        segments.len() + 2, segments.len(), output_dir.display()));
+    
+    Ok(artifacts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn segment(priority: usize, target_addr: u32, len: usize) -> CombinedSegment {
+        CombinedSegment {
+            priority,
+            source: format!("file {}", priority),
+            target_addr,
+            data: SegmentData::Loaded(vec![0; len]),
+            segment: FlashSegment {
+                source_start_addr: 0,
+                source_end_addr: len.saturating_sub(1) as u32,
+                target_start_addr: target_addr,
+                target_end_addr: target_addr + len.saturating_sub(1) as u32,
+                is_compressed: false,
+            },
+        }
+    }
+    
+    #[test]
+    fn overlapping_ranges_are_found() {
+        assert_eq!(find_overlaps(&[(0x100, 0x1FF), (0x180, 0x27F)]), vec![(0x180, 0x1FF)]);
+        // Contained, and merged with an adjacent overlap
+        assert_eq!(find_overlaps(&[(0x000, 0x0FF), (0x010, 0x01F), (0x020, 0x02F)]), vec![(0x010, 0x02F)]);
+    }
+    
+    #[test]
+    fn separate_ranges_do_not_overlap() {
+        assert!(find_overlaps(&[(0x100, 0x1FF), (0x300, 0x3FF)]).is_empty());
+        // Inverted ranges are ignored
+        assert!(find_overlaps(&[(0x100, 0x1FF), (0x180, 0x17F)]).is_empty());
+    }
+    
+    #[test]
+    fn ranges_touching_at_a_boundary() {
+        // Inclusive ends, so adjacent ranges don't overlap but sharing the last address does
+        assert!(find_overlaps(&[(0x100, 0x1FF), (0x200, 0x2FF)]).is_empty());
+        assert_eq!(find_overlaps(&[(0x100, 0x200), (0x200, 0x2FF)]), vec![(0x200, 0x200)]);
+        assert!(find_overlaps(&[(0, 0xFFFF_FFFE), (0xFFFF_FFFF, 0xFFFF_FFFF)]).is_empty());
+    }
+    
+    #[test]
+    fn overlapping_segments_are_found() {
+        let segments = [segment(0, 0x1000, 0x100), segment(1, 0x1080, 0x100)];
+        let overlaps = find_segment_overlaps(&segments);
+        assert_eq!(overlaps.len(), 1);
+        let (start, end, covering, overlapping) = overlaps[0];
+        assert_eq!((start, end), (0x1080, 0x10FF));
+        assert_eq!((covering.priority, overlapping.priority), (0, 1));
+    }
+    
+    #[test]
+    fn separate_segments_do_not_overlap() {
+        let segments = [segment(0, 0x1000, 0x100), segment(1, 0x2000, 0x100), segment(1, 0x1800, 0)];
+        assert!(find_segment_overlaps(&segments).is_empty());
+    }
+    
+    #[test]
+    fn segments_touching_at_a_boundary() {
+        let adjacent = [segment(0, 0x1000, 0x100), segment(1, 0x1100, 0x100)];
+        assert!(find_segment_overlaps(&adjacent).is_empty());
+        
+        let sharing_one_byte = [segment(0, 0x1000, 0x101), segment(1, 0x1100, 0x100)];
+        let overlaps = find_segment_overlaps(&sharing_one_byte);
+        assert_eq!(overlaps.len(), 1);
+        assert_eq!((overlaps[0].0, overlaps[0].1), (0x1100, 0x1100));
+    }
+    
+    #[test]
+    fn checksum_check_values() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc16_ccitt(b"123456789"), 0x29B1);
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc16_ccitt(b""), 0xFFFF);
+        
+        let checksum = compute_checksum(ChecksumAlgorithm::Crc16Ccitt, b"123456789");
+        assert_eq!((checksum.value, checksum.len), (0x29B1, 9));
+        assert_eq!(checksum.to_string(), "CRC16-CCITT 0x29B1");
+    }
+    
+    /// Bytes of a hex record line after its start code
+    fn record_bytes(line: &str, start_code: &str) -> Vec<u8> {
+        let hex = line.strip_prefix(start_code).unwrap();
+        (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect()
+    }
+    
+    #[test]
+    fn intel_hex_round_trip() {
+        let first: Vec<u8> = (0..40).collect();
+        let second = [0xDE, 0xAD, 0xBE, 0xEF, 0x55];
+        let blocks: [(u32, &[u8]); 2] = [(0x0001_FFF0, &first), (0x0003_0100, &second)];
+        let hex = encode_intel_hex(&blocks, &[]);
+        
+        let mut upper_bits = 0u32;
+        let mut extended_records = Vec::new();
+        let mut decoded: Vec<(u32, u8)> = Vec::new();
+        let mut end_of_file = false;
+        for line in hex.split_terminator("\r\n") {
+            assert!(!end_of_file, "record after end of file: {}", line);
+            let bytes = record_bytes(line, ":");
+            assert_eq!(bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)), 0, "checksum of {}", line);
+            let len = bytes[0] as usize;
+            assert_eq!(bytes.len(), len + 5);
+            let address = u16::from_be_bytes([bytes[1], bytes[2]]) as u32;
+            let data = &bytes[4..4 + len];
+            match bytes[3] {
+                0x00 => {
+                    assert!(len <= RECORD_DATA_LEN);
+                    assert!(address + len as u32 <= 0x1_0000, "record crosses a 64 KB boundary: {}", line);
+                    decoded.extend(data.iter().enumerate().map(|(i, &byte)| (upper_bits + address + i as u32, byte)));
+                }
+                0x04 => {
+                    upper_bits = (u16::from_be_bytes([data[0], data[1]]) as u32) << 16;
+                    extended_records.push(upper_bits);
+                }
+                0x01 => end_of_file = true,
+                other => panic!("unexpected record type {:02X}", other),
+            }
+        }
+        
+        assert!(end_of_file);
+        assert_eq!(extended_records, vec![0x0001_0000, 0x0002_0000, 0x0003_0000]);
+        let expected: Vec<(u32, u8)> = blocks.iter()
+            .flat_map(|&(addr, data)| data.iter().enumerate().map(move |(i, &byte)| (addr + i as u32, byte)))
+            .collect();
+        assert_eq!(decoded, expected);
+    }
+    
+    #[test]
+    fn srec_records_match_address_width() {
+        let data: Vec<u8> = (0..18).collect();
+        assert_eq!(encode_srec(&[(0x1000, &data)], "T"), [
+            "S004000054A7",
+            "S1131000000102030405060708090A0B0C0D0E0F64",
+            "S10510101011B9",
+            "S5030002FA",
+            "S9030000FC",
+        ].join("\r\n") + "\r\n");
+        
+        assert_eq!(encode_srec(&[(0x0001_2340, &[0xAA, 0xBB])], "T"), [
+            "S004000054A7",
+            "S206012340AABB30",
+            "S5030001FB",
+            "S804000000FB",
+        ].join("\r\n") + "\r\n");
+        
+        assert_eq!(encode_srec(&[(0x1234_5678, &[0x01])], "T"), [
+            "S004000054A7",
+            "S3061234567801E4",
+            "S5030001FB",
+            "S70500000000FA",
+        ].join("\r\n") + "\r\n");
+    }
+    
+    #[test]
+    fn banner_heads_text_outputs() {
+        let banner = vec!["Created by BMW Virtual Reader 1.0".to_string(), "Sources: SWFL1 swfl_1.bin".to_string()];
+        let hex = encode_intel_hex(&[(0x1000, &[0xAA])], &banner);
+        let lines: Vec<&str> = hex.split_terminator("\r\n").collect();
+        assert_eq!(lines[..2], ["; Created by BMW Virtual Reader 1.0", "; Sources: SWFL1 swfl_1.bin"]);
+        assert_eq!(lines[2..], encode_intel_hex(&[(0x1000, &[0xAA])], &[]).split_terminator("\r\n").collect::<Vec<_>>()[..]);
+        
+        // The S0 record holds as much of the banner as fits its one-byte length
+        let long_header = "x".repeat(300);
+        let srec = encode_srec(&[(0x1000, &[0xAA])], &long_header);
+        let header = record_bytes(srec.lines().next().unwrap(), "S0");
+        assert_eq!(header[0] as usize, 2 + SREC_HEADER_MAX_LEN + 1);
+        assert_eq!(&header[3..header.len() - 1], "x".repeat(SREC_HEADER_MAX_LEN).as_bytes());
+    }
+
+    #[test]
+    fn recalculated_checksums_mark_edited_segments() {
+        let dir = std::env::temp_dir().join(format!("bmw-virtual-reader-checksums-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let segment_xml = |source: u32, target: u32| format!(
+            "<FLASH-SEGMENT COMPRESSION-STATUS=\"UNCOMPRESSED\"><SOURCE-START-ADDRESS>{:08X}</SOURCE-START-ADDRESS><SOURCE-END-ADDRESS>{:08X}</SOURCE-END-ADDRESS><TARGET-START-ADDRESS>{:08X}</TARGET-START-ADDRESS><TARGET-END-ADDRESS>{:08X}</TARGET-END-ADDRESS></FLASH-SEGMENT>",
+            source, source + 0xF, target, target + 0xF);
+        fs::write(dir.join("swfl_1.xml"), format!("<FLASH-SEGMENTS>{}{}</FLASH-SEGMENTS>", segment_xml(0, 0x1000), segment_xml(0x10, 0x1020))).unwrap();
+        let inputs = [("SWFL1", dir.join("swfl_1.bin"))];
+        let image_path = dir.join("out.bin");
+        let mut image: Vec<u8> = (0..0x30).collect();
+        fs::write(&image_path, &image).unwrap();
+        
+        let options = OutputOptions::default();
+        let changed_flags = |manifest_path: &Path| -> Vec<(String, bool)> {
+            let manifest: serde_json::Value = serde_json::from_str(&fs::read_to_string(manifest_path).unwrap()).unwrap();
+            manifest["segments"].as_array().unwrap().iter()
+                .map(|entry| (entry["target_start"].as_str().unwrap().to_string(), entry["changed"].as_bool().unwrap()))
+                .collect()
+        };
+        let manifest_path = recalculate_segment_checksums(&image_path, &inputs, &options, &mut |_| {}).unwrap();
+        assert_eq!(manifest_path, checksum_manifest_path(&image_path));
+        assert_eq!(changed_flags(&manifest_path), [("0x00001000".to_string(), false), ("0x00001020".to_string(), false)]);
+        
+        image[0x25] ^= 0xFF;
+        fs::write(&image_path, &image).unwrap();
+        recalculate_segment_checksums(&image_path, &inputs, &options, &mut |_| {}).unwrap();
+        assert_eq!(changed_flags(&manifest_path), [("0x00001000".to_string(), false), ("0x00001020".to_string(), true)]);
+        
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failure_kind_survives_context() {
+        let error = stage(FailureKind::Write)(anyhow::anyhow!("disk full"));
+        assert_eq!(error.to_string(), "disk full");
+        assert_eq!(failure_kind(&error.context("Failed to write output file")), FailureKind::Write);
+        assert_eq!(failure_kind(&anyhow::anyhow!("Output not written: segments overlap")), FailureKind::Validation);
+    }
+
+    #[test]
+    fn segment_files_with_the_same_name_are_kept_apart() {
+        let dir = std::env::temp_dir().join(format!("bmw-virtual-reader-segments-{}", std::process::id()));
+        // Same-named inputs from two PSDZ folders, both with a segment at 0x1000
+        let mut first = segment(0, 0x1000, 4);
+        let mut second = segment(1, 0x1000, 8);
+        first.source = "swfl_1.bin".to_string();
+        second.source = "swfl_1.bin".to_string();
+        let mut warnings = Vec::new();
+        let artifacts = write_segment_directory(&dir, &[first, second], &[], &OutputOptions::default(), &mut |status| {
+            if status.starts_with("Warning") {
+                warnings.push(status.to_string());
+            }
+        }).unwrap();
+        
+        let files: Vec<PathBuf> = artifacts.iter().map(|artifact| artifact.path.clone()).collect();
+        assert_eq!(files, [dir.join("0x00001000_swfl_1.bin"), dir.join("0x00001000_swfl_1_2.bin")]);
+        assert_eq!(fs::metadata(&files[0]).unwrap().len(), 4);
+        assert_eq!(fs::metadata(&files[1]).unwrap().len(), 8);
+        assert_eq!(warnings.len(), 1);
+        let manifest: serde_json::Value = serde_json::from_str(&fs::read_to_string(dir.join("manifest.json")).unwrap()).unwrap();
+        let manifest_files: Vec<&str> = manifest["segments"].as_array().unwrap().iter().map(|entry| entry["file"].as_str().unwrap()).collect();
+        assert_eq!(manifest_files, ["0x00001000_swfl_1.bin", "0x00001000_swfl_1_2.bin"]);
+        
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn cancelled_write_keeps_the_previous_output() {
+        let dir = std::env::temp_dir().join(format!("bmw-virtual-reader-write-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("out.bin");
+        let options = OutputOptions::default();
+        
+        write_output_file(&path, &options, |file| file.write_all(b"first")).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"first");
+        assert!(!partial_path(&path).exists());
+        
+        options.cancel.store(true, std::sync::atomic::Ordering::Relaxed);
+        assert!(write_output_file(&path, &options, |file| file.write_all(b"second")).is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"first");
+        assert!(!partial_path(&path).exists());
+        
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    
+    #[test]
+    fn replace_last_in_unicode_file_names() {
+        let replace = |name: &str, from: &str, to: &str| {
+            replace_last_in_file_name(OsStr::new(name), from, to).map(|name| name.into_string().unwrap())
+        };
+        assert_eq!(replace("swfl_ä.bin.bin", ".bin", ".xml").as_deref(), Some("swfl_ä.bin.xml"));
+        // Multibyte characters directly on both sides of the marker
+        assert_eq!(replace("日本.binü", ".bin", ".xml").as_deref(), Some("日本.xmlü"));
+        assert_eq!(replace("€.bin.€", ".bin.", "_").as_deref(), Some("€_€"));
+        assert_eq!(replace("Ünïcødé", ".bin", ".xml"), None);
+        
+        let long_name = "é".repeat(200) + ".bin";
+        assert_eq!(replace(&long_name, ".bin", ".xml"), Some("é".repeat(200) + ".xml"));
+    }
+    
+    #[test]
+    fn output_filename_from_unicode_name() {
+        let path = PathBuf::from("psdz").join("swfl_1234_Ünïcødé€.bin");
+        assert_eq!(generate_output_filename(&path, DEFAULT_OUTPUT_NAME_TEMPLATE).as_deref(), Some("Ünïcødé€.vr.bin"));
+        assert_eq!(generate_output_filename(&path, "{ecu}_{version}").as_deref(), Some("1234_Ünïcødé€"));
+        
+        let extended = PathBuf::from("swfl_ab€_ü.bin.001_015_000");
+        assert_eq!(generate_output_filename(&extended, "{name}").as_deref(), Some("swfl_ab€_ü_001_015_000"));
+    }
+    
+    #[test]
+    fn output_filename_length_is_limited() {
+        // Two bytes per character, so the limit is reached in bytes long before characters
+        let fits = PathBuf::from(format!("swfl_1234_{}.bin", "é".repeat(124)));
+        assert_eq!(generate_output_filename(&fits, DEFAULT_OUTPUT_NAME_TEMPLATE).map(|name| name.len()), Some(255));
+        
+        let too_long = PathBuf::from(format!("swfl_1234_{}.bin", "é".repeat(125)));
+        assert_eq!(generate_output_filename(&too_long, DEFAULT_OUTPUT_NAME_TEMPLATE), None);
+    }
+}
@@ -0,0 +1,78 @@
+//! Extraction core of BMW Virtual Reader: parsing the XML segment maps, decompressing
+//! segments with libucl and combining BTLD/SWFL files into images. The GUI binary is a
+//! thin layer on top of [`VirtualReader`]; other tools can use it the same way without
+//! building the GUI (`default-features = false`).
+//!
+//! ```no_run
+//! use std::path::{Path, PathBuf};
+//! use bmw_virtual_reader::VirtualReader;
+//! use bmw_virtual_reader::types::OutputOptions;
+//!
+//! let reader = VirtualReader::new("ucl.dll")?;
+//! let inputs = [("SWFL1", PathBuf::from("swfl_1234.bin"))];
+//! for message in reader.extract(&inputs, Path::new("out.bin"), &OutputOptions::default())? {
+//!     println!("{}", message);
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::path::{Path, PathBuf};
+use crate::file_ops::process_files;
+use crate::types::{ExtractionProgress, OutputOptions, RunSummary};
+use crate::ucl_bindings::{UclLibrary, DEFAULT_TRANSIENT_RETRIES};
+
+pub mod file_ops;
+pub mod types;
+pub mod ucl_bindings;
+pub mod xml_parser;
+
+/// A loaded UCL library and everything needed to turn input files into outputs
+pub struct VirtualReader {
+    library: UclLibrary,
+}
+
+impl VirtualReader {
+    /// Load the UCL library at `ucl_path`, validating it and probing the usual NRV2
+    /// decompression functions. Relative paths are resolved against the program directory.
+    pub fn new(ucl_path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::with_library_options(ucl_path, true, None, DEFAULT_TRANSIENT_RETRIES)
+    }
+
+    /// Like `new`, with validation switchable, optionally an exact decompression
+    /// function to use instead of probing, and how often transient decompression
+    /// failures are retried
+    pub fn with_library_options(ucl_path: &str, validate: bool, pinned_symbol: Option<&str>, transient_retries: usize) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self {
+            library: UclLibrary::new(ucl_path, validate, pinned_symbol, transient_retries)?,
+        })
+    }
+
+    /// The loaded library, e.g. for its path and decompression function
+    pub fn library(&self) -> &UclLibrary {
+        &self.library
+    }
+
+    /// Combine `inputs` into `output` as `options` say and return the status messages.
+    /// Each input is a label such as "SWFL1" and a bin file; later inputs overwrite
+    /// earlier ones where segments overlap. Warnings start with "Warning: ".
+    pub fn extract(&self, inputs: &[(&str, PathBuf)], output: &Path, options: &OutputOptions) -> anyhow::Result<Vec<String>> {
+        let mut messages = Vec::new();
+        self.extract_with_progress(inputs, output, options, &mut |status| messages.push(status.to_string()), &mut |_| {})?;
+        Ok(messages)
+    }
+
+    /// Like `extract`, but passing each status message to `status_callback` as it
+    /// happens and the file, segment and share of the run done to `progress_callback`.
+    /// Returns the checksum, size and address range of a combined image, the segments
+    /// taken from each input and the inputs that were left out.
+    pub fn extract_with_progress(
+        &self,
+        inputs: &[(&str, PathBuf)],
+        output: &Path,
+        options: &OutputOptions,
+        status_callback: &mut dyn FnMut(&str),
+        progress_callback: &mut dyn FnMut(&ExtractionProgress)
+    ) -> anyhow::Result<RunSummary> {
+        process_files(inputs, output, options, &self.library, status_callback, progress_callback)
+    }
+}
@@ -1,18 +1,33 @@
+#[cfg(feature = "gui")]
 use eframe::egui;
+#[cfg(feature = "gui")]
 use crate::app::BMWVirtualReaderApp;
+#[cfg(feature = "gui")]
 use crate::ui::*;
-use crate::types::UIMessage;
+use crate::config::AppConfig;
 
+use bmw_virtual_reader::{file_ops, types, ucl_bindings, xml_parser};
+
+mod bench;
+mod cli;
+#[cfg(feature = "control-server")]
+mod control_server;
+// Saving and the recent lists are only used by the window
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
 mod config;
-mod ucl_bindings;
-mod types;
-mod xml_parser;
-mod file_ops;
+#[cfg(feature = "gui")]
+mod project;
+#[cfg(all(feature = "gui", feature = "sample-data"))]
+mod sample_data;
+#[cfg(feature = "gui")]
 mod ui;
+#[cfg(feature = "gui")]
 mod app;
 
+#[cfg(feature = "gui")]
 impl eframe::App for BMWVirtualReaderApp {
     fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        self.stop_extraction();
         if let Err(e) = self.config.save() {
             eprintln!("Failed to save config: {}", e);
         }
@@ -21,7 +36,7 @@ impl eframe::App for BMWVirtualReaderApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         egui::CentralPanel::default().show(ctx, |ui| {
             // Header
-            render_header(ui, &mut self.ui_state.show_settings);
+            render_header(ui, &mut self.ui_state.show_settings, self.reader.is_some(), self.ucl_required, &mut self.ui_state.message_queue);
             
             ui.add_space(5.0);
             ui.separator();
@@ -30,7 +45,8 @@ impl eframe::App for BMWVirtualReaderApp {
             // PSDZ Section
             render_psdz_section(
                 ui,
-                &self.psdz_folder,
+                &self.psdz_folders,
+                self.psdz_scan.as_ref().map(|scan| scan.psdz_path.as_path()),
                 &mut self.ui_state.message_queue
             );
             
@@ -42,9 +58,9 @@ impl eframe::App for BMWVirtualReaderApp {
                 &mut self.ui_state.show_file_browser,
                 &self.available_files,
                 &mut self.ui_state.file_search_filter,
-                &self.ui_state.selected_btld_index,
-                &self.ui_state.selected_swfl1_index,
-                &self.ui_state.selected_swfl2_index,
+                &self.btld_file,
+                &self.swfl1_file,
+                &self.swfl2_file,
                 &mut self.ui_state.message_queue
             );
             
@@ -54,6 +70,8 @@ impl eframe::App for BMWVirtualReaderApp {
                 &self.btld_file,
                 &self.swfl1_file,
                 &self.swfl2_file,
+                &self.ui_state.combine_order,
+                &self.ui_state.disabled_slots,
                 &mut self.ui_state.message_queue
             );
             
@@ -65,6 +83,7 @@ impl eframe::App for BMWVirtualReaderApp {
                 &self.btld_file,
                 &self.swfl1_file,
                 &self.swfl2_file,
+                &mut self.ui_state.manual_paths,
                 &mut self.ui_state.message_queue
             );
             
@@ -74,8 +93,9 @@ impl eframe::App for BMWVirtualReaderApp {
             render_output_configuration(
                 ui,
                 &self.output_file,
-                &mut self.ui_state.desired_size_mb,
-                &mut self.ui_state.use_desired_size,
+                self.output_is_auto,
+                &mut self.ui_state.output_options,
+                &self.config.recent_outputs,
                 &mut self.ui_state.message_queue
             );
             
@@ -85,30 +105,108 @@ impl eframe::App for BMWVirtualReaderApp {
             render_extract_button(
                 ui,
                 self.is_processing,
+                !self.enabled_inputs().is_empty(),
+                self.output_file.is_some(),
+                self.reader.is_some(),
                 &mut self.ui_state.message_queue
             );
             
+            render_file_progress(ui, &self.file_progress);
+            
             ui.add_space(10.0);
             
             // Status
-            render_status(ui, &self.status_message);
+            render_status(ui, &self.status_message, &self.warnings, self.progress.as_ref(), self.last_checksum.as_ref());
+            
+            // Segment Preview Window
+            let selected_labels: Vec<&str> = self.ordered_inputs().into_iter().map(|(label, _)| label).collect();
+            render_segment_preview(
+                ctx,
+                &mut self.ui_state.show_segment_preview,
+                &self.segment_preview,
+                &selected_labels,
+                &mut self.ui_state.output_options.segment_selection,
+                &mut self.config.show_decimal_addresses,
+                &mut self.ui_state.message_queue
+            );
+            
+            // File List Import
+            render_file_list_import(
+                ctx,
+                &mut self.ui_state.show_file_list_import,
+                &mut self.ui_state.file_list_text,
+                &self.ui_state.file_list_problems,
+                &mut self.ui_state.message_queue
+            );
+            
+            // Run Preview
+            render_run_preview(
+                ctx,
+                &mut self.ui_state.show_run_preview,
+                &self.run_preview,
+                self.config.show_decimal_addresses
+            );
+            
+            // Validation Report
+            render_validation_report(
+                ctx,
+                &mut self.ui_state.show_validation_report,
+                &mut self.validation_report,
+                &mut self.ui_state.validation_sort
+            );
+            
+            // XML Inspector
+            render_xml_inspector(
+                ctx,
+                &mut self.ui_state.show_xml_inspector,
+                &self.xml_inspection,
+                &mut self.ui_state.inspected_segment,
+                &mut self.ui_state.xml_inspector_scroll
+            );
+            
+            // First-run UCL setup
+            render_ucl_setup_window(
+                ctx,
+                &mut self.ui_state.show_ucl_setup,
+                &self.ui_state.ucl_candidates,
+                self.ui_state.ucl_search_done,
+                &self.status_message,
+                &mut self.ui_state.message_queue
+            );
             
             // Settings Window
+            let diagnostics = if self.ui_state.show_settings { self.diagnostics() } else { String::new() };
             render_settings_window(
                 ctx,
                 &mut self.ui_state.show_settings,
-                &mut self.config.ucl_library_path,
+                &mut self.config,
+                &self.ucl_path_override,
+                self.ucl_path_check.as_ref(),
+                &diagnostics,
                 &mut self.ui_state.message_queue
             );
         });
         
         // Handle UI messages after rendering
-        self.handle_ui_messages();
+        self.handle_ui_messages(ctx);
+        
+        // Pick up a finished PSDZ scan
+        self.poll_psdz_scan(ctx);
+        
+        // Apply progress and the outcome of a running extraction
+        self.poll_extraction(ctx);
+        
+        // Give feedback on a UCL path typed into the settings
+        self.check_ucl_path_if_due(ctx);
+        
+        // Persist settings changes without waiting for exit
+        self.save_config_if_dirty(ctx);
     }
 }
 
+#[cfg(feature = "gui")]
 impl BMWVirtualReaderApp {
-    fn handle_ui_messages(&mut self) {
+    fn handle_ui_messages(&mut self, ctx: &egui::Context) {
         let messages: Vec<UIMessage> = self.ui_state.message_queue.drain(..).collect();
         
         for message in messages {
@@ -116,15 +214,56 @@ impl BMWVirtualReaderApp {
                 UIMessage::SelectPSDZFolder => {
                     self.select_psdz_folder();
                 }
+                UIMessage::RemovePSDZFolder(index) => {
+                    self.remove_psdz_folder(index);
+                }
+                UIMessage::RescanPSDZFolder(index) => {
+                    self.rescan_psdz_folder(index);
+                }
                 UIMessage::ToggleFileBrowser => {
                     self.ui_state.show_file_browser = !self.ui_state.show_file_browser;
                 }
-                UIMessage::SelectFile(index, file_type) => {
-                    self.select_file_by_index(index, &file_type);
+                UIMessage::CancelPSDZScan => {
+                    self.cancel_psdz_scan();
+                }
+                UIMessage::ValidateAllFiles => {
+                    self.validate_all_files();
+                }
+                UIMessage::SelectFile(path, file_type) => {
+                    self.set_file_path(&file_type, path);
                 }
                 UIMessage::ClearFile(file_type) => {
                     self.clear_file_selection(&file_type);
                 }
+                UIMessage::SetFileEnabled(file_type, enabled) => {
+                    if enabled {
+                        self.ui_state.disabled_slots.remove(&file_type);
+                    } else {
+                        self.ui_state.disabled_slots.insert(file_type);
+                    }
+                }
+                UIMessage::SetFilePath(file_type, path) => {
+                    self.set_file_path(&file_type, path);
+                }
+                UIMessage::ToggleFileListImport => {
+                    self.ui_state.show_file_list_import = !self.ui_state.show_file_list_import;
+                }
+                UIMessage::ImportFileList => {
+                    self.import_file_list();
+                }
+                UIMessage::MoveCombineOrder(from, to) => {
+                    let order = &mut self.ui_state.combine_order;
+                    if from < order.len() && to < order.len() {
+                        let slot = order.remove(from);
+                        order.insert(to, slot);
+                    }
+                }
+                UIMessage::OpenProject => {
+                    self.open_project();
+                }
+                UIMessage::SaveProject => {
+                    self.save_project();
+                }
                 UIMessage::SelectBTLDFile => {
                     self.select_btld_file();
                 }
@@ -137,6 +276,34 @@ impl BMWVirtualReaderApp {
                 UIMessage::SelectOutputFile => {
                     self.select_output_file();
                 }
+                UIMessage::SelectReferenceFile => {
+                    self.select_reference_file();
+                }
+                UIMessage::ClearReferenceFile => {
+                    self.ui_state.output_options.reference_file = None;
+                }
+                UIMessage::SelectBaseImage => {
+                    self.select_base_image();
+                }
+                UIMessage::ClearBaseImage => {
+                    self.ui_state.output_options.base_image = None;
+                }
+                UIMessage::RevealPath(path) => {
+                    if let Err(e) = file_ops::reveal_in_file_manager(&path) {
+                        self.status_message = format!("Error: Failed to open file manager: {}", e);
+                    }
+                }
+                UIMessage::PreviewRun => {
+                    self.preview_run();
+                }
+                UIMessage::RecalculateChecksums => {
+                    self.recalculate_checksums();
+                }
+                UIMessage::CopyCommandLine => {
+                    if let Some(command) = self.command_line() {
+                        ctx.copy_text(command);
+                    }
+                }
                 UIMessage::ExtractFiles => {
                     if let Err(e) = self.process_files() {
                         self.status_message = format!("Error: {}", e);
@@ -145,28 +312,151 @@ impl BMWVirtualReaderApp {
                 UIMessage::ReloadUCLLibrary => {
                     self.reload_ucl_library();
                 }
+                UIMessage::ClearUCLOverride => {
+                    self.ucl_path_override = None;
+                    self.reload_ucl_library();
+                }
+                UIMessage::ResetConfig(keep_recent_folders) => {
+                    self.reset_config(keep_recent_folders);
+                }
+                UIMessage::ConfigChanged => {
+                    self.mark_config_dirty();
+                }
                 UIMessage::BrowseUCLLibrary => {
                     if let Some(new_path) = rfd::FileDialog::new()
                         .add_filter("DLL files", &["dll"])
                         .add_filter("All files", &["*"])
                         .pick_file() 
                     {
-                        self.config.ucl_library_path = new_path.to_string_lossy().to_string();
-                        self.reload_ucl_library();
+                        self.use_ucl_library(&new_path);
                     }
                 }
+                UIMessage::SearchUCLLibrary => {
+                    self.ui_state.ucl_candidates = AppConfig::find_ucl_library_candidates();
+                    self.ui_state.ucl_search_done = true;
+                }
+                UIMessage::UseUCLLibrary(path) => {
+                    self.use_ucl_library(&path);
+                }
+                UIMessage::FinishUCLSetup => {
+                    self.finish_ucl_setup();
+                }
                 UIMessage::SetDesiredSizeMB(size) => {
-                    self.ui_state.desired_size_mb = size;
+                    self.ui_state.output_options.desired_size_mb = size;
+                }
+                UIMessage::OutputNameTemplateChanged => {
+                    self.mark_config_dirty();
+                    self.update_auto_output_path();
+                }
+                UIMessage::OutputLayoutChanged => {
+                    // A path picked for one layout doesn't suit the other
+                    self.output_is_auto = true;
+                    self.update_auto_output_path();
+                    self.remember_selections();
+                }
+                UIMessage::OutputFormatChanged => {
+                    self.update_auto_output_path();
+                }
+                UIMessage::ToggleSegmentPreview => {
+                    self.ui_state.show_segment_preview = !self.ui_state.show_segment_preview;
+                    if self.ui_state.show_segment_preview {
+                        self.refresh_segment_preview();
+                    }
                 }
-                UIMessage::ToggleUseDesiredSize => {
-                    self.ui_state.use_desired_size = !self.ui_state.use_desired_size;
+                UIMessage::RefreshSegmentPreview => {
+                    self.refresh_segment_preview();
+                }
+                UIMessage::InspectXml(label) => {
+                    self.inspect_xml(&label);
+                }
+                UIMessage::CopySegmentHex(label, index) => {
+                    match self.segment_hex_dump(&label, index) {
+                        Ok((hex, description)) => {
+                            ctx.copy_text(hex);
+                            self.status_message = description;
+                        }
+                        Err(e) => self.status_message = format!("Error: Failed to copy segment: {}", e),
+                    }
                 }
             }
         }
     }
 }
 
-fn main() -> Result<(), eframe::Error> {
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    // --ucl-lib is the spelling the command-line mode documents, --ucl the original one
+    let cli_ucl_path = args.iter()
+        .position(|arg| arg == "--ucl" || arg == "--ucl-lib")
+        .and_then(|pos| args.get(pos + 1))
+        .cloned();
+    
+    if let Some(pos) = args.iter().position(|arg| arg == "--bench") {
+        let iterations = args.get(pos + 1)
+            .and_then(|n| n.parse().ok())
+            .unwrap_or(bench::DEFAULT_BENCH_ITERATIONS);
+        let config = AppConfig::load();
+        let (ucl_path, _) = AppConfig::ucl_library_override(cli_ucl_path)
+            .unwrap_or_else(|| config.configured_ucl_library_path());
+        if let Err(e) = bench::run_benchmark(&ucl_path, &config, iterations) {
+            eprintln!("Benchmark failed: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    
+    #[cfg(feature = "control-server")]
+    if let Some(pos) = args.iter().position(|arg| arg == "--serve") {
+        // A socket path on Unix, a port elsewhere
+        let endpoint = args.get(pos + 1)
+            .map(String::as_str)
+            .filter(|arg| !arg.starts_with("--"));
+        let config = AppConfig::load();
+        let (ucl_path, _) = AppConfig::ucl_library_override(cli_ucl_path)
+            .unwrap_or_else(|| config.configured_ucl_library_path());
+        if let Err(e) = config.check_xml_schema() {
+            eprintln!("Warning: {}", e);
+        }
+        if let Err(e) = control_server::run_control_server(&ucl_path, &config, endpoint) {
+            eprintln!("Control server failed: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    
+    if cli::is_cli_run(&args) {
+        let config = AppConfig::load();
+        let (ucl_path, _) = AppConfig::ucl_library_override(cli_ucl_path)
+            .unwrap_or_else(|| config.configured_ucl_library_path());
+        if let Err(e) = config.check_xml_schema() {
+            eprintln!("Warning: {}", e);
+        }
+        let status = cli::run_cli(&args, &ucl_path, &config);
+        if status != cli::ExitStatus::Success {
+            std::process::exit(status.code());
+        }
+        return;
+    }
+    
+    #[cfg(feature = "gui")]
+    if let Err(e) = run_gui(&args, cli_ucl_path) {
+        eprintln!("Failed to open the window: {}", e);
+        std::process::exit(1);
+    }
+    #[cfg(not(feature = "gui"))]
+    {
+        eprintln!("This build has no window; pass the inputs and --output to combine files from the command line");
+        std::process::exit(cli::ExitStatus::Usage.code());
+    }
+}
+
+/// Open the window and run the app until it is closed
+#[cfg(feature = "gui")]
+fn run_gui(args: &[String], cli_ucl_path: Option<String>) -> Result<(), eframe::Error> {
+    let buffer_stats = args.iter().any(|arg| arg == "--buffer-stats");
+    #[cfg(feature = "sample-data")]
+    let load_sample_data = args.iter().any(|arg| arg == "--sample-data");
+    
     let options = eframe::NativeOptions {
         default_theme: eframe::Theme::Dark,
         ..Default::default()
@@ -175,8 +465,13 @@ fn main() -> Result<(), eframe::Error> {
     eframe::run_native(
         "BMW Virtual Reader",
         options,
-        Box::new(|cc| {
-            let app = BMWVirtualReaderApp::new(cc);
+        Box::new(move |cc| {
+            #[allow(unused_mut)]
+            let mut app = BMWVirtualReaderApp::new(cc, cli_ucl_path, buffer_stats);
+            #[cfg(feature = "sample-data")]
+            if load_sample_data {
+                app.load_sample_data();
+            }
             // Set dark theme colors
             cc.egui_ctx.set_visuals(egui::Visuals::dark());
             Box::new(app)
@@ -0,0 +1,91 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use crate::types::OutputOptions;
+
+/// Slot keys a project input may use
+const PROJECT_ROLES: [&str; 3] = ["btld", "swfl1", "swfl2"];
+
+fn default_true() -> bool {
+    true
+}
+
+/// One input of a project
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProjectInput {
+    /// Slot key: "btld", "swfl1" or "swfl2"
+    pub role: String,
+    pub bin: PathBuf,
+    /// Segment map; the .xml next to `bin` when omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub xml: Option<PathBuf>,
+    /// Unchecked inputs stay selected but are left out of a run
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+}
+
+/// Everything needed to repeat one extraction: the inputs in combine order with their
+/// segment maps, the output path and the output options. Unlike config.json, which
+/// holds app-wide settings, a project describes a single run.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProjectFile {
+    pub inputs: Vec<ProjectInput>,
+    /// Picked output path; an auto-generated one is derived again on load
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<PathBuf>,
+    #[serde(default)]
+    pub options: OutputOptions,
+}
+
+impl ProjectFile {
+    /// Read and check a project. Relative paths are relative to the project file, so a
+    /// project can be shared along with the files it names.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .context(format!("Failed to read project file: {}", path.display()))?;
+        let mut project: ProjectFile = serde_json::from_str(&text)
+            .context(format!("Invalid project file: {}", path.display()))?;
+        
+        let base_dir = path.parent().unwrap_or(Path::new("."));
+        let resolve = |path: &mut PathBuf| {
+            if path.is_relative() {
+                *path = base_dir.join(&*path);
+            }
+        };
+        
+        let mut seen_roles = Vec::new();
+        for input in &mut project.inputs {
+            input.role = input.role.to_lowercase();
+            if !PROJECT_ROLES.contains(&input.role.as_str()) {
+                return Err(anyhow::anyhow!("Unknown role '{}' in project, expected btld, swfl1 or swfl2", input.role));
+            }
+            if seen_roles.contains(&input.role) {
+                return Err(anyhow::anyhow!("Role '{}' appears more than once in project", input.role));
+            }
+            seen_roles.push(input.role.clone());
+            
+            resolve(&mut input.bin);
+            if let Some(xml) = &mut input.xml {
+                resolve(xml);
+            }
+        }
+        if let Some(output) = &mut project.output {
+            resolve(output);
+        }
+        if let Some(reference) = &mut project.options.reference_file {
+            resolve(reference);
+        }
+        if let Some(base_image) = &mut project.options.base_image {
+            resolve(base_image);
+        }
+        
+        Ok(project)
+    }
+    
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let text = serde_json::to_string_pretty(self)?;
+        fs::write(path, text)
+            .context(format!("Failed to write project file: {}", path.display()))
+    }
+}
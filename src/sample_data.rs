@@ -0,0 +1,86 @@
+use std::fs;
+use std::path::PathBuf;
+use anyhow::{Context, Result};
+
+/// A synthetic input file: where it goes below `swe`, and its segments as
+/// (source offset, target address, length)
+struct SampleFile {
+    dir: &'static str,
+    bin_name: &'static str,
+    segments: &'static [(u32, u32, u32)],
+}
+
+/// One BTLD and two SWFLs with uncompressed segments only, so they extract without
+/// depending on the UCL variant. SWFL2 overlaps the end of SWFL1 on purpose.
+const SAMPLE_FILES: [SampleFile; 3] = [
+    SampleFile {
+        dir: "btld",
+        bin_name: "btld_00001a2b.bin.001_002_003",
+        segments: &[(0x0000, 0x0000_0000, 0x400)],
+    },
+    SampleFile {
+        dir: "swfl",
+        bin_name: "swfl_00001a2c.bin.004_005_006",
+        segments: &[(0x0000, 0x0001_0000, 0x800), (0x0800, 0x0002_0000, 0x400)],
+    },
+    SampleFile {
+        dir: "swfl",
+        bin_name: "swfl_00001a2d.bin.007_008_009",
+        segments: &[(0x0000, 0x0002_0300, 0x200)],
+    },
+];
+
+/// Recognizable bytes for a segment: its file name as a tag, then a counter
+fn sample_bytes(tag: &str, len: u32) -> Vec<u8> {
+    let mut data: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+    let tag_len = tag.len().min(data.len());
+    data[..tag_len].copy_from_slice(&tag.as_bytes()[..tag_len]);
+    data
+}
+
+fn sample_xml(segments: &[(u32, u32, u32)]) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<FLASH-SEGMENTS>\n");
+    for &(source, target, len) in segments {
+        xml.push_str(&format!(concat!(
+            "  <FLASH-SEGMENT COMPRESSION-STATUS=\"UNCOMPRESSED\">\n",
+            "    <SOURCE-START-ADDRESS>{:08X}</SOURCE-START-ADDRESS>\n",
+            "    <SOURCE-END-ADDRESS>{:08X}</SOURCE-END-ADDRESS>\n",
+            "    <TARGET-START-ADDRESS>{:08X}</TARGET-START-ADDRESS>\n",
+            "    <TARGET-END-ADDRESS>{:08X}</TARGET-END-ADDRESS>\n",
+            "  </FLASH-SEGMENT>\n"),
+            source, source + len - 1, target, target + len - 1));
+    }
+    xml.push_str("</FLASH-SEGMENTS>\n");
+    xml
+}
+
+/// Write a small synthetic PSDZ folder to the temp directory and return its path, so
+/// the UI and the extraction pipeline can be tried without real BMW files
+pub fn write_sample_psdz() -> Result<PathBuf> {
+    let psdz_path = std::env::temp_dir().join("bmw-virtual-reader-sample").join("psdzdata");
+    let swe_path = psdz_path.join("swe");
+    
+    for file in &SAMPLE_FILES {
+        let dir = swe_path.join(file.dir);
+        fs::create_dir_all(&dir)
+            .context(format!("Failed to create sample folder: {}", dir.display()))?;
+        
+        let bin_len = file.segments.iter()
+            .map(|&(source, _, len)| source + len)
+            .max()
+            .unwrap_or(0);
+        let mut bin = vec![0xFF; bin_len as usize];
+        for &(source, _, len) in file.segments {
+            let start = source as usize;
+            bin[start..start + len as usize].copy_from_slice(&sample_bytes(file.bin_name, len));
+        }
+        
+        let xml_name = file.bin_name.replacen(".bin", ".xml", 1);
+        fs::write(dir.join(file.bin_name), bin)
+            .context(format!("Failed to write sample file: {}", file.bin_name))?;
+        fs::write(dir.join(&xml_name), sample_xml(file.segments))
+            .context(format!("Failed to write sample file: {}", xml_name))?;
+    }
+    
+    Ok(psdz_path)
+}
@@ -1,28 +1,146 @@
-use std::path::PathBuf;
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use crate::file_ops::DEFAULT_FLASH_SCRIPT_TEMPLATE;
+use crate::xml_parser::XmlSchema;
 
 #[derive(Debug, Clone)]
 pub struct AvailableFile {
     pub path: PathBuf,
+    /// PSDZ folder the file was found in, as selected by the user
+    pub source_folder: PathBuf,
     pub file_type: FileType,
     pub display_name: String,
+    /// Directory of the file relative to `swe`, such as "swfl" or "istep_2/swfl"
+    pub relative_dir: String,
     pub size: u64,
 }
 
+/// Result of scanning a `swe` directory, with which subdirectories were present
+#[derive(Debug, Clone, Default)]
+pub struct PsdzScan {
+    pub files: Vec<AvailableFile>,
+    pub btld_dir_exists: bool,
+    pub swfl_dir_exists: bool,
+    /// Stopped at `ScanLimits::max_files`
+    pub truncated: bool,
+    /// Stopped at `ScanLimits::deadline`
+    pub timed_out: bool,
+}
+
+/// Bounds for scanning PSDZ folders, which can be very slow on network drives
+#[derive(Debug, Clone)]
+pub struct ScanLimits {
+    pub deadline: Instant,
+    pub max_files: usize,
+    /// How many levels below `swe` are searched for `btld`/`swfl` directories
+    pub max_depth: usize,
+    /// Set from the UI thread to abort the scan, e.g. when another folder was picked
+    pub cancel: Arc<AtomicBool>,
+}
+
+impl ScanLimits {
+    pub fn expired(&self) -> bool {
+        Instant::now() >= self.deadline
+    }
+    
+    pub fn cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum FileType {
     BTLD,
     SWFL,
 }
 
-#[derive(Debug)]
-pub enum FileAction {
-    Clear(String),
-    SelectBTLD(usize),
-    SelectSWFL1(usize),
-    SelectSWFL2(usize),
+/// Outcome of checking a file without extracting it, worst first so sorting
+/// by status puts problems at the top
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ValidationStatus {
+    MissingXml,
+    InvalidXml,
+    Suspicious,
+    Ok,
+}
+
+impl std::fmt::Display for ValidationStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValidationStatus::MissingXml => write!(f, "Missing XML"),
+            ValidationStatus::InvalidXml => write!(f, "Invalid XML"),
+            ValidationStatus::Suspicious => write!(f, "Suspicious"),
+            ValidationStatus::Ok => write!(f, "OK"),
+        }
+    }
 }
 
-#[derive(Debug)]
+/// Whether a UCL library path typed into the settings would load, checked without
+/// replacing the library in use
+#[derive(Debug, Clone)]
+pub struct UclPathCheck {
+    pub path: String,
+    /// The pinned decompression function setting the check was made with
+    pub pinned_symbol: String,
+    /// The decompression symbol the library resolves, or why it can't be used
+    pub result: Result<String, String>,
+}
+
+/// Preflight result for one available file
+#[derive(Debug, Clone)]
+pub struct FileValidation {
+    pub path: PathBuf,
+    pub display_name: String,
+    pub file_type: FileType,
+    pub status: ValidationStatus,
+    pub segment_count: usize,
+    /// Why the file isn't OK, empty if it is
+    pub detail: String,
+}
+
+/// Column the validation report is sorted by
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValidationSort {
+    Name,
+    Type,
+    Status,
+    Segments,
+}
+
+/// A segment a run would include, as listed by a dry run
+#[derive(Debug, Clone)]
+pub struct PreviewSegment {
+    pub label: String,
+    /// Index of the segment in its file's XML
+    pub index: usize,
+    pub segment: FlashSegment,
+    /// Where the segment would go in the output and how many bytes it would take there
+    pub addr: u32,
+    pub len: u64,
+}
+
+/// What a run with the given inputs and options would write, worked out from the
+/// segment maps alone
+#[derive(Debug, Clone, Default)]
+pub struct RunPreview {
+    /// Included segments in output address order
+    pub segments: Vec<PreviewSegment>,
+    /// What the run would warn about ("Warning: ") or fail on ("Error: ")
+    pub problems: Vec<String>,
+    /// Ranges (inclusive) between the first and last segment that no segment covers
+    pub gaps: Vec<(u32, u32)>,
+    /// Ranges (inclusive) that more than one segment writes
+    pub overlaps: Vec<(u32, u32)>,
+    /// Bytes of image data in a combined output after padding, without a vendor
+    /// header; `None` for a segment directory or when it can't be worked out
+    pub image_size: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
 pub struct FlashSegment {
     pub source_start_addr: u32,
     pub source_end_addr: u32,
@@ -31,19 +149,409 @@ pub struct FlashSegment {
     pub is_compressed: bool,
 }
 
-#[derive(Debug)]
-pub enum UIMessage {
-    SelectPSDZFolder,
-    ToggleFileBrowser,
-    SelectFile(usize, String), // index, file_type
-    ClearFile(String),
-    SelectBTLDFile,
-    SelectSWFL1File,
-    SelectSWFL2File,
-    SelectOutputFile,
-    ExtractFiles,
-    ReloadUCLLibrary,
-    BrowseUCLLibrary,
-    SetDesiredSizeMB(f32),
-    ToggleUseDesiredSize,
-} 
\ No newline at end of file
+impl FlashSegment {
+    /// Bytes the segment occupies in the input file; 64 bits wide because a range over
+    /// the whole 32-bit address space is 2^32 bytes
+    pub fn source_len(&self) -> u64 {
+        (self.source_end_addr as u64).saturating_sub(self.source_start_addr as u64) + 1
+    }
+    
+    /// Bytes the segment declares at its target address
+    pub fn target_len(&self) -> u64 {
+        (self.target_end_addr as u64).saturating_sub(self.target_start_addr as u64) + 1
+    }
+}
+
+/// How the combined output is padded after the segments are laid out.
+/// The modes are mutually exclusive; padding never truncates the natural buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PadMode {
+    /// Use the natural size of the combined segments
+    Natural,
+    /// Pad up to `OutputOptions::desired_size_mb`
+    DesiredSize,
+    /// Pad up to the next power of two (a buffer that is already a power of two is kept)
+    NextPowerOfTwo,
+}
+
+/// What happens when a segment's extracted length differs from the XML target size
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SizeMismatchPolicy {
+    /// Keep the data as extracted and warn
+    WarnAndKeep,
+    /// Cut over-long segments to the target size; short ones are kept with a warning
+    TruncateToTarget,
+    /// Fail the file on any mismatch
+    Reject,
+}
+
+/// Where still-compressed segments are placed when `OutputOptions::extract_compressed` is set
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RawSegmentAddress {
+    /// At the flash address from the XML; the packed data is shorter than the range it
+    /// decompresses to, so the rest of that range is gap fill
+    Target,
+    /// Every segment, compressed or not, at the offset it has in the input file, which
+    /// reconstructs the input's layout
+    Source,
+}
+
+/// What `process_files` writes to the output path
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputLayout {
+    /// A single binary with every segment at its target offset
+    CombinedImage,
+    /// A directory with one `.bin` per segment plus `manifest.json` and `segments.map`
+    SegmentDirectory,
+}
+
+/// File format of a combined image
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    /// The image as plain bytes from the lowest address on
+    RawBinary,
+    /// Intel HEX records for the addresses the segments cover, leaving the gaps out
+    IntelHex,
+    /// Motorola S-records for the addresses the segments cover, leaving the gaps out
+    Srec,
+}
+
+impl OutputFormat {
+    /// Extension of auto-generated output names in this format
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::RawBinary => "bin",
+            OutputFormat::IntelHex => "hex",
+            OutputFormat::Srec => "srec",
+        }
+    }
+    
+    /// The format the extension of `path` names: `.hex` and `.ihex` Intel HEX, `.srec`,
+    /// `.s19`, `.s28` and `.s37` S-records, anything else a raw binary
+    pub fn from_extension(path: &Path) -> Self {
+        let extension = path.extension()
+            .map(|extension| extension.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default();
+        match extension.as_str() {
+            "hex" | "ihex" => OutputFormat::IntelHex,
+            "srec" | "s19" | "s28" | "s37" => OutputFormat::Srec,
+            _ => OutputFormat::RawBinary,
+        }
+    }
+}
+
+impl std::fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OutputFormat::RawBinary => write!(f, "raw binary"),
+            OutputFormat::IntelHex => write!(f, "Intel HEX"),
+            OutputFormat::Srec => write!(f, "Motorola S-record"),
+        }
+    }
+}
+
+/// Checksum reported over a combined image, to compare with what a flashing tool expects
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChecksumAlgorithm {
+    /// CRC-32 as used by zip and most tools (IEEE 802.3 polynomial, reflected)
+    Crc32,
+    /// CRC-16/CCITT-FALSE: polynomial 0x1021, initial value 0xFFFF, not reflected
+    Crc16Ccitt,
+}
+
+impl std::fmt::Display for ChecksumAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChecksumAlgorithm::Crc32 => write!(f, "CRC32"),
+            ChecksumAlgorithm::Crc16Ccitt => write!(f, "CRC16-CCITT"),
+        }
+    }
+}
+
+/// A checksum over the image bytes of a combined output
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImageChecksum {
+    pub algorithm: ChecksumAlgorithm,
+    pub value: u32,
+    /// Number of bytes checked: the image after padding, without a vendor header
+    pub len: usize,
+}
+
+impl std::fmt::Display for ImageChecksum {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.algorithm {
+            ChecksumAlgorithm::Crc32 => write!(f, "{} 0x{:08X}", self.algorithm, self.value),
+            ChecksumAlgorithm::Crc16Ccitt => write!(f, "{} 0x{:04X}", self.algorithm, self.value),
+        }
+    }
+}
+
+/// The stage a run or an input failed in, for callers that branch on the outcome
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureKind {
+    /// An input or its segment map couldn't be read or parsed
+    Parse,
+    /// A segment didn't decompress to anything near its declared size
+    Decompress,
+    /// The output or one of the files written next to it couldn't be written
+    Write,
+    /// The run was refused before writing, e.g. overlapping segments or an output too large
+    Validation,
+}
+
+impl std::fmt::Display for FailureKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FailureKind::Parse => write!(f, "parse error"),
+            FailureKind::Decompress => write!(f, "decompression error"),
+            FailureKind::Write => write!(f, "write error"),
+            FailureKind::Validation => write!(f, "validation failure"),
+        }
+    }
+}
+
+/// An input that couldn't be processed; the run goes on with the others
+#[derive(Debug, Clone, PartialEq)]
+pub struct FailedInput {
+    pub label: String,
+    pub error: String,
+    pub kind: FailureKind,
+}
+
+/// Outcome of a run that wrote its output, for callers that act on it rather than on
+/// the status messages
+#[derive(Debug, Clone, Default)]
+pub struct RunSummary {
+    /// Checksum of a combined image, `None` for a segment directory
+    pub checksum: Option<ImageChecksum>,
+    /// Inputs left out of the output, in combine order
+    pub failed_inputs: Vec<FailedInput>,
+    /// Segments taken from each processed input, by label in combine order
+    pub segment_counts: Vec<(String, usize)>,
+    /// Bytes written to a combined image including any vendor header, `None` for a
+    /// segment directory
+    pub output_size: Option<u64>,
+    /// First and last address of a combined image, `None` for a segment directory
+    pub address_range: Option<(u32, u32)>,
+}
+
+/// How far a run is, as passed to progress callbacks. Each input file is read in turn,
+/// then the segments are copied into the output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtractionProgress {
+    /// Input label such as "SWFL1", or "Output" while the output is written
+    pub label: String,
+    pub file_name: String,
+    /// Index of the segment being processed and how many the file has
+    pub segment: usize,
+    pub segment_count: usize,
+    /// Share of the current file done, by source bytes read or bytes written
+    pub fraction: f32,
+    /// Share of the whole run done
+    pub overall: f32,
+}
+
+impl std::fmt::Display for ExtractionProgress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.label, self.file_name)?;
+        if self.segment_count > 0 {
+            write!(f, ", segment {} of {}", self.segment + 1, self.segment_count)?;
+        }
+        write!(f, " ({:.0}%)", self.overall * 100.0)
+    }
+}
+
+/// Byte order of the multi-byte fields written into an output. It differs between ECU
+/// families (PowerPC based ones are big-endian, TriCore based ones little-endian), so it
+/// is always chosen explicitly rather than taken from the host.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Endianness {
+    Big,
+    Little,
+}
+
+impl Endianness {
+    pub fn encode_u32(self, value: u32) -> [u8; 4] {
+        match self {
+            Endianness::Big => value.to_be_bytes(),
+            Endianness::Little => value.to_le_bytes(),
+        }
+    }
+}
+
+impl std::fmt::Display for Endianness {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Endianness::Big => write!(f, "big-endian"),
+            Endianness::Little => write!(f, "little-endian"),
+        }
+    }
+}
+
+/// Fixed block written in front of a combined image for tools that expect one: the
+/// magic bytes, then each enabled field as a 32-bit integer in `OutputOptions::byte_order`
+/// (version, base address, payload length). The length excludes the header itself.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HeaderSpec {
+    pub magic: Vec<u8>,
+    pub version: Option<u32>,
+    pub include_base_addr: bool,
+    pub include_length: bool,
+}
+
+impl Default for HeaderSpec {
+    fn default() -> Self {
+        Self {
+            magic: b"BMWV".to_vec(),
+            version: Some(1),
+            include_base_addr: true,
+            include_length: true,
+        }
+    }
+}
+
+/// Per-run output settings. Saved in project files, except for the fields that come
+/// from the app config.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OutputOptions {
+    pub layout: OutputLayout,
+    /// File format of a combined image
+    pub format: OutputFormat,
+    /// Checksum reported over a combined image
+    pub checksum: ChecksumAlgorithm,
+    pub pad_mode: PadMode,
+    pub desired_size_mb: f32,
+    /// Byte written between segments inside the combined range
+    pub gap_fill: u8,
+    /// Byte appended by the pad modes
+    pub pad_fill: u8,
+    /// Known-good image that sets the output length and is diffed against afterwards
+    pub reference_file: Option<PathBuf>,
+    /// Address of the reference's first byte; `None` uses the lowest segment address
+    pub reference_file_addr: Option<u32>,
+    /// Existing flash dump the segments are overlaid onto instead of gap fill; it sets
+    /// the output length
+    pub base_image: Option<PathBuf>,
+    /// Address of the base image's first byte; `None` uses the lowest segment address
+    pub base_image_addr: Option<u32>,
+    /// Cap segment sizes, time out decompression and reject anything suspicious
+    #[serde(skip)]
+    pub safe_mode: bool,
+    pub size_policy: SizeMismatchPolicy,
+    /// Segment indices to extract per input label, e.g. "2-5,8"; missing or empty means all
+    pub segment_selection: HashMap<String, String>,
+    /// Header written before a combined image, none by default
+    pub header: Option<HeaderSpec>,
+    /// Byte order of every multi-byte field written into the output; currently the
+    /// vendor header's version, base address and length
+    pub byte_order: Endianness,
+    /// Also write the address ranges no segment covers, for flashers that skip them
+    pub holes_report: bool,
+    /// Also write a CSV with one row per segment, for spreadsheets
+    pub segments_csv: bool,
+    /// Also write a flashing script listing each written file and its address
+    pub flash_script: bool,
+    /// Head Intel HEX and S-record outputs and `segments.map` with the tool version, time,
+    /// inputs and UCL function; off by default since strict HEX parsers reject comments
+    pub text_banner: bool,
+    /// Template the flashing script is expanded from, see `validate_flash_script_template`
+    #[serde(skip)]
+    pub flash_script_template: String,
+    /// Uncompressed segments larger than this many bytes are copied from the input
+    /// while writing instead of being buffered; `None` buffers everything
+    #[serde(skip)]
+    pub streaming_threshold: Option<usize>,
+    /// Fail a combined image whose share of bytes other than the gap fill is at or below
+    /// this percentage; `Some(0.0)` only rejects images that are entirely fill. Not
+    /// checked over a base image, which has no gap fill.
+    pub min_real_data_percent: Option<f32>,
+    /// Write a combined image whose segments overlap, later files winning; off refuses
+    /// it. Overlaps are reported either way.
+    pub allow_overlap: bool,
+    /// Copy compressed segments as they are instead of decompressing them
+    pub extract_compressed: bool,
+    /// Address raw compressed segments are written at, see `RawSegmentAddress`
+    pub raw_segment_address: RawSegmentAddress,
+    /// Write a combined image as `<output>.part001`, `.part002`, ... of this many KB
+    /// each plus a `.parts.json` index, instead of a single file
+    pub split_part_kb: Option<u32>,
+    /// Add decimal address fields next to the hex ones in segment manifests
+    #[serde(skip)]
+    pub decimal_addresses: bool,
+    /// Segment map per input label where it isn't the `.xml` next to the bin file
+    #[serde(skip)]
+    pub xml_paths: HashMap<String, PathBuf>,
+    /// Element names the segment maps are parsed with
+    #[serde(skip)]
+    pub xml_schema: XmlSchema,
+    /// Set from another thread to stop the run before the next file or segment; once
+    /// set, nothing more is written
+    #[serde(skip)]
+    pub cancel: Arc<AtomicBool>,
+}
+
+impl OutputOptions {
+    pub fn cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        Self {
+            layout: OutputLayout::CombinedImage,
+            format: OutputFormat::RawBinary,
+            checksum: ChecksumAlgorithm::Crc32,
+            pad_mode: PadMode::Natural, // Default to the natural size without padding
+            desired_size_mb: 4.0, // Default to 4.0 MB
+            gap_fill: 0x00,
+            pad_fill: 0x00,
+            reference_file: None,
+            reference_file_addr: None,
+            base_image: None,
+            base_image_addr: None,
+            safe_mode: false,
+            size_policy: SizeMismatchPolicy::WarnAndKeep,
+            segment_selection: HashMap::new(),
+            header: None,
+            byte_order: Endianness::Big,
+            holes_report: false,
+            segments_csv: false,
+            flash_script: false,
+            text_banner: false,
+            flash_script_template: DEFAULT_FLASH_SCRIPT_TEMPLATE.to_string(),
+            streaming_threshold: None,
+            min_real_data_percent: None,
+            allow_overlap: true,
+            extract_compressed: false,
+            raw_segment_address: RawSegmentAddress::Target,
+            split_part_kb: None,
+            decimal_addresses: false,
+            xml_paths: HashMap::new(),
+            xml_schema: XmlSchema::default(),
+            cancel: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+/// Namespace-stripped XML of one input next to what the parser made of it
+#[derive(Debug, Clone)]
+pub struct XmlInspection {
+    pub label: String,
+    pub xml_path: PathBuf,
+    pub xml_text: String,
+    pub segments: Vec<FlashSegment>,
+    pub parse_error: Option<String>,
+    /// Line range (0-based, inclusive) of each FLASH-SEGMENT element in `xml_text`
+    pub segment_rows: Vec<(usize, usize)>,
+}
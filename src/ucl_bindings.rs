@@ -1,9 +1,13 @@
 use std::convert::TryInto;
 use std::mem;
+use std::path::{Path, PathBuf};
 use std::ptr;
-use std::sync::atomic::{AtomicBool, Ordering};
-use libc::{c_int, c_long, c_short, c_uint, c_void};
-use libloading::{Library, Symbol};
+use std::sync::{mpsc, Arc};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
+use libc::{c_char, c_int, c_long, c_short, c_uint, c_void};
+use libloading::Library;
+use crate::file_ops::get_program_directory;
 
 const UCL_VERSION: u32 = 0x01_0300;
 
@@ -28,16 +32,87 @@ type UclDecompressFn = unsafe extern "C" fn(
     wrkmem: *const c_void,
 ) -> c_int;
 
+/// `ucl_nrv2*_99_compress`; these allocate their own work memory, so unlike the
+/// 1x compressors they take none
+type UclCompressFn = unsafe extern "C" fn(
+    src: *const u8,
+    src_len: c_uint,
+    dst: *mut u8,
+    dst_len: *mut c_uint,
+    progress_callback: *const c_void,
+    level: c_int,
+    config: *const c_void,
+    result: *mut c_uint,
+) -> c_int;
+
+type UclVersionStringFn = unsafe extern "C" fn() -> *const c_char;
+
 static INITIALIZED: AtomicBool = AtomicBool::new(false);
 
+/// Labels for the buffer sizes `decompress` tries, in order, followed by the exact
+/// expected size `decompress_into` uses
+pub const BUFFER_SIZE_TIERS: [&str; 6] = ["20x input", "50x input", "100x input", "10 MB", "50 MB", "expected size"];
+const EXPECTED_SIZE_TIER: usize = 5;
+
+/// Largest output buffer allocated for one decompression attempt
+const MAX_BUFFER_SIZE: usize = 200 * 1024 * 1024;
+
+/// How often `decompress` and `decompress_into` repeat an attempt that failed with a
+/// transient error, unless the library is loaded with another count
+pub const DEFAULT_TRANSIENT_RETRIES: usize = 2;
+
+/// Expected output of the validation streams below
+const VALIDATION_PAYLOAD: &[u8] = b"BMW Virtual Reader";
+
+/// Literal-only NRV2B stream (8-bit variant) decoding to `VALIDATION_PAYLOAD`
+const NRV2B_VALIDATION_BLOB: &[u8] = &[
+    0xFF, 0x42, 0x4D, 0x57, 0x20, 0x56, 0x69, 0x72, 0x74, 0xFF, 0x75, 0x61, 0x6C, 0x20,
+    0x52, 0x65, 0x61, 0x64, 0xC0, 0x65, 0x72, 0x00, 0x00, 0x00, 0x00, 0x01, 0x20, 0xFF,
+];
+
+/// Literal-only NRV2D/NRV2E stream (8-bit variants) decoding to `VALIDATION_PAYLOAD`.
+/// Both variants share the same literal and end-of-stream encoding.
+const NRV2DE_VALIDATION_BLOB: &[u8] = &[
+    0xFF, 0x42, 0x4D, 0x57, 0x20, 0x56, 0x69, 0x72, 0x74, 0xFF, 0x75, 0x61, 0x6C, 0x20,
+    0x52, 0x65, 0x61, 0x64, 0xC2, 0x65, 0x72, 0x49, 0x24, 0x92, 0x4A, 0x80, 0xFF,
+];
+
+/// A loaded libucl and the functions resolved from it.
+///
+/// The functions are plain `extern "C" fn` pointers copied out of the libloading
+/// symbols. They are only valid while the library stays loaded, which `library`
+/// guarantees: the pointers are private, every call goes through `&self` (or, for the
+/// detached watchdog worker, a clone of the `Arc`), so the library is unloaded only
+/// after the last call that could use them has returned. Function pointers and
+/// `Library` are `Send` and `Sync`, and the UCL functions keep no state between calls
+/// apart from `__ucl_init2`, which runs once under `INITIALIZED`, so the struct can be
+/// shared with worker threads.
 #[derive(Debug)]
 pub struct UclLibrary {
-    library: Library,
-    init_fn: Option<Symbol<'static, UclInit2Fn>>,
-    decompress_fn: Option<Symbol<'static, UclDecompressFn>>,
+    library: Arc<Library>,
+    /// The path the library was loaded from, after `resolve_library_path`
+    path: PathBuf,
+    init_fn: Option<UclInit2Fn>,
+    decompress_fn: UclDecompressFn,
+    decompress_symbol: String,
+    /// The bounds-checked counterpart of `decompress_fn` (or `decompress_fn` itself when
+    /// it already is one), if the library exports it; required by safe mode
+    safe_decompress_fn: Option<UclDecompressFn>,
+    /// Compressor producing what `decompress_fn` reads, if the library exports it
+    compress_fn: Option<UclCompressFn>,
+    compress_symbol: Option<String>,
+    /// What `ucl_version_string` reported, if the library exports it
+    version: Option<String>,
+    /// How often an attempt that failed with a transient error is repeated
+    transient_retries: AtomicUsize,
+    /// Number of output buffers allocated for decompression attempts, for benchmarking
+    buffer_allocations: AtomicUsize,
+    /// Which buffer size successful decompressions ended up using, collected only when enabled
+    collect_buffer_stats: AtomicBool,
+    buffer_tier_hits: [AtomicUsize; 6],
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum UclErrorKind {
     GenericError,
     InvalidArgument,
@@ -51,7 +126,10 @@ pub enum UclErrorKind {
     OverlapOverrun,
     SrcTooLarge,
     DstTooLarge,
+    /// Never returned, libucl reports a destination that is too small as `OutputOverrun`
     DstTooSmall,
+    /// Rejected by `check_stream_complete` without calling into the library
+    Truncated,
 }
 
 impl std::fmt::Display for UclErrorKind {
@@ -70,6 +148,7 @@ impl std::fmt::Display for UclErrorKind {
             UclErrorKind::SrcTooLarge => write!(f, "src buffer too large"),
             UclErrorKind::DstTooLarge => write!(f, "dst buffer too large"),
             UclErrorKind::DstTooSmall => write!(f, "dst buffer too small"),
+            UclErrorKind::Truncated => write!(f, "segment appears truncated, the compressed stream has no end-of-stream marker"),
         }
     }
 }
@@ -77,6 +156,9 @@ impl std::fmt::Display for UclErrorKind {
 impl std::error::Error for UclErrorKind {}
 
 impl UclErrorKind {
+    /// Map a UCL return code. The library reports a destination that is too small as
+    /// `UCL_E_OUTPUT_OVERRUN` (-202) and has no codes for oversized buffers, so
+    /// `SrcTooLarge`/`DstTooLarge` only come from our own length conversions.
     fn from_code(code: i32) -> Self {
         match code {
             -2 => UclErrorKind::InvalidArgument,
@@ -91,66 +173,419 @@ impl UclErrorKind {
             _ => UclErrorKind::GenericError,
         }
     }
+    
+    /// Failures that aren't caused by the data or the buffer size and may not happen
+    /// again on the same attempt. Everything else is deterministic for a given input.
+    fn is_transient(&self) -> bool {
+        matches!(self, UclErrorKind::GenericError | UclErrorKind::OutOfMemory)
+    }
 }
 
-impl UclLibrary {
-    pub fn new(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
-        let library = unsafe { Library::new(path)? };
+/// The NRV2 stream formats, which differ in how match offsets and lengths are coded
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Nrv2Variant {
+    B,
+    D,
+    E,
+}
+
+/// Reads an 8-bit NRV2 stream the way UCL's decoders do: flag bits MSB first from
+/// bytes that are interleaved with the literal and offset bytes
+struct BitReader<'a> {
+    input: &'a [u8],
+    pos: usize,
+    bb: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(input: &'a [u8]) -> Self {
+        Self { input, pos: 0, bb: 0 }
+    }
+    
+    /// Port of UCL's 8-bit `getbit`; the low bit marks how much of the flag byte is left
+    fn bit(&mut self) -> Result<u32, UclErrorKind> {
+        if self.bb & 0x7f != 0 {
+            self.bb *= 2;
+        } else {
+            self.bb = u32::from(self.byte()?) * 2 + 1;
+        }
+        Ok((self.bb >> 8) & 1)
+    }
+    
+    fn byte(&mut self) -> Result<u8, UclErrorKind> {
+        let byte = *self.input.get(self.pos).ok_or(UclErrorKind::Truncated)?;
+        self.pos += 1;
+        Ok(byte)
+    }
+    
+    /// Skip an Elias-gamma style number: value bits, each followed by a stop bit
+    fn skip_gamma(&mut self) -> Result<(), UclErrorKind> {
+        loop {
+            self.bit()?;
+            if self.bit()? == 1 {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Length of the 8-bit NRV2 stream at the start of `input`, up to and including its
+/// end-of-stream marker. Reads the literal, offset and length codes exactly like the
+/// decoder, with the same wrapping 32-bit offset arithmetic, but produces no output,
+/// so it ends where the library would. Running out of input first means the stream is
+/// truncated. Offsets are not checked against the output; that is left to the library.
+fn nrv2_stream_len(input: &[u8], variant: Nrv2Variant) -> Result<usize, UclErrorKind> {
+    let mut reader = BitReader::new(input);
+    loop {
+        // Literals
+        while reader.bit()? == 1 {
+            reader.byte()?;
+        }
+        
+        let mut m_off: u32 = 1;
+        loop {
+            m_off = m_off.wrapping_mul(2).wrapping_add(reader.bit()?);
+            if reader.bit()? == 1 {
+                break;
+            }
+            if variant != Nrv2Variant::B {
+                m_off = m_off.wrapping_sub(1).wrapping_mul(2).wrapping_add(reader.bit()?);
+            }
+        }
         
-        // Load the required functions
-        let init_fn = unsafe {
-            match library.get::<UclInit2Fn>(b"__ucl_init2") {
-                Ok(f) => Some(std::mem::transmute(f)),
-                Err(_) => None,
+        // An offset code of 2 repeats the last offset, any other is completed by an
+        // offset byte. NRV2D/E take the first length bit from there.
+        let len_bit = if m_off == 2 {
+            match variant {
+                Nrv2Variant::B => None,
+                Nrv2Variant::D | Nrv2Variant::E => Some(reader.bit()?),
+            }
+        } else {
+            let offset = m_off.wrapping_sub(3).wrapping_mul(256).wrapping_add(u32::from(reader.byte()?));
+            if offset == u32::MAX {
+                return Ok(reader.pos);
             }
+            Some((offset ^ u32::MAX) & 1)
         };
         
-        // Try different decompression function names
-        let decompress_fn = unsafe {
-            let function_names: &[&[u8]] = &[
-                b"ucl_nrv2b_decompress_safe_8",
-                b"ucl_nrv2d_decompress_safe_8", 
-                b"ucl_nrv2e_decompress_safe_8",
-                b"ucl_nrv2b_decompress_8",
-                b"ucl_nrv2d_decompress_8",
-                b"ucl_nrv2e_decompress_8",
-            ];
-            
-            let mut found_fn = None;
-            for &func_name in function_names {
-                match library.get::<UclDecompressFn>(func_name) {
-                    Ok(f) => {
-                        found_fn = Some(std::mem::transmute(f));
-                        break;
-                    }
-                    Err(_) => continue,
+        match variant {
+            Nrv2Variant::B => {
+                let m_len = reader.bit()? * 2 + reader.bit()?;
+                if m_len == 0 {
+                    reader.skip_gamma()?;
                 }
             }
-            found_fn
+            Nrv2Variant::D => {
+                let m_len = len_bit.unwrap_or(0) * 2 + reader.bit()?;
+                if m_len == 0 {
+                    reader.skip_gamma()?;
+                }
+            }
+            Nrv2Variant::E => {
+                // After a zero length bit, a flag picks one more bit or a gamma code
+                if len_bit == Some(1) || reader.bit()? == 1 {
+                    reader.bit()?;
+                } else {
+                    reader.skip_gamma()?;
+                }
+            }
+        }
+    }
+}
+
+/// Check that `input` holds a complete stream before it is handed to the library. The
+/// unsafe decompress variants don't bounds-check the input and can read past the end
+/// of a truncated stream. Trailing data after the end-of-stream marker is allowed.
+fn check_stream_complete(input: &[u8], variant: Nrv2Variant) -> Result<(), UclErrorKind> {
+    nrv2_stream_len(input, variant).map(|_| ())
+}
+
+/// Encode `data` as a stored (literal-only) stream for `variant`
+fn encode_stored_stream(data: &[u8], variant: Nrv2Variant) -> Vec<u8> {
+    let mut writer = BitWriter::new(data.len() + data.len() / 8 + 16);
+    
+    for &byte in data {
+        writer.put_bit(1);
+        writer.put_byte(byte);
+    }
+    writer.put_bit(0);
+    
+    // End of stream is a match offset of 0x1000000 followed by 0xFF, written with
+    // the same prefix codes as UCL's own compressors
+    if variant == Nrv2Variant::B {
+        writer.put_prefix_ss11(0x100_0000);
+    } else {
+        writer.put_prefix_ss12(0x100_0000);
+    }
+    writer.put_byte(0xFF);
+    
+    writer.out
+}
+
+/// Reject inputs too small to be a stream or too large to be a segment
+fn check_input_size(input: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+    if input.is_empty() {
+        return Err("Input data is empty".into());
+    }
+    if input.len() < 4 {
+        return Err("Input data too small (less than 4 bytes)".into());
+    }
+    if input.len() > 100 * 1024 * 1024 {
+        return Err(format!("Input data too large: {} bytes", input.len()).into());
+    }
+    Ok(())
+}
+
+/// Append the attempts that led up to a failure to its message
+fn with_attempts(message: String, attempts: &[String]) -> Box<dyn std::error::Error> {
+    if attempts.is_empty() {
+        message.into()
+    } else {
+        format!("{} (attempts: {})", message, attempts.join("; ")).into()
+    }
+}
+
+/// Decompression functions in order of preference; the safe variants bounds-check
+/// the input
+const DECOMPRESS_SYMBOLS: [&str; 6] = [
+    "ucl_nrv2b_decompress_safe_8",
+    "ucl_nrv2d_decompress_safe_8", 
+    "ucl_nrv2e_decompress_safe_8",
+    "ucl_nrv2b_decompress_8",
+    "ucl_nrv2d_decompress_8",
+    "ucl_nrv2e_decompress_8",
+];
+
+/// The first of `DECOMPRESS_SYMBOLS` the library exports, or exactly `pinned` when given
+fn find_decompress_symbol(library: &Library, pinned: Option<&str>) -> Result<(UclDecompressFn, String), Box<dyn std::error::Error>> {
+    if let Some(pinned) = pinned {
+        return unsafe { library.get::<UclDecompressFn>(pinned.as_bytes()) }
+            .map(|f| (*f, pinned.to_string()))
+            .map_err(|_| format!("Configured decompression function {} not found in library", pinned).into());
+    }
+    DECOMPRESS_SYMBOLS.iter()
+        .find_map(|&func_name| unsafe {
+            library.get::<UclDecompressFn>(func_name.as_bytes())
+                .ok()
+                .map(|f| (*f, func_name.to_string()))
+        })
+        .ok_or_else(|| "No compatible UCL decompression function found in library".into())
+}
+
+/// The bounds-checked `_safe_` counterpart of `decompress_symbol`, such as
+/// `ucl_nrv2b_decompress_safe_8` for `ucl_nrv2b_decompress_8`
+fn safe_symbol_for(decompress_symbol: &str) -> String {
+    if decompress_symbol.contains("_safe") {
+        decompress_symbol.to_string()
+    } else {
+        decompress_symbol.replacen("_decompress_", "_decompress_safe_", 1)
+    }
+}
+
+/// The `_99` compressor for the NRV2 variant `decompress_symbol` decodes, if it reads
+/// the 8-bit stream format the compressors write
+fn compress_symbol_for(decompress_symbol: &str) -> Option<String> {
+    if !decompress_symbol.ends_with("_8") {
+        return None;
+    }
+    ["nrv2b", "nrv2d", "nrv2e"].iter()
+        .find(|variant| decompress_symbol.contains(*variant))
+        .map(|variant| format!("ucl_{}_99_compress", variant))
+}
+
+/// Resolve a relative library path against the program directory, so portable installs
+/// work regardless of the working directory. Falls back to `path` as given (relative to
+/// the working directory) when nothing exists next to the program.
+pub fn resolve_library_path(path: &str) -> PathBuf {
+    let configured = Path::new(path);
+    if configured.is_relative() {
+        let beside_program = get_program_directory().join(configured);
+        if beside_program.is_file() {
+            return beside_program;
+        }
+    }
+    configured.to_path_buf()
+}
+
+impl UclLibrary {
+    /// Check that `path` loads and exports a usable decompression function without
+    /// initializing or keeping the library. Returns the symbol that would be used.
+    pub fn probe(path: &str, pinned_symbol: Option<&str>) -> Result<String, Box<dyn std::error::Error>> {
+        let library = unsafe { Library::new(resolve_library_path(path))? };
+        find_decompress_symbol(&library, pinned_symbol)
+            .map(|(_, symbol)| symbol)
+    }
+    
+    /// Load the library at `path`. The decompression function is auto-probed from
+    /// `DECOMPRESS_SYMBOLS` unless `pinned_symbol` names the exact export to use.
+    /// Transient failures are retried `transient_retries` times.
+    pub fn new(path: &str, validate: bool, pinned_symbol: Option<&str>, transient_retries: usize) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = resolve_library_path(path);
+        let library = unsafe { Library::new(&path)? };
+        
+        // Load the required functions; see the struct docs for why copying the
+        // pointers out of the symbols is sound
+        let init_fn = unsafe { library.get::<UclInit2Fn>(b"__ucl_init2") }
+            .ok()
+            .map(|f| *f);
+        
+        // Try different decompression function names
+        let (decompress_fn, decompress_symbol) = find_decompress_symbol(&library, pinned_symbol)?;
+        let safe_decompress_fn = unsafe { library.get::<UclDecompressFn>(safe_symbol_for(&decompress_symbol).as_bytes()) }
+            .ok()
+            .map(|f| *f);
+        
+        // Optional, and UCL only has compressors for the 8-bit stream format
+        let (compress_fn, compress_symbol) = match compress_symbol_for(&decompress_symbol) {
+            Some(symbol) => match unsafe { library.get::<UclCompressFn>(symbol.as_bytes()) } {
+                Ok(f) => (Some(*f), Some(symbol)),
+                Err(_) => (None, None),
+            },
+            None => (None, None),
         };
         
-        if decompress_fn.is_none() {
-            return Err("No compatible UCL decompression function found in library".into());
-        }
+        // Informational only; the string is static in libucl and needs no init
+        let version = unsafe {
+            library.get::<UclVersionStringFn>(b"ucl_version_string")
+                .ok()
+                .map(|version_fn| version_fn())
+                .filter(|version_ptr| !version_ptr.is_null())
+                .map(|version_ptr| std::ffi::CStr::from_ptr(version_ptr).to_string_lossy().into_owned())
+        };
         
         let lib = Self {
-            library,
+            library: Arc::new(library),
+            path,
             init_fn,
             decompress_fn,
+            decompress_symbol,
+            safe_decompress_fn,
+            compress_fn,
+            compress_symbol,
+            version,
+            transient_retries: AtomicUsize::new(transient_retries),
+            buffer_allocations: AtomicUsize::new(0),
+            collect_buffer_stats: AtomicBool::new(false),
+            buffer_tier_hits: Default::default(),
         };
         
         // Initialize UCL library if possible
         lib.ucl_init()?;
         
+        // The validation streams are in the 8-bit format
+        if validate && lib.is_8bit_stream() {
+            lib.validate_abi()?;
+        }
+        
         Ok(lib)
     }
     
+    /// Where the library was loaded from, with a relative configured path resolved
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    
+    /// Name of the decompression symbol resolved from the library
+    pub fn decompress_symbol(&self) -> &str {
+        &self.decompress_symbol
+    }
+    
+    /// Name of the matching compression symbol, `None` if the library doesn't export it
+    pub fn compress_symbol(&self) -> Option<&str> {
+        self.compress_symbol.as_deref()
+    }
+    
+    /// Whether the function reads the 8-bit stream format the built-in checks and
+    /// `encode_stored` understand; a pinned `_le16`/`_le32` variant doesn't
+    fn is_8bit_stream(&self) -> bool {
+        self.decompress_symbol.ends_with("_8")
+    }
+    
+    /// The stream format the function reads, if it is one of the 8-bit NRV2 formats
+    fn nrv2_variant(&self) -> Option<Nrv2Variant> {
+        if !self.is_8bit_stream() {
+            return None;
+        }
+        [("nrv2b", Nrv2Variant::B), ("nrv2d", Nrv2Variant::D), ("nrv2e", Nrv2Variant::E)].iter()
+            .find(|(name, _)| self.decompress_symbol.contains(name))
+            .map(|&(_, variant)| variant)
+    }
+    
+    /// Whether the function bounds-checks its output buffer. The other variants write
+    /// as much as the stream decodes to, whatever `dst_len` says.
+    fn is_safe_variant(&self) -> bool {
+        self.decompress_symbol.contains("_safe")
+    }
+    
+    /// Library version string, `None` if the library doesn't export `ucl_version_string`
+    pub fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+    
+    /// Change how often attempts that failed with a transient error are repeated
+    pub fn set_transient_retries(&self, retries: usize) {
+        self.transient_retries.store(retries, Ordering::Relaxed);
+    }
+    
+    /// Output buffers this library has allocated for decompression attempts so far
+    pub fn buffer_allocation_count(&self) -> usize {
+        self.buffer_allocations.load(Ordering::Relaxed)
+    }
+    
+    pub fn set_buffer_stats_enabled(&self, enabled: bool) {
+        self.collect_buffer_stats.store(enabled, Ordering::Relaxed);
+    }
+    
+    /// Successful decompressions per entry of `BUFFER_SIZE_TIERS` since the last call, or
+    /// `None` when collection is disabled
+    pub fn take_buffer_stats(&self) -> Option<[usize; 6]> {
+        if !self.collect_buffer_stats.load(Ordering::Relaxed) {
+            return None;
+        }
+        Some(std::array::from_fn(|tier| self.buffer_tier_hits[tier].swap(0, Ordering::Relaxed)))
+    }
+    
+    fn record_buffer_tier(&self, tier: usize) {
+        if self.collect_buffer_stats.load(Ordering::Relaxed) {
+            self.buffer_tier_hits[tier].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    
+    /// Encode `data` as a stored (literal-only) stream for the resolved NRV2 variant.
+    /// The stream is slightly larger than the input but decodes with the library's
+    /// decompressor, which makes it a known-good input for benchmarks.
+    pub fn encode_stored(&self, data: &[u8]) -> Vec<u8> {
+        // NRV2D and NRV2E share the literal and end-of-stream encoding
+        let variant = if self.decompress_symbol.contains("nrv2b") { Nrv2Variant::B } else { Nrv2Variant::E };
+        encode_stored_stream(data, variant)
+    }
+    
+    /// Decompress a tiny known stream to confirm the resolved function can be called
+    /// with our calling convention and produces correct output. A library that is
+    /// badly incompatible may still crash here, but it does so at load time rather
+    /// than in the middle of an extraction.
+    fn validate_abi(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let blob = if self.decompress_symbol.contains("nrv2b") {
+            NRV2B_VALIDATION_BLOB
+        } else {
+            NRV2DE_VALIDATION_BLOB
+        };
+        
+        match self.try_decompress_with_size(blob, VALIDATION_PAYLOAD.len()) {
+            Ok(output) if output == VALIDATION_PAYLOAD => Ok(()),
+            Ok(_) => Err(format!("UCL library validation failed: {} returned unexpected data. Incompatible library version or architecture?",
+                self.decompress_symbol).into()),
+            Err(e) => Err(format!("UCL library validation failed: {} reported {}. Incompatible library version or architecture?",
+                self.decompress_symbol, e).into()),
+        }
+    }
+    
     fn ucl_init(&self) -> Result<(), Box<dyn std::error::Error>> {
         if INITIALIZED.load(Ordering::Acquire) {
             return Ok(());
         }
 
-        if let Some(ref init_fn) = self.init_fn {
+        if let Some(init_fn) = self.init_fn {
             unsafe {
                 let res = init_fn(
                     UCL_VERSION,
@@ -176,21 +611,12 @@ impl UclLibrary {
         Ok(())
     }
 
-    pub fn decompress(&self, input: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-        // Add input validation
-        if input.is_empty() {
-            return Err("Input data is empty".into());
-        }
-        
-        if input.len() < 4 {
-            return Err("Input data too small (less than 4 bytes)".into());
-        }
-        
-        // Check for reasonable input size limits
-        if input.len() > 100 * 1024 * 1024 {
-            return Err(format!("Input data too large: {} bytes", input.len()).into());
-        }
-
+    /// Decompress `input`, returning the data and how many input bytes the compressed
+    /// stream occupied. A count smaller than `input.len()` means trailing data follows
+    /// the stream's end marker. The output size is guessed by growing the buffer; use
+    /// `decompress_into` when it is known.
+    pub fn decompress(&self, input: &[u8]) -> Result<(Vec<u8>, usize), Box<dyn std::error::Error>> {
+        check_input_size(input)?;
         
         // Try with different buffer sizes, starting with a reasonable estimate
         let buffer_sizes = [
@@ -201,34 +627,208 @@ impl UclLibrary {
             50 * 1024 * 1024,       // 50MB
         ];
         
-        for &buffer_size in &buffer_sizes {
-            if buffer_size > 200 * 1024 * 1024 {
-                continue; // Skip sizes over 200MB
+        let max_retries = self.transient_retries.load(Ordering::Relaxed);
+        // Every failed attempt, reported with the final error
+        let mut attempts = Vec::new();
+        
+        for (tier, &buffer_size) in buffer_sizes.iter().enumerate() {
+            if buffer_size > MAX_BUFFER_SIZE {
+                continue;
             }
             
-            match self.try_decompress_with_size(input, buffer_size) {
-                Ok(result) => return Ok(result),
-                Err(UclErrorKind::OutputOverrun) => continue,
-                Err(e) => return Err(format!("UCL decompression failed: {}", e).into()),
+            let mut retries = 0;
+            loop {
+                match self.try_decompress_with_size(input, buffer_size) {
+                    Ok(result) => {
+                        self.record_buffer_tier(tier);
+                        return Ok((result, input.len()));
+                    }
+                    // Transient failures are repeated with the same buffer a bounded number of times
+                    Err(e) if e.is_transient() && retries < max_retries => {
+                        retries += 1;
+                        attempts.push(format!("{}: {}, retry {}/{}", BUFFER_SIZE_TIERS[tier], e, retries, max_retries));
+                    }
+                    // Too small a buffer is worth growing. Inputs and buffers are capped well below
+                    // 4 GB, so the length conversions can't fail here.
+                    Err(e @ UclErrorKind::OutputOverrun) => {
+                        attempts.push(format!("{}: {}", BUFFER_SIZE_TIERS[tier], e));
+                        break;
+                    }
+                    Err(UclErrorKind::InputNotConsumed) => {
+                        let result = self.decompress_stream_prefix(input, buffer_size)
+                            .map_err(|e| with_attempts(format!("UCL decompression failed: {}", e), &attempts));
+                        if result.is_ok() {
+                            self.record_buffer_tier(tier);
+                        }
+                        return result;
+                    }
+                    // Corrupt data fails the same way with any buffer, so don't try the larger ones
+                    Err(e) => return Err(with_attempts(format!("UCL decompression failed: {}", e), &attempts)),
+                }
             }
         }
         
-        Err("UCL decompression failed: all buffer sizes exhausted".into())
+        Err(with_attempts("UCL decompression failed: all buffer sizes exhausted".to_string(), &attempts))
     }
     
-    fn try_decompress_with_size(&self, input: &[u8], buffer_size: usize) -> Result<Vec<u8>, UclErrorKind> {
-        let decompress_fn = match self.decompress_fn.as_ref() {
+    /// Like `decompress`, but into a single buffer of exactly `expected_len` bytes, such
+    /// as the target size from the XML. A stream decoding to more than that fails instead
+    /// of growing the buffer. `expected_len` usually comes from untrusted XML, so this
+    /// only holds for the `_safe_` functions; with the others a longer stream would write
+    /// past the buffer, and the growing buffer sizes of `decompress` are used instead. The
+    /// same goes for an `expected_len` over `MAX_BUFFER_SIZE`, which isn't allocated up front.
+    pub fn decompress_into(&self, input: &[u8], expected_len: usize) -> Result<(Vec<u8>, usize), Box<dyn std::error::Error>> {
+        if !self.is_safe_variant() || expected_len > MAX_BUFFER_SIZE {
+            return self.decompress(input);
+        }
+        check_input_size(input)?;
+        
+        let max_retries = self.transient_retries.load(Ordering::Relaxed);
+        let mut attempts = Vec::new();
+        
+        loop {
+            match self.try_decompress_with_size(input, expected_len) {
+                Ok(result) => {
+                    self.record_buffer_tier(EXPECTED_SIZE_TIER);
+                    return Ok((result, input.len()));
+                }
+                Err(e) if e.is_transient() && attempts.len() < max_retries => {
+                    attempts.push(format!("{}, retry {}/{}", e, attempts.len() + 1, max_retries));
+                }
+                Err(e @ UclErrorKind::OutputOverrun) => {
+                    return Err(with_attempts(format!("UCL decompression failed: {} (output larger than the expected {} bytes)", e, expected_len), &attempts));
+                }
+                Err(UclErrorKind::InputNotConsumed) => {
+                    let result = self.decompress_stream_prefix(input, expected_len)
+                        .map_err(|e| with_attempts(format!("UCL decompression failed: {}", e), &attempts));
+                    if result.is_ok() {
+                        self.record_buffer_tier(EXPECTED_SIZE_TIER);
+                    }
+                    return result;
+                }
+                Err(e) => return Err(with_attempts(format!("UCL decompression failed: {}", e), &attempts)),
+            }
+        }
+    }
+    
+    /// Compress `input` into the NRV2 variant the decompressor reads, e.g. to repack a
+    /// modified segment. `level` runs from 1 (fastest) to 10 (best).
+    pub fn compress(&self, input: &[u8], level: u32) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let compress_fn = match self.compress_fn {
             Some(f) => f,
-            None => return Err(UclErrorKind::GenericError),
+            None => return Err(format!("The UCL library has no compressor matching {}", self.decompress_symbol).into()),
+        };
+        if !(1..=10).contains(&level) {
+            return Err(format!("Invalid UCL compression level {}, expected 1 to 10", level).into());
+        }
+        
+        // Worst case for incompressible data, as documented by UCL
+        let capacity = input.len() + input.len() / 8 + 256;
+        let src_len: c_uint = input.len().try_into().map_err(|_| UclErrorKind::SrcTooLarge)?;
+        let mut dst_len: c_uint = capacity.try_into().map_err(|_| UclErrorKind::DstTooLarge)?;
+        let mut dst: Vec<u8> = Vec::with_capacity(capacity);
+        
+        let res = unsafe {
+            compress_fn(input.as_ptr(), src_len, dst.as_mut_ptr(), &mut dst_len, ptr::null(), level as c_int, ptr::null(), ptr::null_mut())
         };
+        match res {
+            0 if dst_len as usize <= capacity => {
+                unsafe { dst.set_len(dst_len as usize) };
+                Ok(dst)
+            }
+            0 => Err(format!("UCL compression failed: {}", UclErrorKind::OutputOverrun).into()),
+            _ => Err(format!("UCL compression failed: {}", UclErrorKind::from_code(res)).into()),
+        }
+    }
+    
+    /// Decompress on a watchdog thread into a buffer of at most `max_output` bytes.
+    /// FFI calls cannot be cancelled, so on timeout the worker is left running detached
+    /// and an error is returned. The stream must consume the whole input. `max_output`
+    /// is only enforced by the `_safe_` functions, so this fails if the library doesn't
+    /// export the bounds-checked counterpart of the configured function.
+    pub fn decompress_with_timeout(&self, input: &[u8], max_output: usize, timeout: Duration) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let decompress_fn = self.safe_decompress_fn.ok_or_else(|| format!(
+            "Safe mode needs the bounds-checked {}, which the UCL library doesn't export",
+            safe_symbol_for(&self.decompress_symbol)))?;
+        // A detached worker may outlive `self`, so it keeps the library loaded itself
+        let library = Arc::clone(&self.library);
+        
+        if let Some(variant) = self.nrv2_variant() {
+            check_stream_complete(input, variant)?;
+        }
+        let src_len: c_uint = input.len().try_into().map_err(|_| UclErrorKind::SrcTooLarge)?;
+        let dst_capacity: c_uint = max_output.try_into().map_err(|_| UclErrorKind::DstTooLarge)?;
+        
+        let input = input.to_vec();
+        let (sender, receiver) = mpsc::channel();
+        self.buffer_allocations.fetch_add(1, Ordering::Relaxed);
+        std::thread::spawn(move || {
+            let mut dst: Vec<u8> = Vec::with_capacity(max_output);
+            let mut dst_len = dst_capacity;
+            
+            let res = unsafe {
+                decompress_fn(input.as_ptr(), src_len, dst.as_mut_ptr(), &mut dst_len, ptr::null())
+            };
+            drop(library);
+            let result = match res {
+                0 if dst_len <= dst_capacity => {
+                    unsafe { dst.set_len(dst_len as usize) };
+                    Ok(dst)
+                }
+                0 => Err(UclErrorKind::OutputOverrun),
+                _ => Err(UclErrorKind::from_code(res)),
+            };
+            // The receiver is gone if we timed out, nothing left to report to
+            let _ = sender.send(result);
+        });
+        
+        match receiver.recv_timeout(timeout) {
+            Ok(Ok(data)) => Ok(data),
+            Ok(Err(e)) => Err(format!("UCL decompression failed: {}", e).into()),
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                Err(format!("UCL decompression did not finish within {} seconds", timeout.as_secs_f32()).into())
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => Err("UCL decompression worker stopped unexpectedly".into()),
+        }
+    }
+    
+    /// The library only reports that input was left over, not how much of it was used.
+    /// Binary search for the shortest prefix that decodes cleanly, which is exactly the
+    /// compressed stream. Shorter prefixes overrun, longer ones leave input unconsumed.
+    fn decompress_stream_prefix(&self, input: &[u8], buffer_size: usize) -> Result<(Vec<u8>, usize), UclErrorKind> {
+        let mut low = 1;
+        let mut high = input.len();
+        
+        while low < high {
+            let mid = low + (high - low) / 2;
+            match self.try_decompress_with_size(&input[..mid], buffer_size) {
+                Ok(_) | Err(UclErrorKind::InputNotConsumed) => high = mid,
+                Err(_) => low = mid + 1,
+            }
+        }
+        
+        self.try_decompress_with_size(&input[..low], buffer_size)
+            .map(|data| (data, low))
+    }
+    
+    fn try_decompress_with_size(&self, input: &[u8], buffer_size: usize) -> Result<Vec<u8>, UclErrorKind> {
+        let decompress_fn = self.decompress_fn;
+        if let Some(variant) = self.nrv2_variant() {
+            check_stream_complete(input, variant)?;
+        }
         
         let src_len = match input.len().try_into() {
             Ok(v) => v,
             Err(_) => return Err(UclErrorKind::SrcTooLarge),
         };
 
+        let mut dst_len: c_uint = match buffer_size.try_into() {
+            Ok(v) => v,
+            Err(_) => return Err(UclErrorKind::DstTooLarge),
+        };
+        
+        self.buffer_allocations.fetch_add(1, Ordering::Relaxed);
         let mut dst = Vec::with_capacity(buffer_size);
-        let mut dst_len = buffer_size as c_uint;
 
         unsafe {
             let res = decompress_fn(
@@ -240,16 +840,214 @@ impl UclLibrary {
             );
 
             match res {
-                0 => {
-                    assert!(
-                        dst_len <= (buffer_size as u32),
-                        "decompression yielded more data than available in dst buffer"
-                    );
+                0 if dst_len as usize <= buffer_size => {
                     dst.set_len(dst_len as usize);
                     Ok(dst)
                 }
+                // Only a function that ignores the buffer size gets here, and the bytes
+                // past the buffer are already written; report it rather than use them
+                0 => Err(UclErrorKind::OutputOverrun),
                 _ => Err(UclErrorKind::from_code(res)),
             }
         }
     }
-}
\ No newline at end of file
+}
+
+// Extractions run on a worker thread with the library shared from the UI thread
+const _: () = {
+    fn assert_send_sync<T: Send + Sync>() {}
+    let _ = assert_send_sync::<UclLibrary>;
+};
+
+/// Writer for the 8-bit NRV2 stream layout: flag bits are packed MSB first into a
+/// byte that is reserved in the output at the point its first bit is written.
+struct BitWriter {
+    out: Vec<u8>,
+    bit_byte: usize,
+    bits_left: u32,
+}
+
+impl BitWriter {
+    fn new(capacity: usize) -> Self {
+        Self {
+            out: Vec::with_capacity(capacity),
+            bit_byte: 0,
+            bits_left: 0,
+        }
+    }
+    
+    fn put_bit(&mut self, bit: u32) {
+        if self.bits_left == 0 {
+            self.bit_byte = self.out.len();
+            self.out.push(0);
+            self.bits_left = 8;
+        }
+        self.bits_left -= 1;
+        self.out[self.bit_byte] |= ((bit & 1) as u8) << self.bits_left;
+    }
+    
+    fn put_byte(&mut self, byte: u8) {
+        self.out.push(byte);
+    }
+    
+    /// Port of UCL's `code_prefix_ss11` (NRV2B offsets)
+    fn put_prefix_ss11(&mut self, mut i: u32) {
+        if i >= 2 {
+            let mut t: u32 = 4;
+            i += 2;
+            loop {
+                t <<= 1;
+                if i < t {
+                    break;
+                }
+            }
+            t >>= 1;
+            loop {
+                t >>= 1;
+                self.put_bit(u32::from(i & t != 0));
+                self.put_bit(0);
+                if t <= 2 {
+                    break;
+                }
+            }
+        }
+        self.put_bit(i & 1);
+        self.put_bit(1);
+    }
+    
+    /// Port of UCL's `code_prefix_ss12` (NRV2D/NRV2E offsets)
+    fn put_prefix_ss12(&mut self, mut i: u32) {
+        if i >= 2 {
+            let mut t: u32 = 2;
+            loop {
+                i -= t;
+                t <<= 2;
+                if i < t {
+                    break;
+                }
+            }
+            loop {
+                t >>= 1;
+                self.put_bit(u32::from(i & t != 0));
+                self.put_bit(0);
+                t >>= 1;
+                self.put_bit(u32::from(i & t != 0));
+                if t <= 2 {
+                    break;
+                }
+            }
+        }
+        self.put_bit(i & 1);
+        self.put_bit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    const VARIANTS: [Nrv2Variant; 3] = [Nrv2Variant::B, Nrv2Variant::D, Nrv2Variant::E];
+    
+    fn assert_every_cut_truncated(stream: &[u8], variant: Nrv2Variant) {
+        for cut in 0..stream.len() {
+            assert!(matches!(nrv2_stream_len(&stream[..cut], variant), Err(UclErrorKind::Truncated)),
+                "{:?} stream cut at {} of {} bytes", variant, cut, stream.len());
+        }
+    }
+    
+    #[test]
+    fn error_codes_are_mapped() {
+        let table = [
+            (-1, UclErrorKind::GenericError),
+            (-2, UclErrorKind::InvalidArgument),
+            (-3, UclErrorKind::OutOfMemory),
+            (-101, UclErrorKind::NotCompressible),
+            (-201, UclErrorKind::InputOverrun),
+            (-202, UclErrorKind::OutputOverrun),
+            (-203, UclErrorKind::LookbehindOverrun),
+            (-204, UclErrorKind::EofNotFound),
+            (-205, UclErrorKind::InputNotConsumed),
+            (-206, UclErrorKind::OverlapOverrun),
+            (-999, UclErrorKind::GenericError),
+        ];
+        for (code, kind) in table {
+            assert_eq!(UclErrorKind::from_code(code), kind, "code {}", code);
+        }
+    }
+    
+    #[test]
+    fn validation_blobs_are_complete() {
+        assert_eq!(nrv2_stream_len(NRV2B_VALIDATION_BLOB, Nrv2Variant::B).unwrap(), NRV2B_VALIDATION_BLOB.len());
+        assert_eq!(nrv2_stream_len(NRV2DE_VALIDATION_BLOB, Nrv2Variant::D).unwrap(), NRV2DE_VALIDATION_BLOB.len());
+        assert_eq!(nrv2_stream_len(NRV2DE_VALIDATION_BLOB, Nrv2Variant::E).unwrap(), NRV2DE_VALIDATION_BLOB.len());
+    }
+    
+    #[test]
+    fn stream_cut_mid_way_is_truncated() {
+        // 0xFF literals end up in every prefix, which is not enough to look complete
+        let data: Vec<u8> = (0..200u32).map(|i| if i % 3 == 0 { 0xFF } else { i as u8 }).collect();
+        for variant in VARIANTS {
+            let stream = encode_stored_stream(&data, variant);
+            assert_eq!(nrv2_stream_len(&stream, variant).unwrap(), stream.len());
+            assert!(matches!(check_stream_complete(&stream[..stream.len() / 2], variant), Err(UclErrorKind::Truncated)));
+            assert_every_cut_truncated(&stream, variant);
+        }
+    }
+    
+    #[test]
+    fn trailing_data_after_end_marker_is_ignored() {
+        for variant in VARIANTS {
+            let mut stream = encode_stored_stream(b"trailing", variant);
+            let len = stream.len();
+            stream.extend_from_slice(&[0x00, 0xFF, 0x12]);
+            assert_eq!(nrv2_stream_len(&stream, variant).unwrap(), len);
+        }
+    }
+    
+    #[test]
+    fn matches_are_walked() {
+        for variant in VARIANTS {
+            let mut writer = BitWriter::new(32);
+            writer.put_bit(1);
+            writer.put_byte(b'A');
+            
+            // A match at a new offset (offset code 3, offset byte 0), then one that
+            // repeats it (offset code 2) with a length long enough for the gamma code
+            writer.put_bit(0);
+            if variant == Nrv2Variant::B {
+                writer.put_prefix_ss11(1);
+                writer.put_byte(0x00);
+                writer.put_bit(0);
+                writer.put_bit(1);
+                writer.put_bit(0);
+                writer.put_prefix_ss11(0);
+                writer.put_bit(0);
+                writer.put_bit(0);
+            } else {
+                writer.put_prefix_ss12(1);
+                writer.put_byte(0x00);
+                writer.put_bit(0);
+                writer.put_bit(0);
+                writer.put_prefix_ss12(0);
+                writer.put_bit(0);
+                writer.put_bit(0);
+            }
+            writer.put_bit(1);
+            writer.put_bit(1);
+            
+            writer.put_bit(1);
+            writer.put_byte(b'B');
+            writer.put_bit(0);
+            if variant == Nrv2Variant::B {
+                writer.put_prefix_ss11(0x100_0000);
+            } else {
+                writer.put_prefix_ss12(0x100_0000);
+            }
+            writer.put_byte(0xFF);
+            
+            let stream = writer.out;
+            assert_eq!(nrv2_stream_len(&stream, variant).unwrap(), stream.len(), "{:?}", variant);
+            assert_every_cut_truncated(&stream, variant);
+        }
+    }
+}
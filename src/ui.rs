@@ -1,18 +1,90 @@
 use eframe::egui;
-use std::path::PathBuf;
-use webbrowser;
-use crate::types::{AvailableFile, FileType, UIMessage};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use crate::config::{AppConfig, UclPathSource};
+use crate::file_ops::{find_overlaps, format_segment_indices, parse_hex_bytes, parse_segment_indices, validate_flash_script_template, validate_output_template, DEFAULT_FLASH_SCRIPT_TEMPLATE, DEFAULT_OUTPUT_NAME_TEMPLATE};
+use crate::types::{AvailableFile, ChecksumAlgorithm, Endianness, ExtractionProgress, FileType, FileValidation, FlashSegment, HeaderSpec, ImageChecksum, OutputFormat, OutputLayout, OutputOptions, PadMode, RawSegmentAddress, RunPreview, SizeMismatchPolicy, UclPathCheck, ValidationSort, ValidationStatus, XmlInspection};
+
+/// Actions the widgets queue for the app to carry out after the frame is drawn
+#[derive(Debug)]
+pub enum UIMessage {
+    SelectPSDZFolder,
+    RemovePSDZFolder(usize),
+    RescanPSDZFolder(usize),
+    CancelPSDZScan,
+    ToggleFileBrowser,
+    ValidateAllFiles,
+    SelectFile(PathBuf, String), // path, file_type
+    ClearFile(String),
+    SetFileEnabled(String, bool), // file_type, enabled
+    SetFilePath(String, PathBuf), // file_type, path
+    ToggleFileListImport,
+    ImportFileList,
+    MoveCombineOrder(usize, usize), // from, to
+    SelectBTLDFile,
+    SelectSWFL1File,
+    SelectSWFL2File,
+    SelectOutputFile,
+    OpenProject,
+    SaveProject,
+    SelectReferenceFile,
+    ClearReferenceFile,
+    SelectBaseImage,
+    ClearBaseImage,
+    RevealPath(PathBuf),
+    ExtractFiles,
+    ReloadUCLLibrary,
+    ClearUCLOverride,
+    ResetConfig(bool), // true keeps recent folders
+    ConfigChanged,
+    BrowseUCLLibrary,
+    SearchUCLLibrary,
+    UseUCLLibrary(PathBuf),
+    FinishUCLSetup,
+    SetDesiredSizeMB(f32),
+    OutputLayoutChanged,
+    OutputFormatChanged,
+    OutputNameTemplateChanged,
+    ToggleSegmentPreview,
+    RefreshSegmentPreview,
+    PreviewRun,
+    RecalculateChecksums,
+    CopyCommandLine,
+    InspectXml(String), // label
+    CopySegmentHex(String, usize), // label, segment index
+}
 
 pub struct UIState {
     pub show_settings: bool,
     pub show_file_browser: bool,
+    pub show_segment_preview: bool,
+    pub show_ucl_setup: bool,
+    pub show_xml_inspector: bool,
+    pub show_validation_report: bool,
+    pub show_run_preview: bool,
+    pub show_file_list_import: bool,
+    /// `role:path` lines pasted into the import window
+    pub file_list_text: String,
+    /// Lines of the last import that couldn't be used
+    pub file_list_problems: Vec<String>,
+    /// Column and direction (true is ascending) of the validation report
+    pub validation_sort: (ValidationSort, bool),
+    /// Segment highlighted in the XML inspector
+    pub inspected_segment: Option<usize>,
+    /// Scroll the XML text to the highlighted segment on the next frame
+    pub xml_inspector_scroll: bool,
+    /// UCL libraries found by the setup window's search
+    pub ucl_candidates: Vec<PathBuf>,
+    pub ucl_search_done: bool,
     pub file_search_filter: String,
-    pub selected_btld_index: Option<usize>,
-    pub selected_swfl1_index: Option<usize>,
-    pub selected_swfl2_index: Option<usize>,
     pub message_queue: Vec<UIMessage>,
-    pub desired_size_mb: f32,
-    pub use_desired_size: bool,
+    pub output_options: OutputOptions,
+    /// Text typed or pasted into the manual selection path fields, by slot key
+    pub manual_paths: HashMap<String, String>,
+    /// Slot keys ("btld", "swfl1", "swfl2") in the order files are combined
+    pub combine_order: Vec<String>,
+    /// Slot keys whose file stays selected but is left out of the next run
+    pub disabled_slots: HashSet<String>,
 }
 
 impl Default for UIState {
@@ -20,28 +92,65 @@ impl Default for UIState {
         Self {
             show_settings: false,
             show_file_browser: false,
+            show_segment_preview: false,
+            show_ucl_setup: false,
+            show_xml_inspector: false,
+            show_validation_report: false,
+            show_run_preview: false,
+            show_file_list_import: false,
+            file_list_text: String::new(),
+            file_list_problems: Vec::new(),
+            validation_sort: (ValidationSort::Status, true),
+            inspected_segment: None,
+            xml_inspector_scroll: false,
+            ucl_candidates: Vec::new(),
+            ucl_search_done: false,
             file_search_filter: String::new(),
-            selected_btld_index: None,
-            selected_swfl1_index: None,
-            selected_swfl2_index: None,
             message_queue: Vec::new(),
-            desired_size_mb: 4.0, // Default to 4.0 MB
-            use_desired_size: false, // Default to false (use natural size)
+            output_options: OutputOptions::default(),
+            manual_paths: HashMap::new(),
+            combine_order: vec!["btld".to_string(), "swfl1".to_string(), "swfl2".to_string()],
+            disabled_slots: HashSet::new(),
         }
     }
 }
 
-pub fn render_header(ui: &mut egui::Ui, show_settings: &mut bool) {
+pub fn render_header(ui: &mut egui::Ui, show_settings: &mut bool, ucl_loaded: bool, ucl_required: Option<bool>, message_queue: &mut Vec<UIMessage>) {
     ui.horizontal(|ui| {
         ui.heading(egui::RichText::new("BMW Virtual Reader")
             .size(24.0)
             .color(egui::Color32::from_rgb(180, 160, 100)));
+        
+        let (badge, color, hint) = match (ucl_loaded, ucl_required) {
+            (true, _) => ("UCL: Loaded", egui::Color32::from_rgb(140, 200, 140), "The UCL library is loaded"),
+            (false, Some(false)) => ("UCL: Not required", egui::Color32::from_rgb(160, 160, 160),
+                "None of the selected files contain compressed segments"),
+            (false, Some(true)) => ("UCL: Not loaded", egui::Color32::from_rgb(200, 140, 140),
+                "The selected files contain compressed segments; configure the library in Settings"),
+            (false, None) => ("UCL: Not loaded", egui::Color32::from_rgb(200, 140, 140),
+                "Compressed segments can't be unpacked until the library is configured in Settings"),
+        };
+        ui.label(egui::RichText::new(badge)
+            .color(color)
+            .size(12.0))
+            .on_hover_text(hint);
         ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
             if ui.button(egui::RichText::new("Settings")
                 .color(egui::Color32::from_rgb(220, 220, 220)))
                 .clicked() {
                 *show_settings = !*show_settings;
             }
+            if ui.button(egui::RichText::new("Save Project")
+                .color(egui::Color32::from_rgb(220, 220, 220)))
+                .on_hover_text("Save the selected files, their XML, the output and its options to a project file")
+                .clicked() {
+                message_queue.push(UIMessage::SaveProject);
+            }
+            if ui.button(egui::RichText::new("Open Project")
+                .color(egui::Color32::from_rgb(220, 220, 220)))
+                .clicked() {
+                message_queue.push(UIMessage::OpenProject);
+            }
             // put link to github below settings button
             if ui.link(egui::RichText::new("github.com/CDFN/bmw-virtual-reader")
                 .color(egui::Color32::from_rgb(100, 150, 255))
@@ -55,7 +164,8 @@ pub fn render_header(ui: &mut egui::Ui, show_settings: &mut bool) {
 
 pub fn render_psdz_section(
     ui: &mut egui::Ui,
-    psdz_folder: &Option<PathBuf>,
+    psdz_folders: &[PathBuf],
+    scanning: Option<&Path>,
     message_queue: &mut Vec<UIMessage>
 ) {
     ui.group(|ui| {
@@ -63,21 +173,52 @@ pub fn render_psdz_section(
             .size(18.0)
             .color(egui::Color32::from_rgb(120, 160, 200)));
         
-        ui.horizontal(|ui| {
-            ui.label(egui::RichText::new("Folder:")
-                .color(egui::Color32::from_rgb(180, 180, 180)));
-            if let Some(ref path) = psdz_folder {
-                ui.label(egui::RichText::new(path.to_string_lossy())
-                    .color(egui::Color32::from_rgb(140, 200, 140)));
-            } else {
+        if psdz_folders.is_empty() {
+            ui.horizontal(|ui| {
+                ui.label(egui::RichText::new("Folder:")
+                    .color(egui::Color32::from_rgb(180, 180, 180)));
                 ui.label(egui::RichText::new("No folder selected")
                     .color(egui::Color32::from_rgb(200, 140, 140)));
-            }
-        });
+            });
+        }
+        
+        for (index, path) in psdz_folders.iter().enumerate() {
+            ui.horizontal(|ui| {
+                ui.label(egui::RichText::new("Folder:")
+                    .color(egui::Color32::from_rgb(180, 180, 180)));
+                ui.label(egui::RichText::new(path.to_string_lossy())
+                    .color(egui::Color32::from_rgb(140, 200, 140)));
+                if ui.small_button("Rescan")
+                    .on_hover_text("Scan this folder again; selected files stay selected unless they were removed")
+                    .clicked() {
+                    message_queue.push(UIMessage::RescanPSDZFolder(index));
+                }
+                if ui.small_button("✕")
+                    .on_hover_text("Remove this folder's files from the browser")
+                    .clicked() {
+                    message_queue.push(UIMessage::RemovePSDZFolder(index));
+                }
+            });
+        }
+        
+        if let Some(scanning) = scanning {
+            ui.horizontal(|ui| {
+                ui.spinner();
+                ui.label(egui::RichText::new(format!("Scanning {}...", scanning.display()))
+                    .color(egui::Color32::from_rgb(180, 180, 180)));
+                if ui.small_button("Cancel")
+                    .on_hover_text("Stop this scan; picking another folder cancels it too")
+                    .clicked() {
+                    message_queue.push(UIMessage::CancelPSDZScan);
+                }
+            });
+        }
         
         ui.horizontal(|ui| {
-            if ui.button(egui::RichText::new("Browse Folder")
+            let browse_label = if psdz_folders.is_empty() { "Browse Folder" } else { "Add Folder" };
+            if ui.button(egui::RichText::new(browse_label)
                 .color(egui::Color32::from_rgb(220, 220, 220)))
+                .on_hover_text("Files from several PSDZ folders can be combined, e.g. a BTLD from one version with SWFLs from another")
                 .clicked() {
                 message_queue.push(UIMessage::SelectPSDZFolder);
             }
@@ -86,18 +227,25 @@ pub fn render_psdz_section(
                 .clicked() {
                 message_queue.push(UIMessage::ToggleFileBrowser);
             }
+            if ui.add_enabled(!psdz_folders.is_empty(), egui::Button::new(egui::RichText::new("Validate All")
+                .color(egui::Color32::from_rgb(220, 220, 220))))
+                .on_hover_text("Check every file's XML and segment layout without writing any output")
+                .clicked() {
+                message_queue.push(UIMessage::ValidateAllFiles);
+            }
         });
     });
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn render_file_browser(
     ctx: &egui::Context,
     show_file_browser: &mut bool,
     available_files: &[AvailableFile],
     file_search_filter: &mut String,
-    selected_btld_index: &Option<usize>,
-    selected_swfl1_index: &Option<usize>,
-    selected_swfl2_index: &Option<usize>,
+    btld_file: &Option<PathBuf>,
+    swfl1_file: &Option<PathBuf>,
+    swfl2_file: &Option<PathBuf>,
     message_queue: &mut Vec<UIMessage>
 ) {
     if *show_file_browser && !available_files.is_empty() {
@@ -116,13 +264,14 @@ pub fn render_file_browser(
                 // File list
                 egui::ScrollArea::vertical().show(ui, |ui| {
                     let filter_text = file_search_filter.to_lowercase();
+                    let mut current_folder: Option<&PathBuf> = None;
                     
-                    for (index, file) in available_files.iter().enumerate() {
+                    for file in available_files {
                         // Since display names now have _ instead of .bin., we can simplify the search
                         let display_name_normalized = file.display_name.to_lowercase();
                         
                         // Create search patterns for different formats
-                        let search_patterns = vec![
+                        let search_patterns = [
                             filter_text.clone(), // Exact match
                             filter_text.replace("-", "_"), // Replace hyphens with underscores
                             filter_text.replace("_", "-"), // Replace underscores with hyphens
@@ -141,9 +290,19 @@ pub fn render_file_browser(
                             continue;
                         }
                         
-                        let is_selected_btld = *selected_btld_index == Some(index);
-                        let is_selected_swfl1 = *selected_swfl1_index == Some(index);
-                        let is_selected_swfl2 = *selected_swfl2_index == Some(index);
+                        // Files are stored grouped by folder, so a header marks each new group
+                        if current_folder != Some(&file.source_folder) {
+                            current_folder = Some(&file.source_folder);
+                            ui.add_space(5.0);
+                            ui.label(egui::RichText::new(file.source_folder.to_string_lossy())
+                                .size(14.0)
+                                .color(egui::Color32::from_rgb(120, 160, 200)));
+                        }
+                        
+                        // Selections are paths, so they survive rescans that reorder the list
+                        let is_selected_btld = btld_file.as_ref() == Some(&file.path);
+                        let is_selected_swfl1 = swfl1_file.as_ref() == Some(&file.path);
+                        let is_selected_swfl2 = swfl2_file.as_ref() == Some(&file.path);
                         
                         let file_type_str = match file.file_type {
                             FileType::BTLD => "BTLD",
@@ -158,7 +317,13 @@ pub fn render_file_browser(
                                     ui.label(egui::RichText::new(&file.display_name)
                                         .size(16.0)
                                         .color(egui::Color32::from_rgb(220, 220, 180)));
-                                    ui.label(egui::RichText::new(format!("Type: {} | Size: {:.0} KiB", file_type_str, size_kb))
+                                    // Only nested layouts need the folder to tell same-named files apart
+                                    let location = if file.relative_dir.contains('/') {
+                                        format!(" | In: swe/{}", file.relative_dir)
+                                    } else {
+                                        String::new()
+                                    };
+                                    ui.label(egui::RichText::new(format!("Type: {} | Size: {:.0} KiB{}", file_type_str, size_kb, location))
                                         .color(egui::Color32::from_rgb(160, 160, 160))
                                         .size(12.0));
                                 });
@@ -175,7 +340,7 @@ pub fn render_file_browser(
                                             if ui.button(egui::RichText::new("Select BTLD")
                                                 .color(egui::Color32::from_rgb(220, 220, 220)))
                                                 .clicked() {
-                                                message_queue.push(UIMessage::SelectFile(index, "btld".to_string()));
+                                                message_queue.push(UIMessage::SelectFile(file.path.clone(), "btld".to_string()));
                                             }
                                         }
                                     } else if file.file_type == FileType::SWFL {
@@ -190,7 +355,7 @@ pub fn render_file_browser(
                                                 if ui.button(egui::RichText::new("SWFL1")
                                                     .color(egui::Color32::from_rgb(220, 220, 220)))
                                                     .clicked() {
-                                                    message_queue.push(UIMessage::SelectFile(index, "swfl1".to_string()));
+                                                    message_queue.push(UIMessage::SelectFile(file.path.clone(), "swfl1".to_string()));
                                                 }
                                             }
                                             
@@ -204,7 +369,7 @@ pub fn render_file_browser(
                                                 if ui.button(egui::RichText::new("SWFL2")
                                                     .color(egui::Color32::from_rgb(220, 220, 220)))
                                                     .clicked() {
-                                                    message_queue.push(UIMessage::SelectFile(index, "swfl2".to_string()));
+                                                    message_queue.push(UIMessage::SelectFile(file.path.clone(), "swfl2".to_string()));
                                                 }
                                             }
                                         });
@@ -225,6 +390,8 @@ pub fn render_selected_files(
     btld_file: &Option<PathBuf>,
     swfl1_file: &Option<PathBuf>,
     swfl2_file: &Option<PathBuf>,
+    combine_order: &[String],
+    disabled_slots: &HashSet<String>,
     message_queue: &mut Vec<UIMessage>
 ) {
     if btld_file.is_some() || swfl1_file.is_some() || swfl2_file.is_some() {
@@ -233,123 +400,215 @@ pub fn render_selected_files(
             ui.heading(egui::RichText::new("Selected Files")
                 .size(16.0)
                 .color(egui::Color32::from_rgb(160, 200, 160)));
+            ui.label(egui::RichText::new("Files are combined top to bottom; drag to reorder. Later files overwrite earlier ones where they overlap. Unchecked files stay selected but are left out.")
+                .color(egui::Color32::from_rgb(160, 160, 160))
+                .size(11.0));
             
-            if let Some(ref path) = btld_file {
-                let file_name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
-                if let Ok(metadata) = std::fs::metadata(path) {
-                    let size_kb = metadata.len() as f64 / 1024.0;
-                    ui.horizontal(|ui| {
-                        ui.label(egui::RichText::new("BTLD:")
-                            .color(egui::Color32::from_rgb(200, 180, 120)));
-                        ui.label(egui::RichText::new(&file_name)
-                            .color(egui::Color32::from_rgb(160, 200, 160)));
-                        ui.label(egui::RichText::new(format!("({:.0} KiB)", size_kb))
-                            .color(egui::Color32::from_rgb(140, 140, 140))
-                            .size(11.0));
-                        if ui.button(egui::RichText::new("Clear")
-                            .color(egui::Color32::from_rgb(200, 140, 140)))
-                            .clicked() {
-                            message_queue.push(UIMessage::ClearFile("btld".to_string()));
-                        }
-                    });
-                } else {
-                    ui.horizontal(|ui| {
-                        ui.label(egui::RichText::new("BTLD:")
-                            .color(egui::Color32::from_rgb(200, 180, 120)));
-                        ui.label(egui::RichText::new(&file_name)
-                            .color(egui::Color32::from_rgb(160, 200, 160)));
-                        if ui.button(egui::RichText::new("Clear")
-                            .color(egui::Color32::from_rgb(200, 140, 140)))
-                            .clicked() {
-                            message_queue.push(UIMessage::ClearFile("btld".to_string()));
-                        }
-                    });
-                }
-            }
-            
-            if let Some(ref path) = swfl1_file {
-                let file_name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
-                if let Ok(metadata) = std::fs::metadata(path) {
-                    let size_kb = metadata.len() as f64 / 1024.0;
-                    ui.horizontal(|ui| {
-                        ui.label(egui::RichText::new("SWFL1:")
-                            .color(egui::Color32::from_rgb(200, 180, 120)));
-                        ui.label(egui::RichText::new(&file_name)
-                            .color(egui::Color32::from_rgb(160, 200, 160)));
-                        ui.label(egui::RichText::new(format!("({:.0} KiB)", size_kb))
-                            .color(egui::Color32::from_rgb(140, 140, 140))
-                            .size(11.0));
-                        if ui.button(egui::RichText::new("Clear")
-                            .color(egui::Color32::from_rgb(200, 140, 140)))
-                            .clicked() {
-                            message_queue.push(UIMessage::ClearFile("swfl1".to_string()));
-                        }
-                    });
-                } else {
-                    ui.horizontal(|ui| {
-                        ui.label(egui::RichText::new("SWFL1:")
-                            .color(egui::Color32::from_rgb(200, 180, 120)));
-                        ui.label(egui::RichText::new(&file_name)
-                            .color(egui::Color32::from_rgb(160, 200, 160)));
-                        if ui.button(egui::RichText::new("Clear")
-                            .color(egui::Color32::from_rgb(200, 140, 140)))
-                            .clicked() {
-                            message_queue.push(UIMessage::ClearFile("swfl1".to_string()));
-                        }
-                    });
-                }
-            }
-            
-            if let Some(ref path) = swfl2_file {
-                let file_name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
-                if let Ok(metadata) = std::fs::metadata(path) {
-                    let size_kb = metadata.len() as f64 / 1024.0;
-                    ui.horizontal(|ui| {
-                        ui.label(egui::RichText::new("SWFL2:")
-                            .color(egui::Color32::from_rgb(200, 180, 120)));
-                        ui.label(egui::RichText::new(&file_name)
-                            .color(egui::Color32::from_rgb(160, 200, 160)));
-                        ui.label(egui::RichText::new(format!("({:.0} KiB)", size_kb))
-                            .color(egui::Color32::from_rgb(140, 140, 140))
-                            .size(11.0));
-                        if ui.button(egui::RichText::new("Clear")
-                            .color(egui::Color32::from_rgb(200, 140, 140)))
-                            .clicked() {
-                            message_queue.push(UIMessage::ClearFile("swfl2".to_string()));
-                        }
-                    });
-                } else {
-                    ui.horizontal(|ui| {
-                        ui.label(egui::RichText::new("SWFL2:")
-                            .color(egui::Color32::from_rgb(200, 180, 120)));
-                        ui.label(egui::RichText::new(&file_name)
-                            .color(egui::Color32::from_rgb(160, 200, 160)));
-                        if ui.button(egui::RichText::new("Clear")
-                            .color(egui::Color32::from_rgb(200, 140, 140)))
-                            .clicked() {
-                            message_queue.push(UIMessage::ClearFile("swfl2".to_string()));
-                        }
-                    });
+            let mut position = 0;
+            for (order_index, slot) in combine_order.iter().enumerate() {
+                let file = match slot.as_str() {
+                    "btld" => btld_file,
+                    "swfl1" => swfl1_file,
+                    "swfl2" => swfl2_file,
+                    _ => continue,
+                };
+                if let Some(ref path) = file {
+                    position += 1;
+                    render_selected_file_row(ui, position, order_index, slot, path, !disabled_slots.contains(slot), message_queue);
                 }
             }
         });
     }
 }
 
+fn render_selected_file_row(
+    ui: &mut egui::Ui,
+    position: usize,
+    order_index: usize,
+    slot: &str,
+    path: &PathBuf,
+    enabled: bool,
+    message_queue: &mut Vec<UIMessage>
+) {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let name_color = if enabled {
+        egui::Color32::from_rgb(160, 200, 160)
+    } else {
+        egui::Color32::from_rgb(120, 120, 120)
+    };
+    
+    let row = ui.horizontal(|ui| {
+        ui.dnd_drag_source(egui::Id::new(("combine_order", slot)), order_index, |ui| {
+            ui.label(egui::RichText::new(format!("↕ {}.", position))
+                .color(egui::Color32::from_rgb(140, 140, 140)));
+        }).response.on_hover_text("Drag to change the combine order");
+        let mut checked = enabled;
+        if ui.checkbox(&mut checked, "")
+            .on_hover_text("Include this file in the next run")
+            .changed() {
+            message_queue.push(UIMessage::SetFileEnabled(slot.to_string(), checked));
+        }
+        ui.label(egui::RichText::new(format!("{}:", slot.to_uppercase()))
+            .color(egui::Color32::from_rgb(200, 180, 120)));
+        let name = egui::RichText::new(&file_name).color(name_color);
+        ui.label(if enabled { name } else { name.strikethrough() });
+        if let Ok(metadata) = std::fs::metadata(path) {
+            let size_kb = metadata.len() as f64 / 1024.0;
+            ui.label(egui::RichText::new(format!("({:.0} KiB)", size_kb))
+                .color(egui::Color32::from_rgb(140, 140, 140))
+                .size(11.0));
+        }
+        if ui.button(egui::RichText::new("Clear")
+            .color(egui::Color32::from_rgb(200, 140, 140)))
+            .clicked() {
+            message_queue.push(UIMessage::ClearFile(slot.to_string()));
+        }
+    }).response;
+    
+    // Highlight the drop target while dragging and reorder on release
+    if row.dnd_hover_payload::<usize>().is_some() {
+        ui.painter().hline(row.rect.x_range(), row.rect.top(),
+            egui::Stroke::new(2.0, egui::Color32::from_rgb(120, 160, 200)));
+    }
+    if let Some(from) = row.dnd_release_payload::<usize>() {
+        message_queue.push(UIMessage::MoveCombineOrder(*from, order_index));
+    }
+}
+
 pub fn render_manual_file_selection(
     ui: &mut egui::Ui,
     btld_file: &Option<PathBuf>,
     swfl1_file: &Option<PathBuf>,
     swfl2_file: &Option<PathBuf>,
+    manual_paths: &mut HashMap<String, String>,
     message_queue: &mut Vec<UIMessage>
 ) {
     ui.collapsing("Manual File Selection", |ui| {
+        if ui.button(egui::RichText::new("Import File List")
+            .color(egui::Color32::from_rgb(220, 220, 220)))
+            .on_hover_text("Paste role:path lines, e.g. from a script or spreadsheet, to fill several slots at once")
+            .clicked() {
+            message_queue.push(UIMessage::ToggleFileListImport);
+        }
+        
+        let slots = [
+            ("btld", "BTLD (bootloader) File:", btld_file, UIMessage::SelectBTLDFile),
+            ("swfl1", "SWFL1 (program) File:", swfl1_file, UIMessage::SelectSWFL1File),
+            ("swfl2", "SWFL2 (tune) File:", swfl2_file, UIMessage::SelectSWFL2File),
+        ];
+        
+        for (slot, label, file, browse_message) in slots {
+            ui.horizontal(|ui| {
+                ui.label(egui::RichText::new(label)
+                    .color(egui::Color32::from_rgb(180, 180, 180)));
+                if let Some(ref path) = file {
+                    ui.label(egui::RichText::new(path.to_string_lossy())
+                        .color(egui::Color32::from_rgb(140, 200, 140)));
+                } else {
+                    ui.label(egui::RichText::new("No file selected")
+                        .color(egui::Color32::from_rgb(200, 140, 140)));
+                }
+                if ui.button(egui::RichText::new("Browse")
+                    .color(egui::Color32::from_rgb(220, 220, 220)))
+                    .clicked() {
+                    message_queue.push(browse_message);
+                }
+            });
+            
+            // Typing or pasting a path sets the slot as soon as it names an existing file
+            let path_text = manual_paths.entry(slot.to_string()).or_default();
+            let response = ui.add(egui::TextEdit::singleline(path_text)
+                .hint_text("or paste a path")
+                .desired_width(f32::INFINITY));
+            let trimmed = path_text.trim().trim_matches('"');
+            let is_valid = PathBuf::from(trimmed).is_file();
+            if response.changed() && is_valid {
+                message_queue.push(UIMessage::SetFilePath(slot.to_string(), PathBuf::from(trimmed)));
+            }
+            if !trimmed.is_empty() && !is_valid {
+                ui.painter().rect_stroke(response.rect, 2.0, egui::Stroke::new(1.0, egui::Color32::from_rgb(200, 140, 140)));
+                response.on_hover_text("File not found");
+            }
+        }
+    });
+}
+
+pub fn render_file_list_import(
+    ctx: &egui::Context,
+    show_file_list_import: &mut bool,
+    file_list_text: &mut String,
+    file_list_problems: &[String],
+    message_queue: &mut Vec<UIMessage>
+) {
+    if !*show_file_list_import {
+        return;
+    }
+    
+    egui::Window::new("Import File List")
+        .open(show_file_list_import)
+        .default_size([600.0, 300.0])
+        .show(ctx, |ui| {
+            ui.label(egui::RichText::new("One file per line as role:path. Roles are btld, swfl1 and swfl2; swfl fills the next free SWFL slot. Tab or comma separated columns work too.")
+                .color(egui::Color32::from_rgb(160, 160, 160))
+                .size(12.0));
+            ui.add(egui::TextEdit::multiline(file_list_text)
+                .hint_text("btld:/path/to/btld_00001234.bin\nswfl:/path/to/swfl_00005678.bin")
+                .code_editor()
+                .desired_rows(6)
+                .desired_width(f32::INFINITY));
+            
+            if ui.add_enabled(!file_list_text.trim().is_empty(), egui::Button::new(egui::RichText::new("Import")
+                .color(egui::Color32::from_rgb(220, 220, 220))))
+                .clicked() {
+                message_queue.push(UIMessage::ImportFileList);
+            }
+            
+            for problem in file_list_problems {
+                ui.label(egui::RichText::new(problem)
+                    .color(egui::Color32::from_rgb(200, 140, 140))
+                    .size(12.0));
+            }
+        });
+}
+
+pub fn render_output_configuration(
+    ui: &mut egui::Ui,
+    output_file: &Option<PathBuf>,
+    output_is_auto: bool,
+    output_options: &mut OutputOptions,
+    recent_outputs: &[String],
+    message_queue: &mut Vec<UIMessage>
+) {
+    ui.group(|ui| {
+        ui.heading(egui::RichText::new("Output Configuration")
+            .size(16.0)
+            .color(egui::Color32::from_rgb(120, 160, 200)));
+        
+        let is_directory = output_options.layout == OutputLayout::SegmentDirectory;
+        
+        ui.horizontal(|ui| {
+            ui.label(egui::RichText::new("Layout:")
+                .color(egui::Color32::from_rgb(180, 180, 180)));
+            let combined = ui.radio_value(&mut output_options.layout, OutputLayout::CombinedImage, "Combined Image");
+            let directory = ui.radio_value(&mut output_options.layout, OutputLayout::SegmentDirectory, "Segment Directory")
+                .on_hover_text("Every decompressed segment as its own file, plus manifest.json and segments.map");
+            if combined.changed() || directory.changed() {
+                message_queue.push(UIMessage::OutputLayoutChanged);
+            }
+        });
+        
         ui.horizontal(|ui| {
-            ui.label(egui::RichText::new("BTLD (bootloader) File:")
+            ui.label(egui::RichText::new(if is_directory { "Output Folder:" } else { "Output File:" })
                 .color(egui::Color32::from_rgb(180, 180, 180)));
-            if let Some(ref path) = btld_file {
+            if let Some(ref path) = output_file {
                 ui.label(egui::RichText::new(path.to_string_lossy())
                     .color(egui::Color32::from_rgb(140, 200, 140)));
+                if output_is_auto {
+                    ui.label(egui::RichText::new("(auto)")
+                        .color(egui::Color32::from_rgb(140, 140, 140))
+                        .size(11.0))
+                        .on_hover_text("Derived from SWFL1 if selected, otherwise BTLD. Browse to choose a path yourself.");
+                }
             } else {
                 ui.label(egui::RichText::new("No file selected")
                     .color(egui::Color32::from_rgb(200, 140, 140)));
@@ -357,89 +616,245 @@ pub fn render_manual_file_selection(
             if ui.button(egui::RichText::new("Browse")
                 .color(egui::Color32::from_rgb(220, 220, 220)))
                 .clicked() {
-                message_queue.push(UIMessage::SelectBTLDFile);
+                message_queue.push(UIMessage::SelectOutputFile);
+            }
+            if !recent_outputs.is_empty() {
+                ui.menu_button(egui::RichText::new("Recent")
+                    .color(egui::Color32::from_rgb(220, 220, 220)), |ui| {
+                    for output in recent_outputs {
+                        let path = PathBuf::from(output);
+                        let file_name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+                        let exists = path.exists();
+                        let response = ui.add_enabled(exists, egui::Button::new(file_name))
+                            .on_hover_text(format!("Show in file manager: {}", output))
+                            .on_disabled_hover_text(format!("File no longer exists: {}", output));
+                        if response.clicked() {
+                            message_queue.push(UIMessage::RevealPath(path));
+                            ui.close_menu();
+                        }
+                    }
+                });
+            }
+        });
+        
+        ui.horizontal(|ui| {
+            ui.label(egui::RichText::new("Size mismatch:")
+                .color(egui::Color32::from_rgb(180, 180, 180)))
+                .on_hover_text("What to do when a segment's extracted length differs from the target size in its XML");
+            ui.radio_value(&mut output_options.size_policy, SizeMismatchPolicy::WarnAndKeep, "Warn");
+            ui.radio_value(&mut output_options.size_policy, SizeMismatchPolicy::TruncateToTarget, "Truncate")
+                .on_hover_text("Cut segments that decompress to more than the declared size");
+            ui.radio_value(&mut output_options.size_policy, SizeMismatchPolicy::Reject, "Reject")
+                .on_hover_text("Fail a file if any of its segments differs from the declared size");
+        });
+        
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut output_options.extract_compressed, egui::RichText::new("Keep compressed")
+                .color(egui::Color32::from_rgb(180, 180, 180)))
+                .on_hover_text("Copy compressed segments as they are instead of decompressing them, for targets that expect the packed data");
+            if output_options.extract_compressed {
+                ui.label(egui::RichText::new("at")
+                    .color(egui::Color32::from_rgb(180, 180, 180)));
+                ui.radio_value(&mut output_options.raw_segment_address, RawSegmentAddress::Target, "Target addresses")
+                    .on_hover_text("Packed data starts at the flash address; the rest of each compressed segment's range is gap fill");
+                ui.radio_value(&mut output_options.raw_segment_address, RawSegmentAddress::Source, "Source offsets")
+                    .on_hover_text("Every segment at its offset in the input file, reconstructing the input's layout");
+            }
+        });
+        
+        ui.checkbox(&mut output_options.holes_report, egui::RichText::new("Holes report")
+            .color(egui::Color32::from_rgb(180, 180, 180)))
+            .on_hover_text("Also write the address ranges between segments that no segment covers (.holes.json next to the output, or holes.json in the segment folder)");
+        ui.checkbox(&mut output_options.segments_csv, egui::RichText::new("Segment CSV")
+            .color(egui::Color32::from_rgb(180, 180, 180)))
+            .on_hover_text("Also write one row per segment with its addresses, sizes and compression for spreadsheets (.segments.csv next to the output, or segments.csv in the segment folder)");
+        ui.checkbox(&mut output_options.flash_script, egui::RichText::new("Flashing script")
+            .color(egui::Color32::from_rgb(180, 180, 180)))
+            .on_hover_text("Also write a recipe listing each written file with its address for an external flashing tool (.flash.txt next to the output, or flash.txt in the segment folder). The format is set in Settings.");
+        ui.checkbox(&mut output_options.text_banner, egui::RichText::new("Provenance banner")
+            .color(egui::Color32::from_rgb(180, 180, 180)))
+            .on_hover_text("Head Intel HEX and S-record outputs and segments.map with the tool version, time, input files and UCL function: as ; comments in Intel HEX, in the S0 record of S-records. Leave off for strict HEX parsers that reject comments.");
+        
+        if is_directory {
+            ui.label(egui::RichText::new("Note: Each segment is written as 0x<address>_<file>.bin together with manifest.json and segments.map. No padding is applied.")
+                .color(egui::Color32::from_rgb(160, 160, 160))
+                .size(11.0));
+            return;
+        }
+        
+        ui.horizontal(|ui| {
+            ui.label(egui::RichText::new("Format:")
+                .color(egui::Color32::from_rgb(180, 180, 180)));
+            let raw = ui.radio_value(&mut output_options.format, OutputFormat::RawBinary, "Raw Binary");
+            let intel_hex = ui.radio_value(&mut output_options.format, OutputFormat::IntelHex, "Intel HEX")
+                .on_hover_text("Address records for the segments only, without gap fill or padding");
+            let srec = ui.radio_value(&mut output_options.format, OutputFormat::Srec, "S-record")
+                .on_hover_text("Motorola S19/S28/S37 records for the segments only, sized to the highest address");
+            if raw.changed() || intel_hex.changed() || srec.changed() {
+                message_queue.push(UIMessage::OutputFormatChanged);
             }
         });
         
         ui.horizontal(|ui| {
-            ui.label(egui::RichText::new("SWFL1 (program) File:")
+            ui.label(egui::RichText::new("Reference:")
                 .color(egui::Color32::from_rgb(180, 180, 180)));
-            if let Some(ref path) = swfl1_file {
+            if let Some(ref path) = output_options.reference_file {
                 ui.label(egui::RichText::new(path.to_string_lossy())
-                    .color(egui::Color32::from_rgb(140, 200, 140)));
+                    .color(egui::Color32::from_rgb(140, 200, 140)))
+                    .on_hover_text("The output takes this file's length and is compared with it after writing");
+                if ui.small_button("Clear").clicked() {
+                    message_queue.push(UIMessage::ClearReferenceFile);
+                }
             } else {
-                ui.label(egui::RichText::new("No file selected")
-                    .color(egui::Color32::from_rgb(200, 140, 140)));
+                ui.label(egui::RichText::new("None")
+                    .color(egui::Color32::from_rgb(160, 160, 160)));
             }
             if ui.button(egui::RichText::new("Browse")
                 .color(egui::Color32::from_rgb(220, 220, 220)))
+                .on_hover_text("Pick a known-good extraction to reproduce and compare against")
                 .clicked() {
-                message_queue.push(UIMessage::SelectSWFL1File);
+                message_queue.push(UIMessage::SelectReferenceFile);
             }
         });
         
+        if output_options.reference_file.is_some() && output_options.base_image.is_none() {
+            ui.horizontal(|ui| {
+                ui.label(egui::RichText::new("Reference starts at:")
+                    .color(egui::Color32::from_rgb(180, 180, 180)));
+                let mut auto_addr = output_options.reference_file_addr.is_none();
+                if ui.checkbox(&mut auto_addr, "Lowest segment")
+                    .on_hover_text("Align the reference's first byte with the lowest segment address")
+                    .changed() {
+                    output_options.reference_file_addr = (!auto_addr).then_some(0);
+                }
+                if let Some(ref mut reference_addr) = output_options.reference_file_addr {
+                    ui.add(egui::DragValue::new(reference_addr).hexadecimal(8, false, true).prefix("0x"))
+                        .on_hover_text("Flash address of the reference's first byte; segments outside it fail the run");
+                }
+            });
+        }
+        
         ui.horizontal(|ui| {
-            ui.label(egui::RichText::new("SWFL2 (tune) File:")
+            ui.label(egui::RichText::new("Base image:")
                 .color(egui::Color32::from_rgb(180, 180, 180)));
-            if let Some(ref path) = swfl2_file {
+            if let Some(ref path) = output_options.base_image {
                 ui.label(egui::RichText::new(path.to_string_lossy())
-                    .color(egui::Color32::from_rgb(140, 200, 140)));
+                    .color(egui::Color32::from_rgb(140, 200, 140)))
+                    .on_hover_text("Segments are written over this dump; everything else keeps its contents");
+                if ui.small_button("Clear").clicked() {
+                    message_queue.push(UIMessage::ClearBaseImage);
+                }
             } else {
-                ui.label(egui::RichText::new("No file selected")
-                    .color(egui::Color32::from_rgb(200, 140, 140)));
+                ui.label(egui::RichText::new("None")
+                    .color(egui::Color32::from_rgb(160, 160, 160)));
             }
             if ui.button(egui::RichText::new("Browse")
                 .color(egui::Color32::from_rgb(220, 220, 220)))
+                .on_hover_text("Pick an existing full flash dump to patch with the extracted segments")
                 .clicked() {
-                message_queue.push(UIMessage::SelectSWFL2File);
+                message_queue.push(UIMessage::SelectBaseImage);
             }
         });
-    });
-}
-
-pub fn render_output_configuration(
-    ui: &mut egui::Ui,
-    output_file: &Option<PathBuf>,
-    desired_size_mb: &mut f32,
-    use_desired_size: &mut bool,
-    message_queue: &mut Vec<UIMessage>
-) {
-    ui.group(|ui| {
-        ui.heading(egui::RichText::new("Output Configuration")
-            .size(16.0)
-            .color(egui::Color32::from_rgb(120, 160, 200)));
+        
+        if output_options.base_image.is_some() {
+            ui.horizontal(|ui| {
+                ui.label(egui::RichText::new("Base image starts at:")
+                    .color(egui::Color32::from_rgb(180, 180, 180)));
+                let mut auto_addr = output_options.base_image_addr.is_none();
+                if ui.checkbox(&mut auto_addr, "Lowest segment")
+                    .on_hover_text("Align the dump's first byte with the lowest segment address")
+                    .changed() {
+                    output_options.base_image_addr = (!auto_addr).then_some(0);
+                }
+                if let Some(ref mut base_addr) = output_options.base_image_addr {
+                    ui.add(egui::DragValue::new(base_addr).hexadecimal(8, false, true).prefix("0x"))
+                        .on_hover_text("Flash address of the dump's first byte; segments before it are skipped");
+                }
+            });
+        }
         
         ui.horizontal(|ui| {
-            ui.label(egui::RichText::new("Output File:")
+            ui.label(egui::RichText::new("Gap Fill:")
                 .color(egui::Color32::from_rgb(180, 180, 180)));
-            if let Some(ref path) = output_file {
-                ui.label(egui::RichText::new(path.to_string_lossy())
-                    .color(egui::Color32::from_rgb(140, 200, 140)));
-            } else {
-                ui.label(egui::RichText::new("No file selected")
-                    .color(egui::Color32::from_rgb(200, 140, 140)));
+            ui.add(egui::DragValue::new(&mut output_options.gap_fill).hexadecimal(2, false, true).prefix("0x"))
+                .on_hover_text("Byte written between segments; 0xFF matches erased flash");
+            ui.label(egui::RichText::new("Pad Fill:")
+                .color(egui::Color32::from_rgb(180, 180, 180)));
+            ui.add(egui::DragValue::new(&mut output_options.pad_fill).hexadecimal(2, false, true).prefix("0x"))
+                .on_hover_text("Byte appended when padding to a desired size or power of two; 0xFF matches erased flash");
+        });
+        
+        ui.horizontal(|ui| {
+            let mut fail_on_fill = output_options.min_real_data_percent.is_some();
+            if ui.checkbox(&mut fail_on_fill, egui::RichText::new("Fail if real data is at most")
+                .color(egui::Color32::from_rgb(180, 180, 180)))
+                .on_hover_text("Don't write an image that is (almost) only gap fill, e.g. because every segment failed")
+                .changed() {
+                output_options.min_real_data_percent = fail_on_fill.then_some(0.0);
             }
-            if ui.button(egui::RichText::new("Browse")
-                .color(egui::Color32::from_rgb(220, 220, 220)))
-                .clicked() {
-                message_queue.push(UIMessage::SelectOutputFile);
+            if let Some(ref mut min_percent) = output_options.min_real_data_percent {
+                ui.add(egui::DragValue::new(min_percent)
+                    .clamp_range(0.0..=99.0)
+                    .speed(0.5)
+                    .suffix(" %"))
+                    .on_hover_text("Share of the image, before padding, that must differ from the gap fill. 0 % only rejects images that are entirely fill.");
+            }
+        });
+        
+        ui.horizontal(|ui| {
+            ui.label(egui::RichText::new("Checksum:")
+                .color(egui::Color32::from_rgb(180, 180, 180)));
+            ui.radio_value(&mut output_options.checksum, ChecksumAlgorithm::Crc32, "CRC32")
+                .on_hover_text("CRC-32 as used by zip and most tools");
+            ui.radio_value(&mut output_options.checksum, ChecksumAlgorithm::Crc16Ccitt, "CRC16-CCITT")
+                .on_hover_text("CRC-16/CCITT-FALSE: polynomial 0x1021, initial value 0xFFFF");
+        });
+        
+        ui.checkbox(&mut output_options.allow_overlap, egui::RichText::new("Allow overlapping segments")
+            .color(egui::Color32::from_rgb(180, 180, 180)))
+            .on_hover_text("Later files in the combine order overwrite earlier ones where segments overlap. Untick to refuse writing such an image; overlaps are listed as warnings either way.");
+        
+        if output_options.format != OutputFormat::RawBinary {
+            ui.label(egui::RichText::new(format!("Note: {} lists only the addresses the segments cover (the whole dump with a base image), so padding, splitting and the vendor header don't apply.", output_options.format))
+                .color(egui::Color32::from_rgb(160, 160, 160))
+                .size(11.0));
+            return;
+        }
+        
+        ui.horizontal(|ui| {
+            let mut split = output_options.split_part_kb.is_some();
+            if ui.checkbox(&mut split, egui::RichText::new("Split into parts of")
+                .color(egui::Color32::from_rgb(180, 180, 180)))
+                .on_hover_text("Write .part001, .part002, ... next to the output instead of one file, with a .parts.json index of their address ranges")
+                .changed() {
+                output_options.split_part_kb = split.then_some(1024);
+            }
+            if let Some(ref mut part_kb) = output_options.split_part_kb {
+                ui.add(egui::DragValue::new(part_kb)
+                    .clamp_range(1..=1024 * 1024)
+                    .speed(16)
+                    .suffix(" KB"))
+                    .on_hover_text("Size of every part except the last, including the vendor header if one is written");
             }
         });
         
         ui.horizontal(|ui| {
-            ui.checkbox(use_desired_size, egui::RichText::new("Use Desired Size")
+            ui.label(egui::RichText::new("Padding:")
                 .color(egui::Color32::from_rgb(180, 180, 180)));
+            ui.radio_value(&mut output_options.pad_mode, PadMode::Natural, "Natural Size");
+            ui.radio_value(&mut output_options.pad_mode, PadMode::DesiredSize, "Desired Size");
+            ui.radio_value(&mut output_options.pad_mode, PadMode::NextPowerOfTwo, "Next Power of Two");
         });
         
-        if *use_desired_size {
+        if output_options.pad_mode == PadMode::DesiredSize {
             ui.horizontal(|ui| {
                 ui.label(egui::RichText::new("Desired Size:")
                     .color(egui::Color32::from_rgb(180, 180, 180)));
-                let mut size_text = format!("{:.1}", desired_size_mb);
+                let mut size_text = format!("{:.1}", output_options.desired_size_mb);
                 if ui.text_edit_singleline(&mut size_text).changed() {
                     if let Ok(size) = size_text.parse::<f32>() {
                         if size > 0.0 {
-                            *desired_size_mb = size;
+                            output_options.desired_size_mb = size;
                             message_queue.push(UIMessage::SetDesiredSizeMB(size));
                         }
                     }
@@ -448,7 +863,11 @@ pub fn render_output_configuration(
                     .color(egui::Color32::from_rgb(180, 180, 180)));
             });
             
-            ui.label(egui::RichText::new("Note: If the combined file size is smaller than the desired size, zero data will be appended to reach the target size.")
+            ui.label(egui::RichText::new("Note: If the combined file size is smaller than the desired size, pad fill bytes will be appended to reach the target size.")
+                .color(egui::Color32::from_rgb(160, 160, 160))
+                .size(11.0));
+        } else if output_options.pad_mode == PadMode::NextPowerOfTwo {
+            ui.label(egui::RichText::new("Note: Pad fill bytes will be appended to round the output up to the next power of two (e.g. 5 MB becomes 8 MB).")
                 .color(egui::Color32::from_rgb(160, 160, 160))
                 .size(11.0));
         } else {
@@ -456,29 +875,161 @@ pub fn render_output_configuration(
                 .color(egui::Color32::from_rgb(160, 160, 160))
                 .size(11.0));
         }
+        
+        render_header_options(ui, &mut output_options.header, &mut output_options.byte_order);
+    });
+}
+
+/// Toggle and fields for the optional vendor header written before a combined image
+fn render_header_options(ui: &mut egui::Ui, header: &mut Option<HeaderSpec>, byte_order: &mut Endianness) {
+    let mut enabled = header.is_some();
+    if ui.checkbox(&mut enabled, egui::RichText::new("Vendor header")
+        .color(egui::Color32::from_rgb(180, 180, 180)))
+        .on_hover_text("Prepend a fixed header block for flashing tools that expect one")
+        .changed() {
+        *header = enabled.then(HeaderSpec::default);
+    }
+    let Some(spec) = header else {
+        return;
+    };
+    
+    // The magic is edited as text and only applied while it parses
+    let magic_id = ui.id().with("header_magic_text");
+    let mut magic_text = ui.data(|d| d.get_temp::<String>(magic_id))
+        .unwrap_or_else(|| spec.magic.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(" "));
+    ui.horizontal(|ui| {
+        ui.label(egui::RichText::new("Magic:")
+            .color(egui::Color32::from_rgb(180, 180, 180)));
+        ui.add(egui::TextEdit::singleline(&mut magic_text)
+            .desired_width(160.0))
+            .on_hover_text("Magic bytes in hex, e.g. 42 4D 57 56");
+        match parse_hex_bytes(&magic_text) {
+            Ok(magic) => spec.magic = magic,
+            Err(e) => {
+                ui.label(egui::RichText::new(e.to_string())
+                    .color(egui::Color32::from_rgb(200, 140, 140))
+                    .size(11.0));
+            }
+        }
+    });
+    ui.data_mut(|d| d.insert_temp(magic_id, magic_text));
+    
+    ui.horizontal(|ui| {
+        let mut has_version = spec.version.is_some();
+        if ui.checkbox(&mut has_version, "Version").changed() {
+            spec.version = has_version.then_some(1);
+        }
+        if let Some(version) = spec.version.as_mut() {
+            ui.add(egui::DragValue::new(version));
+        }
+        ui.checkbox(&mut spec.include_base_addr, "Base address");
+        ui.checkbox(&mut spec.include_length, "Length");
+    });
+    
+    ui.horizontal(|ui| {
+        ui.label(egui::RichText::new("Byte order:")
+            .color(egui::Color32::from_rgb(180, 180, 180)))
+            .on_hover_text("Applies to every 32-bit field written into the output");
+        ui.radio_value(byte_order, Endianness::Big, "Big-endian")
+            .on_hover_text("E.g. PowerPC based ECUs");
+        ui.radio_value(byte_order, Endianness::Little, "Little-endian")
+            .on_hover_text("E.g. TriCore based ECUs");
     });
+    
+    let header_len = spec.magic.len()
+        + [spec.version.is_some(), spec.include_base_addr, spec.include_length].iter().filter(|&&field| field).count() * 4;
+    ui.label(egui::RichText::new(format!("Note: {} byte header: magic, then version, base address and image length as {} 32-bit fields where enabled.", header_len, byte_order))
+        .color(egui::Color32::from_rgb(160, 160, 160))
+        .size(11.0));
 }
 
 pub fn render_extract_button(
     ui: &mut egui::Ui,
     is_processing: bool,
+    has_inputs: bool,
+    has_output: bool,
+    has_ucl: bool,
     message_queue: &mut Vec<UIMessage>
 ) {
+    let mut missing = Vec::new();
+    if !has_inputs {
+        missing.push("select and check at least one BTLD or SWFL file");
+    }
+    if !has_output {
+        missing.push("choose an output file");
+    }
+    if !has_ucl {
+        missing.push("load the UCL library (see Settings)");
+    }
+    if is_processing {
+        missing.push("wait for the current extraction to finish");
+    }
+    
     ui.horizontal(|ui| {
-        if ui.button(egui::RichText::new("Create binary")
+        let button = egui::Button::new(egui::RichText::new("Create binary")
             .size(18.0)
-            .color(egui::Color32::from_rgb(220, 220, 220)))
-            .clicked() && !is_processing {
+            .color(egui::Color32::from_rgb(220, 220, 220)));
+        if ui.add_enabled(missing.is_empty(), button)
+            .on_disabled_hover_text(format!("To create a binary, {}", missing.join(", ")))
+            .clicked() {
             message_queue.push(UIMessage::ExtractFiles);
         }
         
+        if ui.add_enabled(has_inputs, egui::Button::new(egui::RichText::new("Preview")
+            .color(egui::Color32::from_rgb(220, 220, 220))))
+            .on_hover_text("Show the segments, gaps and output size of a run without reading segment data or writing anything")
+            .on_disabled_hover_text("Select and check at least one BTLD or SWFL file")
+            .clicked() {
+            message_queue.push(UIMessage::PreviewRun);
+        }
+        
+        if ui.add_enabled(has_inputs && !is_processing, egui::Button::new(egui::RichText::new("Recalculate checksums")
+            .color(egui::Color32::from_rgb(220, 220, 220))))
+            .on_hover_text("Pick a combined raw image, e.g. one with edited segments, and write the checksum of every segment to <image>.checksums.json, marking the ones that changed since the last time")
+            .on_disabled_hover_text("Select and check the BTLD or SWFL files the image was created from")
+            .clicked() {
+            message_queue.push(UIMessage::RecalculateChecksums);
+        }
+        
+        if ui.add_enabled(has_inputs && has_output, egui::Button::new(egui::RichText::new("Copy command line")
+            .color(egui::Color32::from_rgb(220, 220, 220))))
+            .on_hover_text("Copy a command that repeats this run without the window, e.g. for a build script")
+            .on_disabled_hover_text("Select and check at least one BTLD or SWFL file and choose an output file")
+            .clicked() {
+            message_queue.push(UIMessage::CopyCommandLine);
+        }
+        
+        if ui.button(egui::RichText::new("Segment Preview")
+            .color(egui::Color32::from_rgb(220, 220, 220)))
+            .clicked() {
+            message_queue.push(UIMessage::ToggleSegmentPreview);
+        }
+        
         if is_processing {
             ui.add(egui::widgets::Spinner::new());
         }
     });
 }
 
-pub fn render_status(ui: &mut egui::Ui, status_message: &str) {
+/// Thin progress bar per input file of the current or last run
+pub fn render_file_progress(ui: &mut egui::Ui, file_progress: &[(String, f32)]) {
+    if file_progress.is_empty() {
+        return;
+    }
+    
+    for (label, fraction) in file_progress {
+        ui.horizontal(|ui| {
+            ui.add_sized([50.0, 12.0], egui::Label::new(egui::RichText::new(label)
+                .color(egui::Color32::from_rgb(180, 180, 180))
+                .size(11.0)));
+            ui.add(egui::ProgressBar::new(*fraction)
+                .desired_height(8.0)
+                .text(egui::RichText::new(format!("{:.0}%", fraction * 100.0)).size(9.0)));
+        });
+    }
+}
+
+pub fn render_status(ui: &mut egui::Ui, status_message: &str, warnings: &[String], progress: Option<&ExtractionProgress>, checksum: Option<&ImageChecksum>) {
     ui.group(|ui| {
         ui.heading(egui::RichText::new("Status")
             .size(14.0)
@@ -491,18 +1042,742 @@ pub fn render_status(ui: &mut egui::Ui, status_message: &str) {
             } else {
                 egui::Color32::from_rgb(180, 180, 180)
             }));
+        
+        if let Some(progress) = progress {
+            ui.add(egui::ProgressBar::new(progress.overall)
+                .text(egui::RichText::new(progress.to_string()).size(11.0)));
+        }
+        
+        if let Some(checksum) = checksum {
+            ui.horizontal(|ui| {
+                ui.label(egui::RichText::new("Checksum:")
+                    .color(egui::Color32::from_rgb(180, 180, 180)));
+                if ui.add(egui::Label::new(egui::RichText::new(checksum.to_string())
+                    .monospace()
+                    .color(egui::Color32::from_rgb(220, 220, 180)))
+                    .sense(egui::Sense::click()))
+                    .on_hover_text(format!("Over {} bytes of image data, without a vendor header. Click to copy.", checksum.len))
+                    .clicked() {
+                    ui.ctx().copy_text(checksum.to_string());
+                }
+            });
+        }
+        
+        if !warnings.is_empty() {
+            ui.collapsing(egui::RichText::new(format!("Warnings ({})", warnings.len()))
+                .color(egui::Color32::from_rgb(220, 190, 110)), |ui| {
+                egui::ScrollArea::vertical().max_height(150.0).show(ui, |ui| {
+                    for warning in warnings {
+                        ui.label(egui::RichText::new(warning)
+                            .color(egui::Color32::from_rgb(220, 190, 110))
+                            .size(12.0));
+                    }
+                });
+            });
+        }
     });
 }
 
+/// Format a segment as a single line suitable for pasting into notes or a disassembler
+fn format_segment_line(label: &str, index: usize, segment: &FlashSegment) -> String {
+    format!("{} #{}: source 0x{:08X}-0x{:08X} -> target 0x{:08X}-0x{:08X} ({})",
+        label,
+        index,
+        segment.source_start_addr,
+        segment.source_end_addr,
+        segment.target_start_addr,
+        segment.target_end_addr,
+        if segment.is_compressed { "compressed" } else { "uncompressed" })
+}
+
+/// Render a hex address, optionally followed by its decimal value, that copies the hex
+/// form to the clipboard when clicked
+fn render_address_cell(ui: &mut egui::Ui, addr: u32, show_decimal: bool) {
+    let text = format!("0x{:08X}", addr);
+    let shown = if show_decimal { format!("{} ({})", text, addr) } else { text.clone() };
+    if ui.add(egui::Label::new(egui::RichText::new(shown)
+        .monospace()
+        .color(egui::Color32::from_rgb(220, 220, 180)))
+        .sense(egui::Sense::click()))
+        .on_hover_text("Click to copy")
+        .clicked() {
+        ui.ctx().copy_text(text);
+    }
+}
+
+/// Stacked bar of how many output bytes each file contributes, split into compressed
+/// (darker) and uncompressed segments. Sizes are the declared target sizes of the
+/// segments selected for extraction.
+fn render_composition_bar(
+    ui: &mut egui::Ui,
+    segment_preview: &[(String, Vec<FlashSegment>)],
+    segment_selection: &HashMap<String, String>
+) {
+    let file_colors = [
+        egui::Color32::from_rgb(120, 160, 200),
+        egui::Color32::from_rgb(140, 200, 140),
+        egui::Color32::from_rgb(200, 170, 110),
+    ];
+    
+    // (label, color, compressed bytes, uncompressed bytes) per file
+    let mut parts = Vec::new();
+    for (file_index, (label, segments)) in segment_preview.iter().enumerate() {
+        let selected = segment_selection.get(label)
+            .and_then(|spec| parse_segment_indices(spec).ok())
+            .filter(|indices| !indices.is_empty());
+        let (mut compressed, mut uncompressed) = (0u64, 0u64);
+        for (index, segment) in segments.iter().enumerate() {
+            if selected.as_ref().is_some_and(|indices| !indices.contains(&index)) {
+                continue;
+            }
+            let size = (segment.target_end_addr as u64).saturating_sub(segment.target_start_addr as u64) + 1;
+            if segment.is_compressed {
+                compressed += size;
+            } else {
+                uncompressed += size;
+            }
+        }
+        parts.push((label, file_colors[file_index % file_colors.len()], compressed, uncompressed));
+    }
+    
+    let total: u64 = parts.iter().map(|(_, _, compressed, uncompressed)| compressed + uncompressed).sum();
+    if total == 0 {
+        return;
+    }
+    
+    ui.label(egui::RichText::new("Composition")
+        .color(egui::Color32::from_rgb(180, 180, 180)));
+    let (rect, response) = ui.allocate_exact_size(egui::vec2(ui.available_width(), 18.0), egui::Sense::hover());
+    let painter = ui.painter();
+    let mut x = rect.left();
+    for (_, color, compressed, uncompressed) in &parts {
+        for (bytes, fill) in [(*compressed, color.gamma_multiply(0.6)), (*uncompressed, *color)] {
+            let width = rect.width() * bytes as f32 / total as f32;
+            if width > 0.0 {
+                painter.rect_filled(egui::Rect::from_min_size(egui::pos2(x, rect.top()), egui::vec2(width, rect.height())), 0.0, fill);
+                x += width;
+            }
+        }
+    }
+    response.on_hover_text("Darker parts are compressed segments");
+    
+    ui.horizontal_wrapped(|ui| {
+        for (label, color, compressed, uncompressed) in &parts {
+            let bytes = compressed + uncompressed;
+            ui.label(egui::RichText::new(format!("■ {}: {} bytes ({:.1}%), {} compressed",
+                label, bytes, bytes as f64 * 100.0 / total as f64, compressed))
+                .color(*color)
+                .size(12.0));
+        }
+    });
+}
+
+/// Target address space of the selected segments: each segment is a block in its file's
+/// color, gaps stay empty and ranges covered more than once are drawn in red on top
+fn render_address_map(
+    ui: &mut egui::Ui,
+    segment_preview: &[(String, Vec<FlashSegment>)],
+    segment_selection: &HashMap<String, String>
+) {
+    // (file index, start, end) of every selected segment, inclusive
+    let mut blocks = Vec::new();
+    for (file_index, (label, segments)) in segment_preview.iter().enumerate() {
+        let selected = segment_selection.get(label)
+            .and_then(|spec| parse_segment_indices(spec).ok())
+            .filter(|indices| !indices.is_empty());
+        for (index, segment) in segments.iter().enumerate() {
+            if selected.as_ref().is_some_and(|indices| !indices.contains(&index)) {
+                continue;
+            }
+            if segment.target_end_addr >= segment.target_start_addr {
+                blocks.push((file_index, segment.target_start_addr, segment.target_end_addr));
+            }
+        }
+    }
+    let (Some(min_addr), Some(max_addr)) = (
+        blocks.iter().map(|&(_, start, _)| start).min(),
+        blocks.iter().map(|&(_, _, end)| end).max(),
+    ) else {
+        return;
+    };
+    let ranges: Vec<(u32, u32)> = blocks.iter().map(|&(_, start, end)| (start, end)).collect();
+    let overlaps = find_overlaps(&ranges);
+    
+    let file_colors = [
+        egui::Color32::from_rgb(120, 160, 200),
+        egui::Color32::from_rgb(140, 200, 140),
+        egui::Color32::from_rgb(200, 170, 110),
+    ];
+    let overlap_color = egui::Color32::from_rgb(220, 80, 80);
+    
+    ui.label(egui::RichText::new("Address map")
+        .color(egui::Color32::from_rgb(180, 180, 180)));
+    let (rect, response) = ui.allocate_exact_size(egui::vec2(ui.available_width(), 22.0), egui::Sense::hover());
+    let painter = ui.painter();
+    painter.rect_filled(rect, 0.0, egui::Color32::from_rgb(40, 40, 40));
+    
+    let span = (max_addr - min_addr) as f64 + 1.0;
+    let x_of = |addr: u64| rect.left() + (rect.width() as f64 * (addr - min_addr as u64) as f64 / span) as f32;
+    // Tiny segments still get a visible sliver
+    let block_rect = |start: u32, end: u32| {
+        let left = x_of(start as u64);
+        let right = x_of(end as u64 + 1).max(left + 1.0);
+        egui::Rect::from_min_max(egui::pos2(left, rect.top()), egui::pos2(right, rect.bottom()))
+    };
+    for &(file_index, start, end) in &blocks {
+        painter.rect_filled(block_rect(start, end), 0.0, file_colors[file_index % file_colors.len()]);
+    }
+    for &(start, end) in &overlaps {
+        painter.rect_filled(block_rect(start, end), 0.0, overlap_color);
+    }
+    
+    // Name the segments under the pointer
+    let hover_addr = response.hover_pos().map(|pos| {
+        let fraction = ((pos.x - rect.left()) / rect.width()).clamp(0.0, 1.0) as f64;
+        (min_addr as u64 + (fraction * span) as u64).min(max_addr as u64) as u32
+    });
+    response.on_hover_ui(|ui| {
+        let Some(addr) = hover_addr else {
+            return;
+        };
+        ui.label(format!("0x{:08X}", addr));
+        let mut covered = false;
+        for (file_index, (label, segments)) in segment_preview.iter().enumerate() {
+            for (index, segment) in segments.iter().enumerate() {
+                if blocks.contains(&(file_index, segment.target_start_addr, segment.target_end_addr))
+                    && (segment.target_start_addr..=segment.target_end_addr).contains(&addr) {
+                    covered = true;
+                    ui.label(egui::RichText::new(format!("{} segment {}: 0x{:08X}-0x{:08X}",
+                        label, index, segment.target_start_addr, segment.target_end_addr))
+                        .color(file_colors[file_index % file_colors.len()]));
+                }
+            }
+        }
+        if !covered {
+            ui.label(egui::RichText::new("Gap, filled with the gap fill byte")
+                .color(egui::Color32::from_rgb(160, 160, 160)));
+        }
+    });
+    
+    ui.horizontal(|ui| {
+        ui.label(egui::RichText::new(format!("0x{:08X}", min_addr))
+            .monospace()
+            .color(egui::Color32::from_rgb(160, 160, 160))
+            .size(11.0));
+        ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+            ui.label(egui::RichText::new(format!("0x{:08X}", max_addr))
+                .monospace()
+                .color(egui::Color32::from_rgb(160, 160, 160))
+                .size(11.0));
+        });
+    });
+    if overlaps.is_empty() {
+        ui.label(egui::RichText::new("No overlapping segments")
+            .color(egui::Color32::from_rgb(140, 200, 140))
+            .size(12.0));
+    } else {
+        let overlap_list: Vec<String> = overlaps.iter()
+            .map(|(start, end)| format!("0x{:08X}-0x{:08X}", start, end))
+            .collect();
+        ui.label(egui::RichText::new(format!("Overlaps (red, later files win): {}", overlap_list.join(", ")))
+            .color(egui::Color32::from_rgb(200, 140, 140))
+            .size(12.0));
+    }
+}
+
+pub fn render_segment_preview(
+    ctx: &egui::Context,
+    show_segment_preview: &mut bool,
+    segment_preview: &[(String, Vec<FlashSegment>)],
+    selected_labels: &[&str],
+    segment_selection: &mut HashMap<String, String>,
+    show_decimal: &mut bool,
+    message_queue: &mut Vec<UIMessage>
+) {
+    if *show_segment_preview {
+        egui::Window::new("Segment Preview")
+            .open(show_segment_preview)
+            .default_size([700.0, 400.0])
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    if ui.button(egui::RichText::new("Refresh")
+                        .color(egui::Color32::from_rgb(220, 220, 220)))
+                        .clicked() {
+                        message_queue.push(UIMessage::RefreshSegmentPreview);
+                    }
+                    for label in selected_labels {
+                        if ui.button(egui::RichText::new(format!("Inspect {} XML", label))
+                            .color(egui::Color32::from_rgb(220, 220, 220)))
+                            .on_hover_text("Show the raw XML next to the parsed segments")
+                            .clicked() {
+                            message_queue.push(UIMessage::InspectXml(label.to_string()));
+                        }
+                    }
+                    if ui.checkbox(show_decimal, egui::RichText::new("Decimal")
+                        .color(egui::Color32::from_rgb(180, 180, 180)))
+                        .on_hover_text("Show each address in decimal too; segment manifests then get decimal fields as well")
+                        .changed() {
+                        message_queue.push(UIMessage::ConfigChanged);
+                    }
+                });
+                
+                ui.add_space(10.0);
+                
+                if segment_preview.is_empty() {
+                    ui.label(egui::RichText::new("No files selected")
+                        .color(egui::Color32::from_rgb(200, 140, 140)));
+                    return;
+                }
+                
+                render_composition_bar(ui, segment_preview, segment_selection);
+                ui.add_space(10.0);
+                render_address_map(ui, segment_preview, segment_selection);
+                ui.add_space(10.0);
+                
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    for (label, segments) in segment_preview {
+                        ui.label(egui::RichText::new(format!("{} ({} segments)", label, segments.len()))
+                            .size(16.0)
+                            .color(egui::Color32::from_rgb(160, 200, 160)));
+                        
+                        let spec = segment_selection.entry(label.clone()).or_default();
+                        ui.horizontal(|ui| {
+                            ui.label(egui::RichText::new("Extract segments:")
+                                .color(egui::Color32::from_rgb(180, 180, 180)));
+                            ui.add(egui::TextEdit::singleline(spec)
+                                .hint_text("all")
+                                .desired_width(150.0))
+                                .on_hover_text("Segment indices to extract, e.g. 2-5,8. Leave empty for all segments.");
+                        });
+                        // An invalid selection fails the extraction, so say so here already
+                        let selected = match parse_segment_indices(spec) {
+                            Ok(indices) => Some(indices).filter(|indices| !indices.is_empty()),
+                            Err(e) => {
+                                ui.label(egui::RichText::new(format!("Invalid selection: {}", e))
+                                    .color(egui::Color32::from_rgb(200, 140, 140)));
+                                None
+                            }
+                        };
+                        let mut toggled = None;
+                        
+                        egui::Grid::new(format!("segment_preview_{}", label))
+                            .striped(true)
+                            .show(ui, |ui| {
+                                for header in ["", "#", "Source Start", "Source End", "Target Start", "Target End", "Compressed", ""] {
+                                    ui.label(egui::RichText::new(header)
+                                        .color(egui::Color32::from_rgb(180, 180, 180)));
+                                }
+                                ui.end_row();
+                                
+                                for (index, segment) in segments.iter().enumerate() {
+                                    let mut included = selected.as_ref().map_or(true, |indices| indices.contains(&index));
+                                    if ui.checkbox(&mut included, "")
+                                        .on_hover_text("Include this segment in the extraction")
+                                        .changed() {
+                                        toggled = Some(index);
+                                    }
+                                    ui.label(egui::RichText::new(index.to_string())
+                                        .color(egui::Color32::from_rgb(160, 160, 160)));
+                                    render_address_cell(ui, segment.source_start_addr, *show_decimal);
+                                    render_address_cell(ui, segment.source_end_addr, *show_decimal);
+                                    render_address_cell(ui, segment.target_start_addr, *show_decimal);
+                                    render_address_cell(ui, segment.target_end_addr, *show_decimal);
+                                    ui.label(egui::RichText::new(if segment.is_compressed { "Yes" } else { "No" })
+                                        .color(egui::Color32::from_rgb(160, 160, 160)));
+                                    ui.horizontal(|ui| {
+                                        if ui.small_button("Copy")
+                                            .on_hover_text("Copy this segment as a formatted line")
+                                            .clicked() {
+                                            ui.ctx().copy_text(format_segment_line(label, index, segment));
+                                        }
+                                        if ui.small_button("Hex")
+                                            .on_hover_text("Extract this segment and copy its bytes as a hex dump")
+                                            .clicked() {
+                                            message_queue.push(UIMessage::CopySegmentHex(label.clone(), index));
+                                        }
+                                    });
+                                    ui.end_row();
+                                }
+                            });
+                        
+                        if let Some(index) = toggled {
+                            let mut indices = selected.unwrap_or_else(|| (0..segments.len()).collect());
+                            if !indices.remove(&index) {
+                                indices.insert(index);
+                            }
+                            // Everything selected is written as the empty "all" selection
+                            *spec = if indices.len() == segments.len() && indices.iter().all(|&i| i < segments.len()) {
+                                String::new()
+                            } else {
+                                format_segment_indices(&indices)
+                            };
+                        }
+                        
+                        ui.add_space(10.0);
+                    }
+                });
+            });
+    }
+}
+
+pub fn render_xml_inspector(
+    ctx: &egui::Context,
+    show_xml_inspector: &mut bool,
+    xml_inspection: &Option<XmlInspection>,
+    inspected_segment: &mut Option<usize>,
+    xml_inspector_scroll: &mut bool
+) {
+    let Some(inspection) = xml_inspection else {
+        return;
+    };
+    if !*show_xml_inspector {
+        return;
+    }
+    
+    egui::Window::new(format!("Inspect XML - {}", inspection.label))
+        .open(show_xml_inspector)
+        .default_size([900.0, 500.0])
+        .show(ctx, |ui| {
+            ui.label(egui::RichText::new(inspection.xml_path.to_string_lossy())
+                .color(egui::Color32::from_rgb(160, 160, 160))
+                .size(12.0));
+            if let Some(ref error) = inspection.parse_error {
+                ui.label(egui::RichText::new(format!("Parse error: {}", error))
+                    .color(egui::Color32::from_rgb(200, 140, 140)));
+            }
+            ui.add_space(5.0);
+            
+            let highlighted_rows = inspected_segment.and_then(|index| inspection.segment_rows.get(index).copied());
+            
+            ui.columns(2, |columns| {
+                // Namespace-stripped XML as the parser sees it
+                egui::ScrollArea::both()
+                    .id_source("xml_inspector_text")
+                    .show(&mut columns[0], |ui| {
+                        for (row, line) in inspection.xml_text.lines().enumerate() {
+                            let mut text = egui::RichText::new(format!("{:>5}  {}", row + 1, line))
+                                .monospace()
+                                .color(egui::Color32::from_rgb(180, 180, 180));
+                            let is_highlighted = highlighted_rows.is_some_and(|(start, end)| row >= start && row <= end);
+                            if is_highlighted {
+                                text = text.background_color(egui::Color32::from_rgb(60, 80, 60));
+                            }
+                            let response = ui.label(text);
+                            if *xml_inspector_scroll && highlighted_rows.is_some_and(|(start, _)| row == start) {
+                                response.scroll_to_me(Some(egui::Align::TOP));
+                                *xml_inspector_scroll = false;
+                            }
+                        }
+                    });
+                
+                // What parse_xml extracted; click a row to highlight its XML
+                egui::ScrollArea::vertical()
+                    .id_source("xml_inspector_segments")
+                    .show(&mut columns[1], |ui| {
+                        egui::Grid::new("xml_inspector_grid")
+                            .striped(true)
+                            .show(ui, |ui| {
+                                for header in ["#", "Lines", "Source Start", "Source End", "Target Start", "Target End", "Compressed"] {
+                                    ui.label(egui::RichText::new(header)
+                                        .color(egui::Color32::from_rgb(180, 180, 180)));
+                                }
+                                ui.end_row();
+                                
+                                for (index, segment) in inspection.segments.iter().enumerate() {
+                                    if ui.selectable_label(*inspected_segment == Some(index), index.to_string()).clicked() {
+                                        *inspected_segment = Some(index);
+                                        *xml_inspector_scroll = true;
+                                    }
+                                    let lines = inspection.segment_rows.get(index)
+                                        .map(|(start, end)| format!("{}-{}", start + 1, end + 1))
+                                        .unwrap_or_else(|| "?".to_string());
+                                    ui.label(egui::RichText::new(lines)
+                                        .color(egui::Color32::from_rgb(160, 160, 160)));
+                                    render_address_cell(ui, segment.source_start_addr, false);
+                                    render_address_cell(ui, segment.source_end_addr, false);
+                                    render_address_cell(ui, segment.target_start_addr, false);
+                                    render_address_cell(ui, segment.target_end_addr, false);
+                                    ui.label(egui::RichText::new(if segment.is_compressed { "Yes" } else { "No" })
+                                        .color(egui::Color32::from_rgb(160, 160, 160)));
+                                    ui.end_row();
+                                }
+                            });
+                        
+                        if inspection.segments.is_empty() {
+                            ui.label(egui::RichText::new("No segments parsed")
+                                .color(egui::Color32::from_rgb(200, 140, 140)));
+                        }
+                    });
+            });
+        });
+}
+
+/// Dry run of the current inputs and options: the segments in output order, what the
+/// run would warn about, and the gaps, overlaps and image size
+pub fn render_run_preview(
+    ctx: &egui::Context,
+    show_run_preview: &mut bool,
+    run_preview: &Option<RunPreview>,
+    show_decimal: bool
+) {
+    let Some(preview) = run_preview else {
+        return;
+    };
+    if !*show_run_preview {
+        return;
+    }
+    
+    egui::Window::new("Run Preview")
+        .open(show_run_preview)
+        .default_size([900.0, 450.0])
+        .show(ctx, |ui| {
+            let files = preview.segments.iter()
+                .map(|segment| segment.label.as_str())
+                .collect::<HashSet<_>>()
+                .len();
+            let mut summary = format!("{} segments from {} files", preview.segments.len(), files);
+            if let (Some(first), Some(end)) = (preview.segments.first(), preview.segments.iter().map(|s| s.addr as u64 + s.len - 1).max()) {
+                summary.push_str(&format!(", 0x{:08X} to 0x{:08X}", first.addr, end));
+            }
+            if let Some(size) = preview.image_size {
+                summary.push_str(&format!(", image {} bytes ({:.2} MB)", size, size as f64 / (1024.0 * 1024.0)));
+            }
+            ui.label(egui::RichText::new(summary)
+                .color(egui::Color32::from_rgb(180, 180, 180)));
+            ui.label(egui::RichText::new("Sizes are the ones the XML declares; nothing was read from the bin files or written.")
+                .color(egui::Color32::from_rgb(160, 160, 160))
+                .size(11.0));
+            
+            for problem in &preview.problems {
+                ui.label(egui::RichText::new(problem)
+                    .color(if problem.starts_with("Error") {
+                        egui::Color32::from_rgb(200, 140, 140)
+                    } else {
+                        egui::Color32::from_rgb(220, 190, 110)
+                    })
+                    .size(12.0));
+            }
+            
+            for (title, ranges) in [("Gaps", &preview.gaps), ("Overlaps", &preview.overlaps)] {
+                if ranges.is_empty() {
+                    continue;
+                }
+                ui.collapsing(egui::RichText::new(format!("{} ({})", title, ranges.len()))
+                    .color(egui::Color32::from_rgb(180, 180, 180)), |ui| {
+                    for &(start, end) in ranges {
+                        ui.label(egui::RichText::new(format!("0x{:08X}-0x{:08X} ({} bytes)", start, end, (end - start) as u64 + 1))
+                            .monospace()
+                            .color(egui::Color32::from_rgb(220, 220, 180)));
+                    }
+                });
+            }
+            ui.add_space(5.0);
+            
+            egui::ScrollArea::both().show(ui, |ui| {
+                egui::Grid::new("run_preview_grid")
+                    .striped(true)
+                    .show(ui, |ui| {
+                        for header in ["Input", "#", "Source Start", "Source End", "Target Start", "Target End", "Compressed", "Output Address", "Bytes"] {
+                            ui.label(egui::RichText::new(header)
+                                .color(egui::Color32::from_rgb(180, 180, 180)));
+                        }
+                        ui.end_row();
+                        
+                        for entry in &preview.segments {
+                            ui.label(egui::RichText::new(&entry.label)
+                                .color(egui::Color32::from_rgb(180, 180, 180)));
+                            ui.label(egui::RichText::new(entry.index.to_string())
+                                .color(egui::Color32::from_rgb(160, 160, 160)));
+                            render_address_cell(ui, entry.segment.source_start_addr, show_decimal);
+                            render_address_cell(ui, entry.segment.source_end_addr, show_decimal);
+                            render_address_cell(ui, entry.segment.target_start_addr, show_decimal);
+                            render_address_cell(ui, entry.segment.target_end_addr, show_decimal);
+                            ui.label(egui::RichText::new(if entry.segment.is_compressed { "Yes" } else { "No" })
+                                .color(egui::Color32::from_rgb(160, 160, 160)));
+                            render_address_cell(ui, entry.addr, show_decimal);
+                            ui.label(egui::RichText::new(entry.len.to_string())
+                                .color(egui::Color32::from_rgb(160, 160, 160)));
+                            ui.end_row();
+                        }
+                    });
+            });
+        });
+}
+
+pub fn render_validation_report(
+    ctx: &egui::Context,
+    show_validation_report: &mut bool,
+    validation_report: &mut [FileValidation],
+    validation_sort: &mut (ValidationSort, bool)
+) {
+    if !*show_validation_report {
+        return;
+    }
+    
+    egui::Window::new("Validation Report")
+        .open(show_validation_report)
+        .default_size([800.0, 450.0])
+        .show(ctx, |ui| {
+            let ok_count = validation_report.iter()
+                .filter(|validation| validation.status == ValidationStatus::Ok)
+                .count();
+            ui.label(egui::RichText::new(format!("{} files, {} OK, {} with problems. Click a column header to sort.",
+                validation_report.len(), ok_count, validation_report.len() - ok_count))
+                .color(egui::Color32::from_rgb(160, 160, 160))
+                .size(12.0));
+            ui.add_space(5.0);
+            
+            egui::ScrollArea::both().show(ui, |ui| {
+                egui::Grid::new("validation_report_grid")
+                    .striped(true)
+                    .show(ui, |ui| {
+                        let columns = [
+                            ("File", Some(ValidationSort::Name)),
+                            ("Type", Some(ValidationSort::Type)),
+                            ("Status", Some(ValidationSort::Status)),
+                            ("Segments", Some(ValidationSort::Segments)),
+                            ("Details", None),
+                        ];
+                        for (header, sort) in columns {
+                            let Some(sort) = sort else {
+                                ui.label(egui::RichText::new(header)
+                                    .color(egui::Color32::from_rgb(180, 180, 180)));
+                                continue;
+                            };
+                            let (current, ascending) = *validation_sort;
+                            let arrow = match (current == sort, ascending) {
+                                (true, true) => " ▲",
+                                (true, false) => " ▼",
+                                (false, _) => "",
+                            };
+                            if ui.selectable_label(current == sort, egui::RichText::new(format!("{}{}", header, arrow))
+                                .color(egui::Color32::from_rgb(180, 180, 180)))
+                                .clicked() {
+                                *validation_sort = (sort, current != sort || !ascending);
+                                sort_validation_report(validation_report, *validation_sort);
+                            }
+                        }
+                        ui.end_row();
+                        
+                        for validation in validation_report.iter() {
+                            ui.label(egui::RichText::new(&validation.display_name)
+                                .color(egui::Color32::from_rgb(220, 220, 180)))
+                                .on_hover_text(validation.path.to_string_lossy());
+                            ui.label(egui::RichText::new(match validation.file_type {
+                                FileType::BTLD => "BTLD",
+                                FileType::SWFL => "SWFL",
+                            }).color(egui::Color32::from_rgb(160, 160, 160)));
+                            let status_color = match validation.status {
+                                ValidationStatus::Ok => egui::Color32::from_rgb(140, 200, 140),
+                                ValidationStatus::Suspicious => egui::Color32::from_rgb(200, 180, 120),
+                                ValidationStatus::MissingXml | ValidationStatus::InvalidXml => egui::Color32::from_rgb(200, 140, 140),
+                            };
+                            ui.label(egui::RichText::new(validation.status.to_string())
+                                .color(status_color));
+                            ui.label(egui::RichText::new(validation.segment_count.to_string())
+                                .color(egui::Color32::from_rgb(160, 160, 160)));
+                            ui.label(egui::RichText::new(&validation.detail)
+                                .color(egui::Color32::from_rgb(160, 160, 160))
+                                .size(12.0));
+                            ui.end_row();
+                        }
+                    });
+            });
+        });
+}
+
+fn sort_validation_report(validation_report: &mut [FileValidation], (sort, ascending): (ValidationSort, bool)) {
+    validation_report.sort_by(|a, b| {
+        let ordering = match sort {
+            ValidationSort::Name => a.display_name.to_lowercase().cmp(&b.display_name.to_lowercase()),
+            ValidationSort::Type => (a.file_type == FileType::SWFL).cmp(&(b.file_type == FileType::SWFL)),
+            ValidationSort::Status => a.status.cmp(&b.status),
+            ValidationSort::Segments => a.segment_count.cmp(&b.segment_count),
+        };
+        if ascending { ordering } else { ordering.reverse() }
+    });
+}
+
+pub fn render_ucl_setup_window(
+    ctx: &egui::Context,
+    show_ucl_setup: &mut bool,
+    ucl_candidates: &[PathBuf],
+    ucl_search_done: bool,
+    status_message: &str,
+    message_queue: &mut Vec<UIMessage>
+) {
+    if *show_ucl_setup {
+        egui::Window::new("UCL Library Setup")
+            .open(show_ucl_setup)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                ui.heading(egui::RichText::new("A UCL library is required")
+                    .size(18.0)
+                    .color(egui::Color32::from_rgb(120, 160, 200)));
+                ui.label(egui::RichText::new("Compressed segments in BMW flash files are unpacked with libucl (NRV2 decompression), which is not included with this program. Point it at a libucl build, e.g. libucl-1.dll on Windows.")
+                    .color(egui::Color32::from_rgb(180, 180, 180)));
+                ui.label(egui::RichText::new(status_message)
+                    .color(egui::Color32::from_rgb(200, 140, 140))
+                    .size(12.0));
+                
+                ui.add_space(10.0);
+                ui.horizontal(|ui| {
+                    if ui.button(egui::RichText::new("Browse for Library")
+                        .color(egui::Color32::from_rgb(220, 220, 220)))
+                        .clicked() {
+                        message_queue.push(UIMessage::BrowseUCLLibrary);
+                    }
+                    if ui.button(egui::RichText::new("Search Common Locations")
+                        .color(egui::Color32::from_rgb(220, 220, 220)))
+                        .clicked() {
+                        message_queue.push(UIMessage::SearchUCLLibrary);
+                    }
+                });
+                
+                if ucl_search_done {
+                    ui.add_space(5.0);
+                    if ucl_candidates.is_empty() {
+                        ui.label(egui::RichText::new("No UCL library found next to the program, in the working directory or in the system library folders.")
+                            .color(egui::Color32::from_rgb(160, 160, 160))
+                            .size(12.0));
+                    }
+                    for candidate in ucl_candidates {
+                        ui.horizontal(|ui| {
+                            ui.label(egui::RichText::new(candidate.to_string_lossy())
+                                .color(egui::Color32::from_rgb(140, 200, 140)));
+                            if ui.small_button("Use").clicked() {
+                                message_queue.push(UIMessage::UseUCLLibrary(candidate.clone()));
+                            }
+                        });
+                    }
+                }
+                
+                ui.add_space(10.0);
+                ui.separator();
+                if ui.button(egui::RichText::new("Skip, don't show again")
+                    .color(egui::Color32::from_rgb(180, 180, 180)))
+                    .on_hover_text("The library can still be configured later in Settings")
+                    .clicked() {
+                    message_queue.push(UIMessage::FinishUCLSetup);
+                }
+            });
+    }
+}
+
 pub fn render_settings_window(
     ctx: &egui::Context,
     show_settings: &mut bool,
-    ucl_library_path: &mut String,
+    config: &mut AppConfig,
+    ucl_path_override: &Option<(String, UclPathSource)>,
+    ucl_path_check: Option<&UclPathCheck>,
+    diagnostics: &str,
     message_queue: &mut Vec<UIMessage>
 ) {
     if *show_settings {
         egui::Window::new("Settings")
             .open(show_settings)
+            .resizable(true)
+            .default_width(520.0)
             .show(ctx, |ui| {
                 ui.heading(egui::RichText::new("UCL Library Configuration")
                     .size(18.0)
@@ -510,7 +1785,44 @@ pub fn render_settings_window(
                 
                 ui.label(egui::RichText::new("UCL Library Path:")
                     .color(egui::Color32::from_rgb(180, 180, 180)));
-                ui.text_edit_singleline(ucl_library_path);
+                let path_response = ui.add(egui::TextEdit::singleline(&mut config.ucl_library_path)
+                    .desired_width(f32::INFINITY))
+                    .on_hover_text("Press Enter to reload the library from this path");
+                if path_response.changed() {
+                    message_queue.push(UIMessage::ConfigChanged);
+                }
+                if path_response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                    message_queue.push(UIMessage::ReloadUCLLibrary);
+                }
+                
+                match ucl_path_check.filter(|check| check.path == config.ucl_library_path && check.pinned_symbol == config.ucl_decompress_symbol) {
+                    Some(UclPathCheck { result: Ok(symbol), .. }) => {
+                        ui.label(egui::RichText::new(format!("✔ Loads, provides {}", symbol))
+                            .color(egui::Color32::from_rgb(140, 200, 140))
+                            .size(12.0));
+                    }
+                    Some(UclPathCheck { result: Err(e), .. }) => {
+                        ui.label(egui::RichText::new(format!("✖ {}", e))
+                            .color(egui::Color32::from_rgb(200, 140, 140))
+                            .size(12.0));
+                    }
+                    None => {
+                        ui.label(egui::RichText::new("Checking...")
+                            .color(egui::Color32::from_rgb(160, 160, 160))
+                            .size(12.0));
+                    }
+                }
+                
+                if let Some((override_path, source)) = ucl_path_override {
+                    ui.horizontal(|ui| {
+                        ui.label(egui::RichText::new(format!("Overridden for this session by {}: {}", source, override_path))
+                            .color(egui::Color32::from_rgb(220, 190, 110))
+                            .size(12.0));
+                        if ui.small_button("Use configured path").clicked() {
+                            message_queue.push(UIMessage::ClearUCLOverride);
+                        }
+                    });
+                }
                 
                 ui.horizontal(|ui| {
                     if ui.button(egui::RichText::new("Browse")
@@ -525,10 +1837,226 @@ pub fn render_settings_window(
                     }
                 });
                 
+                ui.horizontal(|ui| {
+                    ui.label(egui::RichText::new("Decompression function:")
+                        .color(egui::Color32::from_rgb(180, 180, 180)));
+                    if ui.add(egui::TextEdit::singleline(&mut config.ucl_decompress_symbol)
+                        .hint_text("auto"))
+                        .on_hover_text("Exact export to use instead of probing the usual ucl_nrv2*_decompress names, e.g. ucl_nrv2e_decompress_le32. Validation and the truncation check only apply to _8 variants. Takes effect on reload.")
+                        .changed() {
+                        message_queue.push(UIMessage::ConfigChanged);
+                    }
+                });
+                
+                if ui.checkbox(&mut config.validate_ucl_library, egui::RichText::new("Validate library on load")
+                    .color(egui::Color32::from_rgb(180, 180, 180)))
+                    .on_hover_text("Decompress a small known stream when loading the library to catch incompatible builds early. Disable for libraries with unusual exports.")
+                    .changed() {
+                    message_queue.push(UIMessage::ConfigChanged);
+                }
+                
+                if ui.checkbox(&mut config.safe_mode, egui::RichText::new("Safe mode")
+                    .color(egui::Color32::from_rgb(180, 180, 180)))
+                    .on_hover_text("For untrusted or possibly corrupt files: caps segment sizes, times out stuck decompression and drops segments instead of falling back to raw data.")
+                    .changed() {
+                    message_queue.push(UIMessage::ConfigChanged);
+                }
+                
+                if ui.checkbox(&mut config.format_from_extension, egui::RichText::new("Format from extension")
+                    .color(egui::Color32::from_rgb(180, 180, 180)))
+                    .on_hover_text("When you browse for a combined output, pick its format from the extension: .hex for Intel HEX, .srec/.s19/.s28/.s37 for S-records, anything else raw binary. The Format choice can still be changed afterwards.")
+                    .changed() {
+                    message_queue.push(UIMessage::ConfigChanged);
+                }
+                
+                if ui.checkbox(&mut config.keep_last_output, egui::RichText::new("Keep last output")
+                    .color(egui::Color32::from_rgb(180, 180, 180)))
+                    .on_hover_text("Hold the last combined image in memory so re-running the same inputs lists the changed byte ranges. Otherwise only its checksum is kept and a re-run just reports whether it changed. Applies from the next run.")
+                    .changed() {
+                    message_queue.push(UIMessage::ConfigChanged);
+                }
+                
+                ui.horizontal(|ui| {
+                    ui.label(egui::RichText::new("PSDZ scan limit:")
+                        .color(egui::Color32::from_rgb(180, 180, 180)));
+                    let timeout_changed = ui.add(egui::DragValue::new(&mut config.scan_timeout_secs)
+                        .clamp_range(1..=3600)
+                        .suffix(" s"))
+                        .on_hover_text("Stop scanning a folder after this long and show what was found so far")
+                        .changed();
+                    let max_files_changed = ui.add(egui::DragValue::new(&mut config.scan_max_files)
+                        .clamp_range(1..=1_000_000)
+                        .suffix(" files"))
+                        .on_hover_text("Stop scanning a folder once this many files were found")
+                        .changed();
+                    let max_depth_changed = ui.add(egui::DragValue::new(&mut config.scan_max_depth)
+                        .clamp_range(1..=10)
+                        .suffix(" levels"))
+                        .on_hover_text("How deep below swe to look for btld/swfl folders, e.g. 2 for one subfolder per I-step. Applies to the next scan.")
+                        .changed();
+                    if timeout_changed || max_files_changed || max_depth_changed {
+                        message_queue.push(UIMessage::ConfigChanged);
+                    }
+                });
+                
+                ui.horizontal(|ui| {
+                    ui.label(egui::RichText::new("Stream segments above:")
+                        .color(egui::Color32::from_rgb(180, 180, 180)));
+                    if ui.add(egui::DragValue::new(&mut config.streaming_threshold_mb)
+                        .clamp_range(0..=1024)
+                        .suffix(" MB"))
+                        .on_hover_text("Uncompressed segments larger than this are copied straight from the input file while writing instead of being held in memory. 0 turns streaming off.")
+                        .changed() {
+                        message_queue.push(UIMessage::ConfigChanged);
+                    }
+                });
+                
+                ui.horizontal(|ui| {
+                    ui.label(egui::RichText::new("Output name template:")
+                        .color(egui::Color32::from_rgb(180, 180, 180)));
+                    if ui.text_edit_singleline(&mut config.output_name_template)
+                        .on_hover_text("Name of auto-generated outputs for SWFL1 inputs. Placeholders: {ecu}, {version}, {date}, {name}")
+                        .changed() {
+                        message_queue.push(UIMessage::OutputNameTemplateChanged);
+                    }
+                });
+                if let Err(e) = validate_output_template(&config.output_name_template) {
+                    ui.label(egui::RichText::new(format!("{}; using {}", e, DEFAULT_OUTPUT_NAME_TEMPLATE))
+                        .color(egui::Color32::from_rgb(200, 140, 140))
+                        .size(12.0));
+                }
+                
+                ui.horizontal(|ui| {
+                    ui.label(egui::RichText::new("Flashing script template:")
+                        .color(egui::Color32::from_rgb(180, 180, 180)));
+                    if ui.small_button("Default").clicked() {
+                        config.flash_script_template = DEFAULT_FLASH_SCRIPT_TEMPLATE.to_string();
+                        message_queue.push(UIMessage::ConfigChanged);
+                    }
+                });
+                if ui.add(egui::TextEdit::multiline(&mut config.flash_script_template)
+                    .code_editor()
+                    .desired_rows(3)
+                    .desired_width(f32::INFINITY))
+                    .on_hover_text("Lines using {file}, {path}, {addr}, {end}, {addr_dec}, {size} or {skip} are repeated for every written file; other lines are written once and may use {output}, {count} and {date}. Addresses are 8 hex digits without a prefix.")
+                    .changed() {
+                    message_queue.push(UIMessage::ConfigChanged);
+                }
+                if let Err(e) = validate_flash_script_template(&config.flash_script_template) {
+                    ui.label(egui::RichText::new(format!("{}; using the default template", e))
+                        .color(egui::Color32::from_rgb(200, 140, 140))
+                        .size(12.0));
+                }
+                
+                ui.horizontal(|ui| {
+                    ui.label(egui::RichText::new("Decompression retries:")
+                        .color(egui::Color32::from_rgb(180, 180, 180)));
+                    if ui.add(egui::DragValue::new(&mut config.ucl_transient_retries)
+                        .clamp_range(0..=10))
+                        .on_hover_text("How often a decompression that failed with a generic or out-of-memory UCL error is repeated. Corrupt data fails immediately.")
+                        .changed() {
+                        message_queue.push(UIMessage::ConfigChanged);
+                    }
+                });
+
                 ui.add_space(10.0);
-                ui.label(egui::RichText::new("Note: Changes will be saved when you close the application.")
+                if ui.checkbox(&mut config.autosave_config, egui::RichText::new("Save settings automatically")
+                    .color(egui::Color32::from_rgb(180, 180, 180)))
+                    .changed() {
+                    message_queue.push(UIMessage::ConfigChanged);
+                }
+                ui.label(egui::RichText::new(if config.autosave_config {
+                        "Note: Changes are saved shortly after they are made and when you close the application."
+                    } else {
+                        "Note: Changes will be saved when you close the application."
+                    })
                     .color(egui::Color32::from_rgb(160, 160, 160))
                     .size(12.0));
+                
+                ui.add_space(10.0);
+                let config_path = AppConfig::config_path();
+                let copied_id = ui.id().with("config_path_copied");
+                ui.horizontal(|ui| {
+                    if ui.button(egui::RichText::new("Open config folder")
+                        .color(egui::Color32::from_rgb(220, 220, 220)))
+                        .on_hover_text(config_path.to_string_lossy())
+                        .clicked() {
+                        // Select the file if it was saved already, otherwise just open its folder
+                        let target = if config_path.exists() {
+                            config_path.clone()
+                        } else {
+                            config_path.parent().map(|dir| dir.to_path_buf()).unwrap_or_else(|| config_path.clone())
+                        };
+                        message_queue.push(UIMessage::RevealPath(target));
+                    }
+                    if ui.button(egui::RichText::new("Show config path")
+                        .color(egui::Color32::from_rgb(220, 220, 220)))
+                        .on_hover_text("Copies the path to the clipboard")
+                        .clicked() {
+                        ui.ctx().copy_text(config_path.to_string_lossy().to_string());
+                        ui.data_mut(|d| d.insert_temp(copied_id, true));
+                    }
+                });
+                if ui.data(|d| d.get_temp::<bool>(copied_id)).unwrap_or(false) {
+                    ui.label(egui::RichText::new(format!("Copied: {}", config_path.display()))
+                        .color(egui::Color32::from_rgb(160, 160, 160))
+                        .size(12.0));
+                }
+                
+                ui.add_space(10.0);
+                ui.collapsing("About", |ui| {
+                    ui.label(egui::RichText::new(diagnostics)
+                        .monospace()
+                        .color(egui::Color32::from_rgb(180, 180, 180)));
+                    let diagnostics_copied_id = ui.id().with("diagnostics_copied");
+                    ui.horizontal(|ui| {
+                        if ui.button(egui::RichText::new("Copy diagnostics")
+                            .color(egui::Color32::from_rgb(220, 220, 220)))
+                            .on_hover_text("Copies these details for pasting into a bug report")
+                            .clicked() {
+                            ui.ctx().copy_text(diagnostics.to_string());
+                            ui.data_mut(|d| d.insert_temp(diagnostics_copied_id, true));
+                        }
+                        if ui.data(|d| d.get_temp::<bool>(diagnostics_copied_id)).unwrap_or(false) {
+                            ui.label(egui::RichText::new("Copied")
+                                .color(egui::Color32::from_rgb(160, 160, 160))
+                                .size(12.0));
+                        }
+                    });
+                });
+                
+                ui.add_space(10.0);
+                ui.separator();
+                // Confirmation state only lives as long as the window, so keep it in egui memory
+                let confirm_id = ui.id().with("confirm_reset_config");
+                let mut confirm_reset_config = ui.data(|d| d.get_temp::<bool>(confirm_id)).unwrap_or(false);
+                if confirm_reset_config {
+                    ui.label(egui::RichText::new("Reset all settings to their defaults? The UCL library will be reloaded.")
+                        .color(egui::Color32::from_rgb(220, 190, 110)));
+                    ui.horizontal(|ui| {
+                        if ui.button(egui::RichText::new("Reset, keep recent folders")
+                            .color(egui::Color32::from_rgb(220, 220, 220)))
+                            .on_hover_text("Keeps the last used input/output folders and recent outputs")
+                            .clicked() {
+                            message_queue.push(UIMessage::ResetConfig(true));
+                            confirm_reset_config = false;
+                        }
+                        if ui.button(egui::RichText::new("Reset everything")
+                            .color(egui::Color32::from_rgb(200, 140, 140)))
+                            .clicked() {
+                            message_queue.push(UIMessage::ResetConfig(false));
+                            confirm_reset_config = false;
+                        }
+                        if ui.button("Cancel").clicked() {
+                            confirm_reset_config = false;
+                        }
+                    });
+                } else if ui.button(egui::RichText::new("Reset to defaults")
+                    .color(egui::Color32::from_rgb(220, 220, 220)))
+                    .clicked() {
+                    confirm_reset_config = true;
+                }
+                ui.data_mut(|d| d.insert_temp(confirm_id, confirm_reset_config));
             });
     }
 }
\ No newline at end of file
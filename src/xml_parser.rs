@@ -1,21 +1,134 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::fs;
+use std::path::Path;
 use anyhow::{Result, Context};
+use serde::{Deserialize, Serialize};
+use xml::common::Position;
 use xml::reader::{EventReader, XmlEvent};
 use crate::types::FlashSegment;
 
-pub fn parse_xml(xml_path: &std::path::PathBuf) -> Result<Vec<FlashSegment>> {
+/// Element and attribute names the parser looks for. Each field accepts several names
+/// so one table can cover schema variants across PSDZ versions; the first name is the
+/// one used in messages.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct XmlSchema {
+    pub segment_element: Vec<String>,
+    pub source_start: Vec<String>,
+    pub source_end: Vec<String>,
+    pub target_start: Vec<String>,
+    pub target_end: Vec<String>,
+    /// Attribute of the segment element that marks compression
+    pub compression_attribute: Vec<String>,
+    pub compressed_value: String,
+}
+
+impl Default for XmlSchema {
+    fn default() -> Self {
+        Self {
+            segment_element: vec!["FLASH-SEGMENT".to_string()],
+            source_start: vec!["SOURCE-START-ADDRESS".to_string()],
+            source_end: vec!["SOURCE-END-ADDRESS".to_string()],
+            target_start: vec!["TARGET-START-ADDRESS".to_string()],
+            target_end: vec!["TARGET-END-ADDRESS".to_string()],
+            compression_attribute: vec!["COMPRESSION-STATUS".to_string()],
+            compressed_value: "COMPRESSED".to_string(),
+        }
+    }
+}
+
+impl XmlSchema {
+    /// Address fields in `FlashSegment` order
+    fn address_fields(&self) -> [&[String]; 4] {
+        [&self.source_start, &self.source_end, &self.target_start, &self.target_end]
+    }
+    
+    /// Check that every field has a name and no element name is claimed by two fields
+    pub fn validate(&self) -> Result<()> {
+        let fields: [(&str, &[String]); 6] = [
+            ("segment_element", &self.segment_element),
+            ("source_start", &self.source_start),
+            ("source_end", &self.source_end),
+            ("target_start", &self.target_start),
+            ("target_end", &self.target_end),
+            ("compression_attribute", &self.compression_attribute),
+        ];
+        for (field, names) in fields {
+            if names.is_empty() || names.iter().any(|name| name.trim().is_empty()) {
+                return Err(anyhow::anyhow!("XML schema field {} needs at least one non-empty name", field));
+            }
+        }
+        
+        // Attribute names live apart from element names, so only elements must be unique
+        let mut seen = HashMap::new();
+        for (field, names) in &fields[..5] {
+            for name in names.iter() {
+                if let Some(other) = seen.insert(name.as_str(), *field) {
+                    return Err(anyhow::anyhow!("XML schema maps {} to both {} and {}", name, other, field));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn matches_name(names: &[String], name: &str) -> bool {
+    names.iter().any(|candidate| candidate == name)
+}
+
+/// Read the segments of the XML file at `xml_path` with the element names of `schema`,
+/// which should have passed `XmlSchema::validate`
+pub fn parse_xml(xml_path: &Path, schema: &XmlSchema) -> Result<Vec<FlashSegment>> {
+    let xml_content = read_xml_stripped(xml_path)?;
+    parse_xml_str(&xml_content, schema)
+}
+
+/// Read an XML file with its default namespace removed, which is the text the parser sees
+pub fn read_xml_stripped(xml_path: &Path) -> Result<String> {
     let xml_content = fs::read_to_string(xml_path)
-        .context("Failed to read XML file")?;
+        .context(format!("Failed to read XML file {}", xml_path.display()))?;
     
-    let xml_content = regex::Regex::new(r#" xmlns="[^"]+""#)
+    Ok(regex::Regex::new(r#" xmlns="[^"]+""#)
         .unwrap()
-        .replace(&xml_content, "");
+        .replace(&xml_content, "")
+        .into_owned())
+}
+
+/// Line range (0-based, inclusive) of each FLASH-SEGMENT element, in the same order
+/// `parse_xml_str` returns segments. Stops at the first malformed element.
+pub fn flash_segment_rows(xml_content: &str, schema: &XmlSchema) -> Vec<(usize, usize)> {
+    let mut parser = EventReader::from_str(xml_content);
+    let mut rows = Vec::new();
+    let mut start_row = None;
+    
+    loop {
+        match parser.next() {
+            Ok(XmlEvent::StartElement { name, .. }) if matches_name(&schema.segment_element, &name.local_name) => {
+                start_row = Some(parser.position().row as usize);
+            }
+            Ok(XmlEvent::EndElement { name }) if matches_name(&schema.segment_element, &name.local_name) => {
+                if let Some(start) = start_row.take() {
+                    rows.push((start, parser.position().row as usize));
+                }
+            }
+            Ok(XmlEvent::EndDocument) | Err(_) => break,
+            Ok(_) => {}
+        }
+    }
     
-    let parser = EventReader::from_str(&xml_content);
+    rows
+}
+
+pub fn parse_xml_str(xml_content: &str, schema: &XmlSchema) -> Result<Vec<FlashSegment>> {
+    let address_fields = schema.address_fields();
+    // Every element name in the document, to describe an unrecognized schema
+    let mut element_names = BTreeSet::new();
+    let parser = EventReader::from_str(xml_content);
     let mut segments = Vec::new();
-    let mut current_element = String::new();
-    let mut in_flash_segment = false;
+    // Open elements, innermost last. Address fields are only read as direct children
+    // of a segment element, so CHECKSUM/SIGNATURE blocks with the same child names
+    // (inside or next to a segment) don't leak into it.
+    let mut element_stack: Vec<String> = Vec::new();
     let mut current_segment = FlashSegment {
         source_start_addr: 0,
         source_end_addr: 0,
@@ -23,40 +136,54 @@ pub fn parse_xml(xml_path: &std::path::PathBuf) -> Result<Vec<FlashSegment>> {
         target_end_addr: 0,
         is_compressed: false,
     };
+    let mut seen_fields = [false; 4];
     let mut element_attrs = HashMap::new();
     
     for event in parser {
         match event? {
             XmlEvent::StartElement { name, attributes, .. } => {
-                current_element = name.local_name.clone();
+                let element = name.local_name;
+                element_names.insert(element.clone());
                 element_attrs.clear();
                 for attr in attributes {
                     element_attrs.insert(attr.name.local_name.clone(), attr.value);
                 }
                 
-                if current_element == "FLASH-SEGMENT" {
-                    in_flash_segment = true;
-                    current_segment.is_compressed = element_attrs.get("COMPRESSION-STATUS")
-                        .map(|s| s == "COMPRESSED")
+                if matches_name(&schema.segment_element, &element) {
+                    if element_stack.iter().any(|open| matches_name(&schema.segment_element, open)) {
+                        return Err(anyhow::anyhow!("{} #{} is nested inside another {}", element, segments.len(), element));
+                    }
+                    current_segment.is_compressed = schema.compression_attribute.iter()
+                        .find_map(|attr| element_attrs.get(attr))
+                        .map(|s| *s == schema.compressed_value)
                         .unwrap_or(false);
                 }
+                element_stack.push(element);
             }
             XmlEvent::Characters(text) => {
-                if in_flash_segment {
-                    match current_element.as_str() {
-                        "SOURCE-START-ADDRESS" => {
+                let segment_child = match element_stack.as_slice() {
+                    [.., parent, current] if matches_name(&schema.segment_element, parent) => Some(current),
+                    _ => None,
+                };
+                if let Some(current_element) = segment_child {
+                    let field = address_fields.iter().position(|names| matches_name(names, current_element));
+                    if let Some(field) = field {
+                        seen_fields[field] = true;
+                    }
+                    match field {
+                        Some(0) => {
                             current_segment.source_start_addr = u32::from_str_radix(&text, 16)
                                 .context("Invalid source start address")?;
                         }
-                        "SOURCE-END-ADDRESS" => {
+                        Some(1) => {
                             current_segment.source_end_addr = u32::from_str_radix(&text, 16)
                                 .context("Invalid source end address")?;
                         }
-                        "TARGET-START-ADDRESS" => {
+                        Some(2) => {
                             current_segment.target_start_addr = u32::from_str_radix(&text, 16)
                                 .context("Invalid target start address")?;
                         }
-                        "TARGET-END-ADDRESS" => {
+                        Some(3) => {
                             current_segment.target_end_addr = u32::from_str_radix(&text, 16)
                                 .context("Invalid target end address")?;
                         }
@@ -65,7 +192,29 @@ pub fn parse_xml(xml_path: &std::path::PathBuf) -> Result<Vec<FlashSegment>> {
                 }
             }
             XmlEvent::EndElement { name } => {
-                if name.local_name == "FLASH-SEGMENT" && in_flash_segment {
+                element_stack.pop();
+                if matches_name(&schema.segment_element, &name.local_name) {
+                    // Fields default to zero, so a missing one would silently produce a bogus range
+                    let missing: Vec<&str> = address_fields.iter()
+                        .zip(seen_fields.iter())
+                        .filter(|(_, seen)| !**seen)
+                        .map(|(names, _)| names[0].as_str())
+                        .collect();
+                    if !missing.is_empty() {
+                        return Err(anyhow::anyhow!("{} #{} is missing {}", name.local_name, segments.len(), missing.join(", ")));
+                    }
+                    seen_fields = [false; 4];
+                    
+                    // A range ending before it starts would underflow when its length is computed
+                    let ranges = [
+                        ("source", current_segment.source_start_addr, current_segment.source_end_addr),
+                        ("target", current_segment.target_start_addr, current_segment.target_end_addr),
+                    ];
+                    if let Some((kind, start, end)) = ranges.iter().find(|(_, start, end)| end < start) {
+                        return Err(anyhow::anyhow!("{} #{} has a {} end address 0x{:08X} before its start address 0x{:08X}",
+                            name.local_name, segments.len(), kind, end, start));
+                    }
+                    
                     segments.push(current_segment);
                     current_segment = FlashSegment {
                         source_start_addr: 0,
@@ -74,12 +223,17 @@ pub fn parse_xml(xml_path: &std::path::PathBuf) -> Result<Vec<FlashSegment>> {
                         target_end_addr: 0,
                         is_compressed: false,
                     };
-                    in_flash_segment = false;
                 }
             }
             _ => {}
         }
     }
     
+    if segments.is_empty() {
+        return Err(anyhow::anyhow!("Unknown XML schema: no {} elements found. Elements in the file: {}. Adjust xml_schema in config.json to match.",
+            schema.segment_element.join("/"),
+            element_names.into_iter().collect::<Vec<_>>().join(", ")));
+    }
+    
     Ok(segments)
 } 
\ No newline at end of file
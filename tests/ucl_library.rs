@@ -0,0 +1,68 @@
+//! Round trips through a real libucl. There is no library in the repository for every
+//! platform, so these are ignored by default; run them with `BMW_UCL_PATH` pointing at
+//! one:
+//!
+//! ```bash
+//! BMW_UCL_PATH=/path/to/libucl.so cargo test --test ucl_library -- --ignored
+//! ```
+
+use bmw_virtual_reader::ucl_bindings::{UclLibrary, DEFAULT_TRANSIENT_RETRIES};
+
+fn library() -> UclLibrary {
+    let path = std::env::var("BMW_UCL_PATH").expect("BMW_UCL_PATH must point at the UCL library");
+    UclLibrary::new(&path, true, None, DEFAULT_TRANSIENT_RETRIES)
+        .unwrap_or_else(|e| panic!("Failed to load {}: {}", path, e))
+}
+
+/// Pseudo-random bytes from a fixed seed, with every other block repeating an earlier
+/// one so the compressor emits matches as well as literals
+fn test_data(len: usize) -> Vec<u8> {
+    let mut state: u32 = 0x2545_F491;
+    let mut data: Vec<u8> = (0..len).map(|_| {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state as u8
+    }).collect();
+    for block in (512..len.saturating_sub(256)).step_by(512) {
+        let source = (state as usize % block) & !0xFF;
+        data.copy_within(source..source + 256, block);
+        state = state.rotate_left(7);
+    }
+    data
+}
+
+#[test]
+#[ignore = "needs BMW_UCL_PATH"]
+fn compress_and_decompress_round_trip() {
+    let library = library();
+    assert!(library.compress_symbol().is_some(), "{} has no matching compressor", library.decompress_symbol());
+    
+    for len in [1, 255, 4096, 100_000] {
+        let data = test_data(len);
+        for level in [1, 10] {
+            let compressed = library.compress(&data, level).unwrap();
+            let (decompressed, consumed) = library.decompress(&compressed).unwrap();
+            assert_eq!(decompressed, data, "{} bytes at level {}", len, level);
+            assert_eq!(consumed, compressed.len());
+            
+            let (decompressed, _) = library.decompress_into(&compressed, data.len()).unwrap();
+            assert_eq!(decompressed, data, "{} bytes at level {} into an exact buffer", len, level);
+        }
+    }
+}
+
+#[test]
+#[ignore = "needs BMW_UCL_PATH"]
+fn library_is_usable_from_another_thread() {
+    let library = library();
+    
+    let data = test_data(10_000);
+    let stream = library.encode_stored(&data);
+    let worker = std::thread::spawn(move || {
+        let (decompressed, consumed) = library.decompress(&stream).unwrap();
+        assert_eq!(consumed, stream.len());
+        decompressed
+    });
+    assert_eq!(worker.join().unwrap(), data);
+}
@@ -0,0 +1,49 @@
+//! Segment maps in tests/fixtures that the parser must reject or read selectively
+
+use std::path::{Path, PathBuf};
+use bmw_virtual_reader::file_ops::validate_file;
+use bmw_virtual_reader::types::{AvailableFile, FileType, ValidationStatus};
+use bmw_virtual_reader::xml_parser::{parse_xml, XmlSchema};
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("fixtures").join(name)
+}
+
+fn available_file(name: &str) -> AvailableFile {
+    let path = fixture(name);
+    AvailableFile {
+        size: std::fs::metadata(&path).unwrap().len(),
+        source_folder: fixture(""),
+        file_type: FileType::SWFL,
+        display_name: name.to_string(),
+        relative_dir: "swfl".to_string(),
+        path,
+    }
+}
+
+#[test]
+fn segment_missing_an_address_is_rejected() {
+    let error = parse_xml(&fixture("swfl_incomplete_segment.xml"), &XmlSchema::default()).unwrap_err();
+    assert_eq!(error.to_string(), "FLASH-SEGMENT #1 is missing TARGET-END-ADDRESS");
+}
+
+#[test]
+fn segment_missing_an_address_fails_validation() {
+    let validation = validate_file(&available_file("swfl_incomplete_segment.bin"), &XmlSchema::default());
+    assert_eq!(validation.status, ValidationStatus::InvalidXml);
+    assert_eq!(validation.segment_count, 0);
+    assert!(validation.detail.contains("is missing TARGET-END-ADDRESS"), "{}", validation.detail);
+}
+
+#[test]
+fn checksum_and_signature_blocks_are_not_segments() {
+    let segments = parse_xml(&fixture("swfl_checksum_blocks.xml"), &XmlSchema::default()).unwrap();
+    let fields: Vec<(u32, u32, u32, u32, bool)> = segments.iter()
+        .map(|segment| (segment.source_start_addr, segment.source_end_addr,
+            segment.target_start_addr, segment.target_end_addr, segment.is_compressed))
+        .collect();
+    assert_eq!(fields, vec![
+        (0x0000_0000, 0x0000_001F, 0x0001_0000, 0x0001_001F, false),
+        (0x0000_0020, 0x0000_003F, 0x0002_0000, 0x0002_00FF, true),
+    ]);
+}